@@ -158,4 +158,28 @@ pub mod anime {
         let anime = get_resources::<models::Anime>(client, uri).await?;
         Ok(anime)
     }
+
+    /// Searches the Kitsu API for anime resources matching a text query.
+    ///
+    /// # Arguments
+    ///
+    /// * `client` - A reference to a `reqwest::Client` instance for making HTTP requests.
+    /// * `text` - The text to search titles for.
+    /// * `limit` - The maximum number of results to return.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the request fails or the response cannot be parsed from JSON.
+    pub async fn search(
+        client: &reqwest::Client,
+        text: &str,
+        limit: u32,
+    ) -> Result<Collection<models::Anime>> {
+        let uri = Url::parse_with_params(
+            "https://kitsu.io/api/edge/anime/",
+            [("filter[text]", text), ("page[limit]", &limit.to_string())],
+        )?;
+        let anime = get_resources::<models::Anime>(client, uri).await?;
+        Ok(anime)
+    }
 }
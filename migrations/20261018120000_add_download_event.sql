@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS download_event
+(
+    sequence    BIGSERIAL PRIMARY KEY,
+    download_id UUID        NOT NULL,
+    payload     JSONB       NOT NULL,
+    created_at  TIMESTAMPTZ NOT NULL DEFAULT now(),
+    FOREIGN KEY (download_id) REFERENCES download (id) ON DELETE CASCADE
+);
+
+CREATE INDEX download_event_download_id_idx ON download_event (download_id);
@@ -0,0 +1,23 @@
+CREATE TABLE IF NOT EXISTS webhook
+(
+    id         UUID PRIMARY KEY     DEFAULT uuid_generate_v4(),
+    url        TEXT        NOT NULL,
+    secret     TEXT        NOT NULL,
+    enabled    BOOLEAN     NOT NULL DEFAULT TRUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE TABLE IF NOT EXISTS webhook_delivery
+(
+    id          BIGSERIAL PRIMARY KEY,
+    webhook_id  UUID        NOT NULL,
+    sequence    BIGINT      NOT NULL,
+    attempt     INTEGER     NOT NULL,
+    status_code SMALLINT,
+    error       TEXT,
+    created_at  TIMESTAMPTZ NOT NULL DEFAULT now(),
+    FOREIGN KEY (webhook_id) REFERENCES webhook (id) ON DELETE CASCADE
+);
+
+CREATE INDEX webhook_delivery_webhook_id_idx ON webhook_delivery (webhook_id, created_at);
@@ -0,0 +1,6 @@
+CREATE TYPE webhook_format AS ENUM ('generic', 'discord', 'slack');
+
+ALTER TABLE webhook
+    ADD COLUMN format         webhook_format NOT NULL DEFAULT 'generic',
+    ADD COLUMN title_patterns TEXT[]         NOT NULL DEFAULT '{}',
+    ALTER COLUMN secret DROP NOT NULL;
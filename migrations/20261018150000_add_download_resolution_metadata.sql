@@ -0,0 +1,3 @@
+ALTER TABLE download_resolution
+    ADD COLUMN size    BIGINT,
+    ADD COLUMN seeders INTEGER;
@@ -0,0 +1,3 @@
+CREATE TYPE download_event_kind AS ENUM ('download', 'revised');
+ALTER TABLE download_event
+    ADD COLUMN kind download_event_kind NOT NULL DEFAULT 'download';
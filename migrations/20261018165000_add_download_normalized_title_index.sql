@@ -0,0 +1,3 @@
+-- matches the normalized titles that related downloads, coverage and watchlist queries compare
+CREATE INDEX IF NOT EXISTS download_normalized_title_idx
+    ON download (lower(regexp_replace(title, '[^[:alnum:]]+', '', 'g')));
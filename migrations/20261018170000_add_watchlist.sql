@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS watchlist
+(
+    id         UUID PRIMARY KEY     DEFAULT uuid_generate_v4(),
+    title      TEXT,
+    show_id    INTEGER,
+    -- the known titles of followed Kitsu shows, looked up once when the show is followed
+    show_titles TEXT[]     NOT NULL DEFAULT '{}',
+    resolution SMALLINT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    CONSTRAINT watchlist_title_or_show CHECK ((title IS NULL) != (show_id IS NULL))
+);
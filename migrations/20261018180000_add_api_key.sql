@@ -0,0 +1,16 @@
+CREATE TYPE api_key_scope AS ENUM ('read', 'subscribe', 'admin');
+CREATE TABLE IF NOT EXISTS api_key
+(
+    id         UUID PRIMARY KEY     DEFAULT uuid_generate_v4(),
+    name       TEXT        NOT NULL,
+    -- the first characters of the key, to recognize it by
+    prefix     TEXT        NOT NULL,
+    -- sha-256 digest of the key, which itself is never stored
+    key_hash   BYTEA       NOT NULL UNIQUE,
+    scopes     api_key_scope[] NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+-- every stored key follows its own shows
+ALTER TABLE watchlist
+    ADD COLUMN api_key_id UUID NOT NULL REFERENCES api_key (id) ON DELETE CASCADE;
+CREATE INDEX IF NOT EXISTS watchlist_api_key_id_idx ON watchlist (api_key_id);
@@ -43,6 +43,7 @@ pub enum DownloadVariant {
 
 #[derive(Debug)]
 pub struct AnimeDownloads {
+    pub provider: String,
     pub title: String,
     pub variant: DownloadVariant,
     pub downloads: Vec<Download>,
@@ -61,12 +62,18 @@ pub struct Download {
     pub comments: String,
     pub resolution: u16,
     pub torrent: String,
+    pub magnet: Option<String>,
     pub file_name: String,
+    /// Size of the torrent contents in bytes.
+    pub size: Option<u64>,
+    /// Number of seeders at the time the feed was fetched.
+    pub seeders: Option<u32>,
     pub pub_date: DateTime<Utc>,
 }
 
 #[derive(Debug)]
 struct Entry {
+    provider: String,
     title: String,
     variant: DownloadVariant,
     download: Download,
@@ -112,12 +119,16 @@ fn build_url(title: Option<&str>) -> Result<Url, Error> {
 
 #[instrument(err)]
 fn map_item(item: Item) -> Result<Entry, Error> {
+    let magnet = nyaa_extension(&item, "infoHash").map(|hash| magnet(hash, item.title()));
+    let size = nyaa_extension(&item, "size").and_then(parse_size);
+    let seeders = nyaa_extension(&item, "seeders").and_then(|s| s.parse().ok());
     let pub_date = item.pub_date.ok_or(Error::None("rss pub date"))?;
     let date = DateTime::parse_from_rfc2822(&pub_date)?;
     let file_name = item.title.ok_or(Error::None("rss title"))?;
     let parts = ParsedDownload::try_from(&file_name)?;
 
     Ok(Entry {
+        provider: parts.source.to_string(),
         title: parts.title.to_string(),
         variant: parts.download_type.into(),
         download: Download {
@@ -125,11 +136,48 @@ fn map_item(item: Item) -> Result<Entry, Error> {
             resolution: parts.resolution,
             file_name,
             torrent: item.link.ok_or(Error::None("rss link"))?,
+            magnet,
+            size,
+            seeders,
             pub_date: date.with_timezone(&Utc),
         },
     })
 }
 
+/// Reads the value of an element in the `nyaa` namespace of the feed item.
+fn nyaa_extension<'i>(item: &'i Item, name: &str) -> Option<&'i str> {
+    item.extensions().get("nyaa")?.get(name)?.first()?.value()
+}
+
+fn magnet(info_hash: &str, file_name: Option<&str>) -> String {
+    let mut magnet = format!("magnet:?xt=urn:btih:{info_hash}");
+    if let Some(file_name) = file_name {
+        magnet.push_str("&dn=");
+        magnet.extend(url::form_urlencoded::byte_serialize(file_name.as_bytes()));
+    }
+    magnet
+}
+
+/// Parses sizes like `1.4 GiB` as displayed by nyaa into bytes.
+fn parse_size(value: &str) -> Option<u64> {
+    let (number, unit) = value.trim().split_once(' ')?;
+    let number: f64 = number.parse().ok()?;
+    let exponent = match unit {
+        "B" | "Bytes" => 0,
+        "KiB" => 1,
+        "MiB" => 2,
+        "GiB" => 3,
+        "TiB" => 4,
+        _ => return None,
+    };
+    let bytes = (number * 1024_f64.powi(exponent)).round();
+    if !bytes.is_finite() || bytes < 0.0 {
+        return None;
+    }
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    Some(bytes as u64)
+}
+
 fn map_groups<I>(entries: I) -> impl Iterator<Item = AnimeDownloads>
 where
     I: Iterator<Item = Entry>,
@@ -137,16 +185,44 @@ where
     let mut result_map = HashMap::<_, Vec<_>, RandomState>::default();
     for entry in entries {
         result_map
-            .entry((entry.title, entry.variant))
+            .entry((entry.provider, entry.title, entry.variant))
             .or_default()
             .push(entry.download);
     }
 
     result_map
         .into_iter()
-        .map(|((title, variant), downloads)| AnimeDownloads {
+        .map(|((provider, title, variant), downloads)| AnimeDownloads {
+            provider,
             title,
             variant,
             downloads,
         })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("1.4 GiB"), Some(1_503_238_554));
+        assert_eq!(parse_size("350.2 MiB"), Some(367_211_315));
+        assert_eq!(parse_size("12 Bytes"), Some(12));
+        assert_eq!(parse_size("1.4 GB"), None);
+        assert_eq!(parse_size("GiB"), None);
+    }
+
+    #[test]
+    fn test_magnet() {
+        let magnet = magnet(
+            "0123456789abcdef0123456789abcdef01234567",
+            Some("[SubsPlease] Frieren - 01 (1080p).mkv"),
+        );
+        assert_eq!(
+            magnet,
+            "magnet:?xt=urn:btih:0123456789abcdef0123456789abcdef01234567\
+             &dn=%5BSubsPlease%5D+Frieren+-+01+%281080p%29.mkv"
+        );
+    }
+}
@@ -1,8 +1,13 @@
+use std::env;
+use std::path::PathBuf;
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     // trigger recompilation when a new migration is added
     println!("cargo:rerun-if-changed=protos");
+    let out_dir = PathBuf::from(env::var("OUT_DIR")?);
     tonic_prost_build::configure()
         .emit_rerun_if_changed(true)
-        .compile_protos(&["protos/api.v2.proto"], &["protos"])?;
+        .file_descriptor_set_path(out_dir.join("api_descriptor.bin"))
+        .compile_protos(&["protos/api.v2.proto", "protos/api.v3.proto"], &["protos"])?;
     Ok(())
 }
@@ -1,7 +1,16 @@
+/// Encoded `FileDescriptorSet` of all api packages, used for server reflection.
+pub const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("api_descriptor");
+
 pub mod api {
     pub mod v2 {
         #![allow(clippy::large_enum_variant)]
         #![allow(clippy::derive_partial_eq_without_eq)]
         tonic::include_proto!("api.v2");
     }
+
+    pub mod v3 {
+        #![allow(clippy::large_enum_variant)]
+        #![allow(clippy::derive_partial_eq_without_eq)]
+        tonic::include_proto!("api.v3");
+    }
 }
@@ -0,0 +1,3 @@
+DELETE
+FROM api_key
+WHERE id = $1
\ No newline at end of file
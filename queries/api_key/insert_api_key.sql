@@ -0,0 +1,3 @@
+INSERT INTO api_key (name, prefix, key_hash, scopes)
+VALUES ($1, $2, $3, $4)
+RETURNING id, name, prefix, scopes AS "scopes: Vec<ApiKeyScope>", created_at
@@ -0,0 +1,3 @@
+SELECT id, name, prefix, scopes AS "scopes: Vec<ApiKeyScope>", created_at
+FROM api_key
+WHERE id = $1;
@@ -0,0 +1,2 @@
+SELECT id, key_hash, scopes AS "scopes: Vec<ApiKeyScope>"
+FROM api_key;
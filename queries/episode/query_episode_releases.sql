@@ -0,0 +1,9 @@
+SELECT title, episode as "episode!", min(created_at) as "released_at!"
+FROM download
+WHERE variant = 'episode'
+  AND episode IS NOT NULL
+  AND decimal IS NULL
+  AND (title ILIKE COALESCE($1, '') || '%')
+  AND created_at >= $2
+GROUP BY title, episode
+ORDER BY title, episode;
@@ -0,0 +1,19 @@
+INSERT INTO download_event (download_id, payload, kind)
+SELECT d.id,
+       $2,
+       CASE
+           WHEN EXISTS (SELECT 1
+                        FROM download older
+                        WHERE older.variant = 'episode'
+                          AND older.provider = d.provider
+                          AND older.title = d.title
+                          AND older.episode = d.episode
+                          AND COALESCE(older.decimal, -1) = COALESCE(d.decimal, -1)
+                          AND COALESCE(older.extra, '') = COALESCE(d.extra, '')
+                          AND COALESCE(older.version, 1) < COALESCE(d.version, 1))
+               THEN 'revised'::download_event_kind
+           ELSE 'download'::download_event_kind
+           END
+FROM download d
+WHERE d.id = $1
+RETURNING sequence, kind AS "kind: EventKind"
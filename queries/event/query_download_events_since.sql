@@ -0,0 +1,5 @@
+SELECT sequence, download_id, kind AS "kind: EventKind", payload AS "payload: Json<DownloadGroup>"
+FROM download_event
+WHERE sequence > $1
+ORDER BY sequence
+LIMIT $2;
@@ -0,0 +1,2 @@
+SELECT MAX(sequence) AS sequence
+FROM download_event
\ No newline at end of file
@@ -1,3 +1,4 @@
-INSERT INTO download_resolution (download_id, resolution, torrent, file_name, comments, magnet, created_at)
-VALUES ($1, $2, $3, $4, $5, $6, $7)
+INSERT INTO download_resolution (download_id, resolution, torrent, file_name, comments, magnet, size, seeders,
+                                 created_at)
+VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
 ON CONFLICT DO NOTHING
\ No newline at end of file
@@ -0,0 +1,15 @@
+SELECT d.title,
+       d.variant as "variant: Variant",
+       d.episode,
+       d.start_index,
+       d.end_index,
+       array_agg(dr.resolution) as "resolutions!"
+FROM download d
+         INNER JOIN download_resolution dr ON d.id = dr.download_id
+WHERE d.variant IN ('episode', 'batch')
+  AND d.decimal IS NULL
+  AND (d.title ILIKE COALESCE($1, '') || '%')
+  AND (cardinality($2::text[]) = 0
+    OR lower(regexp_replace(d.title, '[^[:alnum:]]+', '', 'g')) = ANY ($2))
+GROUP BY d.id
+ORDER BY d.title;
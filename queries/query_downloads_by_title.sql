@@ -9,9 +9,28 @@ SELECT id,
        extra,
        variant as "variant: Variant",
        start_index,
-       end_index
+       end_index,
+       superseded as "superseded!"
 FROM download
+         CROSS JOIN LATERAL (SELECT variant = 'episode' AND EXISTS (SELECT 1
+                                                                FROM download newer
+                                                                WHERE newer.variant = 'episode'
+                                                                  AND newer.provider = download.provider
+                                                                  AND newer.title = download.title
+                                                                  AND newer.episode = download.episode
+                                                                  AND COALESCE(newer.decimal, -1) = COALESCE(download.decimal, -1)
+                                                                  AND COALESCE(newer.extra, '') = COALESCE(download.extra, '')
+                                                                  AND COALESCE(newer.version, 1) > COALESCE(download.version, 1))
+                                 AS superseded) versions
 WHERE ($1::download_variant IS NULL OR variant = $1::download_variant)
   AND (title ILIKE COALESCE($2, '') || '%')
-ORDER BY updated_at DESC
-LIMIT 25;
\ No newline at end of file
+  AND ($3::uuid IS NULL OR id = $3)
+  AND ($4::text IS NULL OR lower(provider) = lower($4))
+  AND (cardinality($8::text[]) = 0
+    OR lower(regexp_replace(title, '[^[:alnum:]]+', '', 'g')) = ANY ($8))
+  AND (cardinality($9::download_variant[]) = 0 OR variant = ANY ($9))
+  AND ($10::integer IS NULL OR episode = $10 OR $10 BETWEEN start_index AND end_index)
+  AND NOT ($12::boolean AND superseded)
+  AND ($5::timestamptz IS NULL OR (updated_at, id) < ($5, $6::uuid))
+ORDER BY updated_at DESC, id DESC
+LIMIT COALESCE($7::bigint, 25) OFFSET COALESCE($11::bigint, 0);
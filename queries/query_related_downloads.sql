@@ -0,0 +1,13 @@
+SELECT d.id       as "download_id!",
+       related.id as "related_id!",
+       related.variant as "variant!: Variant"
+FROM download d
+         INNER JOIN download related
+                    ON lower(regexp_replace(related.title, '[^[:alnum:]]+', '', 'g')) =
+                       lower(regexp_replace(d.title, '[^[:alnum:]]+', '', 'g'))
+WHERE d.id = ANY ($1)
+  AND ((d.variant = 'episode' AND related.variant = 'batch'
+    AND d.episode BETWEEN related.start_index AND related.end_index)
+    OR (d.variant = 'batch' AND related.variant = 'episode'
+        AND related.episode BETWEEN d.start_index AND d.end_index))
+ORDER BY related.start_index, related.episode, related.decimal NULLS FIRST, related.provider, related.id;
@@ -0,0 +1,4 @@
+DELETE
+FROM watchlist
+WHERE id = $1
+  AND api_key_id = $2
@@ -0,0 +1,3 @@
+INSERT INTO watchlist (api_key_id, title, show_id, show_titles, resolution)
+VALUES ($1, $2, $3, $4, $5)
+RETURNING id, title, show_id, show_titles, resolution, created_at, updated_at
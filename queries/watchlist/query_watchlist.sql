@@ -0,0 +1,4 @@
+SELECT id, title, show_id, show_titles, resolution, created_at, updated_at
+FROM watchlist
+WHERE api_key_id = $1
+ORDER BY created_at;
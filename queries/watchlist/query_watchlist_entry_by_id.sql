@@ -0,0 +1,4 @@
+SELECT id, title, show_id, show_titles, resolution, created_at, updated_at
+FROM watchlist
+WHERE id = $1
+  AND api_key_id = $2
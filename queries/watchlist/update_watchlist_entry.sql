@@ -0,0 +1,6 @@
+UPDATE watchlist
+SET resolution = $3,
+    updated_at = now()
+WHERE id = $1
+  AND api_key_id = $2
+RETURNING id, title, show_id, show_titles, resolution, created_at, updated_at
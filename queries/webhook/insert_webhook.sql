@@ -0,0 +1,3 @@
+INSERT INTO webhook (url, secret, format, title_patterns, enabled)
+VALUES ($1, $2, $3, $4, $5)
+RETURNING id, url, format AS "format: WebhookFormat", title_patterns, secret IS NOT NULL AS "signed!", enabled, created_at, updated_at
\ No newline at end of file
@@ -0,0 +1,2 @@
+INSERT INTO webhook_delivery (webhook_id, sequence, attempt, status_code, error)
+VALUES ($1, $2, $3, $4, $5)
\ No newline at end of file
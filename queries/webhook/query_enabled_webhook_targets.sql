@@ -0,0 +1,3 @@
+SELECT id, url, secret, format AS "format: WebhookFormat", title_patterns
+FROM webhook
+WHERE enabled;
\ No newline at end of file
@@ -0,0 +1,3 @@
+SELECT id, url, format AS "format: WebhookFormat", title_patterns, secret IS NOT NULL AS "signed!", enabled, created_at, updated_at
+FROM webhook
+WHERE id = $1
\ No newline at end of file
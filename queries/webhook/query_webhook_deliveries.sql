@@ -0,0 +1,5 @@
+SELECT id, sequence, attempt, status_code, error, created_at
+FROM webhook_delivery
+WHERE webhook_id = $1
+ORDER BY created_at DESC, id DESC
+LIMIT $2;
\ No newline at end of file
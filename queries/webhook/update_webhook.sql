@@ -0,0 +1,9 @@
+UPDATE webhook
+SET url            = COALESCE($2, url),
+    secret         = COALESCE($3, secret),
+    format         = COALESCE($4, format),
+    title_patterns = COALESCE($5, title_patterns),
+    enabled        = COALESCE($6, enabled),
+    updated_at     = now()
+WHERE id = $1
+RETURNING id, url, format AS "format: WebhookFormat", title_patterns, secret IS NOT NULL AS "signed!", enabled, created_at, updated_at
\ No newline at end of file
@@ -0,0 +1,405 @@
+//! Api keys and the scopes they grant, checked by the REST middleware and the gRPC interceptor.
+use std::collections::HashMap;
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::Duration;
+
+use ahash::RandomState;
+use axum::extract::{Request, State};
+use axum::http::header::AUTHORIZATION;
+use axum::middleware::Next;
+use axum::response::Response;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use sha2::{Digest, Sha256};
+use tonic::Status;
+use tonic::service::Interceptor;
+use tracing::error;
+use uuid::Uuid;
+
+use crate::datasource::repository;
+use crate::datasource::repository::api_keys::ApiKeyScope;
+use crate::errors::Error;
+use crate::state::{AuthConfig, DBPool};
+
+const KEY_PREFIX: &str = "ak_";
+/// Number of leading characters of a key that are stored to recognize it by.
+const DISPLAY_PREFIX_LENGTH: usize = 10;
+const REFRESH_INTERVAL: Duration = Duration::from_mins(1);
+const API_KEY_HEADER: &str = "x-api-key";
+/// The query parameter keys can be passed in, for clients that cannot set headers such as feed
+/// readers, browser event sources and Torznab indexer clients.
+pub(crate) const API_KEY_PARAM: &str = "apikey";
+
+const NOT_STORED_MESSAGE: &str = "only stored api keys have a watchlist, not the admin key";
+
+pub(crate) type KeyHash = [u8; 32];
+
+/// A key created through the api, as kept in memory.
+#[derive(Debug, Clone)]
+struct StoredKey {
+    id: Uuid,
+    scopes: Vec<ApiKeyScope>,
+}
+
+/// The scopes granted to a request.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Access {
+    /// Whether the request came with a valid api key.
+    authenticated: bool,
+    /// The stored key the request came with, which the configured admin key is not.
+    key_id: Option<Uuid>,
+    scopes: Vec<ApiKeyScope>,
+}
+
+impl Access {
+    fn allows(&self, scope: ApiKeyScope) -> bool {
+        self.scopes
+            .iter()
+            .any(|&granted| granted == scope || granted == ApiKeyScope::Admin)
+    }
+
+    pub(crate) fn require(&self, scope: ApiKeyScope) -> Result<(), Denied> {
+        if self.allows(scope) {
+            Ok(())
+        } else if self.authenticated {
+            Err(Denied::Forbidden(scope))
+        } else {
+            Err(Denied::Unauthenticated)
+        }
+    }
+
+    /// The id of the stored key the request came with, which owns a watchlist.
+    pub(crate) fn key_id(&self) -> Result<Uuid, Denied> {
+        match self.key_id {
+            Some(key_id) => Ok(key_id),
+            None if self.authenticated => Err(Denied::NotStored),
+            None => Err(Denied::Unauthenticated),
+        }
+    }
+}
+
+/// Why a request was refused.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum Denied {
+    /// The api key is missing or unknown.
+    Unauthenticated,
+    /// The api key lacks the scope.
+    Forbidden(ApiKeyScope),
+    /// The configured admin key was used where a stored key is needed.
+    NotStored,
+}
+
+impl From<Denied> for Error {
+    fn from(value: Denied) -> Self {
+        match value {
+            Denied::Unauthenticated => Self::Unauthorized,
+            Denied::Forbidden(scope) => Self::Forbidden(scope),
+            Denied::NotStored => Self::BadRequest(NOT_STORED_MESSAGE.to_string()),
+        }
+    }
+}
+
+impl From<Denied> for Status {
+    fn from(value: Denied) -> Self {
+        match value {
+            Denied::Unauthenticated => Status::unauthenticated("missing or invalid api key"),
+            Denied::Forbidden(scope) => {
+                Status::permission_denied(format!("the api key lacks the {scope} scope"))
+            }
+            Denied::NotStored => Status::failed_precondition(NOT_STORED_MESSAGE),
+        }
+    }
+}
+
+/// The known api keys by their hash.
+///
+/// Kept in memory so keys can be checked without a database round trip, which the synchronous
+/// gRPC interceptor could not make. Changes made by this instance are loaded right away, those of
+/// others within [`REFRESH_INTERVAL`].
+#[derive(Debug, Clone)]
+pub struct ApiKeys {
+    keys: Arc<RwLock<HashMap<KeyHash, StoredKey, RandomState>>>,
+    admin_key: Option<KeyHash>,
+    anonymous_scopes: Vec<ApiKeyScope>,
+}
+
+impl ApiKeys {
+    #[must_use]
+    pub fn new(config: &AuthConfig) -> Self {
+        Self {
+            keys: Arc::default(),
+            admin_key: config.admin_key.as_deref().map(hash),
+            anonymous_scopes: config.anonymous_scopes.clone(),
+        }
+    }
+
+    /// What a request with `key` has access to, or `None` when the key is unknown.
+    pub(crate) fn access(&self, key: Option<&str>) -> Option<Access> {
+        let Some(key) = key else {
+            return Some(Access {
+                authenticated: false,
+                key_id: None,
+                scopes: self.anonymous_scopes.clone(),
+            });
+        };
+        let hash = hash(key);
+        let (key_id, scopes) = if self.admin_key == Some(hash) {
+            (None, vec![ApiKeyScope::Admin])
+        } else {
+            let keys = self.keys.read().unwrap_or_else(PoisonError::into_inner);
+            let key = keys.get(&hash)?;
+            (Some(key.id), key.scopes.clone())
+        };
+        Some(Access {
+            authenticated: true,
+            key_id,
+            scopes,
+        })
+    }
+
+    /// The hash of `key` if it is known, to tell clients apart by.
+    pub(crate) fn known_hash(&self, key: &str) -> Option<KeyHash> {
+        let hash = hash(key);
+        let known = self.admin_key == Some(hash)
+            || self
+                .keys
+                .read()
+                .unwrap_or_else(PoisonError::into_inner)
+                .contains_key(&hash);
+        known.then_some(hash)
+    }
+
+    pub(crate) fn require(&self, key: Option<&str>, scope: ApiKeyScope) -> Result<(), Denied> {
+        self.access(key)
+            .ok_or(Denied::Unauthenticated)?
+            .require(scope)
+    }
+
+    /// Adds a stored key without going through the database.
+    #[cfg(test)]
+    pub(crate) fn insert_stored(&self, key: &str, id: Uuid, scopes: Vec<ApiKeyScope>) {
+        self.keys
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(hash(key), StoredKey { id, scopes });
+    }
+
+    /// Replaces the known keys with those in the database.
+    pub async fn reload(&self, pool: &DBPool) -> anyhow::Result<()> {
+        let keys = repository::api_keys::hashes(pool)
+            .await?
+            .into_iter()
+            .filter_map(|key| {
+                let stored = StoredKey {
+                    id: key.id,
+                    scopes: key.scopes,
+                };
+                Some((key.key_hash.try_into().ok()?, stored))
+            })
+            .collect();
+        *self.keys.write().unwrap_or_else(PoisonError::into_inner) = keys;
+        Ok(())
+    }
+
+    /// Reloads the keys every [`REFRESH_INTERVAL`], starting right away.
+    pub async fn refresh(self, pool: DBPool) {
+        let mut interval = tokio::time::interval(REFRESH_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(e) = self.reload(&pool).await {
+                error!(error = ?e, "failed to reload the api keys");
+            }
+        }
+    }
+}
+
+/// A new random key, along with the hash it is stored as.
+pub(crate) fn generate() -> (String, Vec<u8>) {
+    let key = format!(
+        "{KEY_PREFIX}{}",
+        URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>())
+    );
+    let hash = hash(&key).to_vec();
+    (key, hash)
+}
+
+/// The leading characters of `key` that are stored to recognize it by.
+pub(crate) fn display_prefix(key: &str) -> String {
+    key.chars().take(DISPLAY_PREFIX_LENGTH).collect()
+}
+
+fn hash(key: &str) -> KeyHash {
+    Sha256::digest(key.as_bytes()).into()
+}
+
+fn bearer_token(authorization: &str) -> Option<&str> {
+    let (scheme, token) = authorization.split_once(' ')?;
+    scheme.eq_ignore_ascii_case("bearer").then(|| token.trim())
+}
+
+/// The key of a request, from the authorization or `x-api-key` header or the query.
+pub(crate) fn request_key(request: &Request) -> Option<String> {
+    let headers = request.headers();
+    let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+    if let Some(token) = header(AUTHORIZATION.as_str()).and_then(bearer_token) {
+        return Some(token.to_string());
+    }
+    if let Some(key) = header(API_KEY_HEADER) {
+        return Some(key.to_string());
+    }
+    url::form_urlencoded::parse(request.uri().query()?.as_bytes())
+        .find(|(name, _)| name == API_KEY_PARAM)
+        .map(|(_, key)| key.into_owned())
+}
+
+/// Rejects requests whose key lacks `scope` and attaches the [`Access`] of the others, for the
+/// handlers that need the calling key.
+async fn require(
+    keys: &ApiKeys,
+    scope: ApiKeyScope,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, Error> {
+    let access = keys
+        .access(request_key(&request).as_deref())
+        .ok_or(Denied::Unauthenticated)?;
+    access.require(scope)?;
+    request.extensions_mut().insert(access);
+    Ok(next.run(request).await)
+}
+
+pub(crate) async fn require_read(
+    State(keys): State<ApiKeys>,
+    request: Request,
+    next: Next,
+) -> Result<Response, Error> {
+    require(&keys, ApiKeyScope::Read, request, next).await
+}
+
+pub(crate) async fn require_subscribe(
+    State(keys): State<ApiKeys>,
+    request: Request,
+    next: Next,
+) -> Result<Response, Error> {
+    require(&keys, ApiKeyScope::Subscribe, request, next).await
+}
+
+pub(crate) async fn require_admin(
+    State(keys): State<ApiKeys>,
+    request: Request,
+    next: Next,
+) -> Result<Response, Error> {
+    require(&keys, ApiKeyScope::Admin, request, next).await
+}
+
+/// Rejects gRPC calls with an unknown key and attaches the [`Access`] of the others, which the
+/// methods check with [`authorize`].
+#[derive(Debug, Clone)]
+pub(crate) struct ApiKeyInterceptor {
+    keys: ApiKeys,
+}
+
+impl ApiKeyInterceptor {
+    pub(crate) fn new(keys: ApiKeys) -> Self {
+        Self { keys }
+    }
+}
+
+impl Interceptor for ApiKeyInterceptor {
+    fn call(&mut self, mut request: tonic::Request<()>) -> Result<tonic::Request<()>, Status> {
+        let metadata = request.metadata();
+        let header = |name| metadata.get(name).and_then(|value| value.to_str().ok());
+        let key = header(AUTHORIZATION.as_str())
+            .and_then(bearer_token)
+            .or_else(|| header(API_KEY_HEADER));
+        let access = self.keys.access(key).ok_or(Denied::Unauthenticated)?;
+        request.extensions_mut().insert(access);
+        Ok(request)
+    }
+}
+
+/// Checks the [`Access`] attached by the [`ApiKeyInterceptor`] grants `scope`.
+pub(crate) fn authorize<T>(request: &tonic::Request<T>, scope: ApiKeyScope) -> Result<(), Status> {
+    request
+        .extensions()
+        .get::<Access>()
+        .ok_or(Denied::Unauthenticated)?
+        .require(scope)?;
+    Ok(())
+}
+
+/// The id of the stored key of a gRPC call, from the [`Access`] attached by the
+/// [`ApiKeyInterceptor`].
+pub(crate) fn key_id<T>(request: &tonic::Request<T>) -> Result<Uuid, Status> {
+    let key_id = request
+        .extensions()
+        .get::<Access>()
+        .ok_or(Denied::Unauthenticated)?
+        .key_id()?;
+    Ok(key_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys() -> ApiKeys {
+        let keys = ApiKeys::new(&AuthConfig {
+            admin_key: Some("admin".to_string()),
+            anonymous_scopes: vec![ApiKeyScope::Read],
+        });
+        keys.insert_stored(
+            "subscriber",
+            Uuid::from_u128(1),
+            vec![ApiKeyScope::Subscribe],
+        );
+        keys
+    }
+
+    #[test]
+    fn test_key_id() {
+        let keys = keys();
+        let key_id = |key| keys.access(key).unwrap().key_id();
+        assert_eq!(key_id(Some("subscriber")), Ok(Uuid::from_u128(1)));
+        assert_eq!(key_id(Some("admin")), Err(Denied::NotStored));
+        assert_eq!(key_id(None), Err(Denied::Unauthenticated));
+    }
+
+    #[test]
+    fn test_require() {
+        let keys = keys();
+        assert_eq!(keys.require(None, ApiKeyScope::Read), Ok(()));
+        assert_eq!(
+            keys.require(None, ApiKeyScope::Subscribe),
+            Err(Denied::Unauthenticated)
+        );
+        assert_eq!(
+            keys.require(Some("subscriber"), ApiKeyScope::Subscribe),
+            Ok(())
+        );
+        assert_eq!(
+            keys.require(Some("subscriber"), ApiKeyScope::Admin),
+            Err(Denied::Forbidden(ApiKeyScope::Admin))
+        );
+        assert_eq!(keys.require(Some("admin"), ApiKeyScope::Subscribe), Ok(()));
+        assert_eq!(
+            keys.require(Some("unknown"), ApiKeyScope::Read),
+            Err(Denied::Unauthenticated)
+        );
+    }
+
+    #[test]
+    fn test_bearer_token() {
+        assert_eq!(bearer_token("Bearer ak_key"), Some("ak_key"));
+        assert_eq!(bearer_token("bearer  ak_key "), Some("ak_key"));
+        assert_eq!(bearer_token("Basic dXNlcg=="), None);
+    }
+
+    #[test]
+    fn test_generate() {
+        let (key, key_hash) = generate();
+        assert!(key.starts_with(KEY_PREFIX));
+        assert_eq!(key_hash, hash(&key));
+        assert_eq!(display_prefix(&key).len(), DISPLAY_PREFIX_LENGTH);
+    }
+}
@@ -1,7 +1,8 @@
 use anyhow::Result;
 use tracing_subscriber::prelude::*;
 
-use anime_service::{jobs::poller, state::AppState};
+use anime_service::jobs::poller;
+use anime_service::state::{AppState, PollerConfig};
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -13,7 +14,8 @@ async fn main() -> Result<()> {
 
     let app_state = AppState::new()?;
     sqlx::migrate!().run(&app_state.pool).await?;
-    let poller = poller::Poller::persistent_from_state(&app_state).await?;
+    let poller =
+        poller::Poller::configured_from_state(&app_state, &PollerConfig::from_env()?).await?;
     poller.start()?;
 
     anime_service::serve_combined(app_state).await?;
@@ -2,7 +2,9 @@ use anyhow::Result;
 use std::time::{Duration, Instant};
 use tracing_subscriber::prelude::*;
 
-use anime_service::{jobs::poller, state::AppState};
+use anime_service::jobs::handlers::{self, BoxedHandler};
+use anime_service::jobs::poller;
+use anime_service::state::{AppState, PollerConfig};
 use poller::{PersistentPoller, Poller};
 
 #[tokio::main]
@@ -16,7 +18,7 @@ async fn main() -> Result<()> {
     let app_state = AppState::new()?;
     sqlx::migrate!().run(&app_state.pool).await?;
 
-    let poller = get_poller(&app_state);
+    let poller = get_poller(&app_state, &PollerConfig::from_env()?);
 
     let interval = tokio::time::interval_at(
         (Instant::now() + Duration::from_secs(2)).into(),
@@ -27,11 +29,12 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
-fn get_poller(app_state: &AppState) -> Poller<PersistentPoller> {
+fn get_poller(app_state: &AppState, config: &PollerConfig) -> Poller<BoxedHandler> {
     use chrono::{Duration, Utc};
 
     let one_week = Duration::weeks(1);
     let last_updated_at = Utc::now() - one_week;
-    let handler = PersistentPoller::new(app_state);
+    let handler = handlers::pipeline(PersistentPoller::new(app_state), config);
     Poller::new_with_last_updated_at(app_state.client.clone(), handler, last_updated_at)
+        .with_health(app_state.poller_health.clone())
 }
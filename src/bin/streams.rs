@@ -2,9 +2,16 @@ use anyhow::Result;
 use chrono::{Duration, Utc};
 use reqwest::Client;
 use tokio::sync::broadcast;
+use tracing::warn;
 use tracing_subscriber::prelude::*;
 
+use anime_service::auth::ApiKeys;
+use anime_service::jobs::handlers;
 use anime_service::jobs::poller::{Poller, TransientPoller};
+use anime_service::rate_limit::RateLimits;
+use anime_service::state::{
+    AuthConfig, PollerConfig, RateLimitConfig, SubscriptionConfig, create_db_pool,
+};
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -14,13 +21,31 @@ async fn main() -> Result<()> {
         .with(tracing_subscriber::fmt::layer())
         .init();
 
-    let (tx, _) = broadcast::channel(32);
+    let subscriptions = SubscriptionConfig::from_env()?;
+    let (tx, _) = broadcast::channel(subscriptions.channel_capacity.get());
     let one_week = Duration::try_weeks(1).expect("1 week fits in a duration");
     let last_updated_at = Utc::now() - one_week;
-    let handler = TransientPoller::new(tx.clone());
-    let poller = Poller::new_with_last_updated_at(Client::default(), handler, last_updated_at);
+    let handler = handlers::pipeline(TransientPoller::new(tx.clone()), &PollerConfig::from_env()?);
+    let client = Client::default();
+    let poller = Poller::new_with_last_updated_at(client.clone(), handler, last_updated_at);
+    let poller_health = poller.health();
     poller.start()?;
 
-    anime_service::serve_tonic(tx).await?;
+    let api_keys = ApiKeys::new(&AuthConfig::from_env()?);
+    // the keys created through the REST api are stored in its database, if it is configured
+    let api_key_pool = create_db_pool()
+        .inspect_err(|e| warn!(error = ?e, "no database for api keys, only the admin key is known"))
+        .ok();
+    let rate_limits = RateLimits::new(&RateLimitConfig::from_env()?);
+    anime_service::serve_tonic(
+        client,
+        tx,
+        subscriptions,
+        poller_health,
+        api_keys,
+        api_key_pool,
+        rate_limits,
+    )
+    .await?;
     Ok(())
 }
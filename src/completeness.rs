@@ -0,0 +1,151 @@
+//! Finds the episodes and resolutions missing from the downloads of a show.
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::RangeInclusive;
+
+use crate::models::{Completeness, MissingResolutions};
+use crate::subscription::filter::ShowTitle;
+
+/// The episodes and resolutions of a single episode or batch download.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Coverage {
+    pub(crate) title: String,
+    pub(crate) episodes: RangeInclusive<u32>,
+    pub(crate) batch: bool,
+    pub(crate) resolutions: Vec<u16>,
+}
+
+/// The completeness of every show in `coverage`, ordered by title.
+///
+/// Titles that only differ in case or punctuation are considered the same show.
+pub(crate) fn completeness(coverage: &[Coverage]) -> Vec<Completeness> {
+    let mut shows: BTreeMap<_, Vec<_>> = BTreeMap::new();
+    for download in coverage {
+        shows
+            .entry(ShowTitle::new(&download.title).normalized().to_string())
+            .or_default()
+            .push(download);
+    }
+    let mut completeness: Vec<_> = shows
+        .into_values()
+        .filter_map(|downloads| show_completeness(&downloads))
+        .collect();
+    completeness.sort_by(|a, b| a.title.cmp(&b.title));
+    completeness
+}
+
+fn show_completeness(downloads: &[&Coverage]) -> Option<Completeness> {
+    let title = downloads.first()?.title.clone();
+    let mut individual: BTreeMap<u32, BTreeSet<u16>> = BTreeMap::new();
+    let mut batched: BTreeMap<u32, BTreeSet<u16>> = BTreeMap::new();
+    for download in downloads {
+        let episodes = if download.batch {
+            &mut batched
+        } else {
+            &mut individual
+        };
+        for episode in download.episodes.clone() {
+            episodes
+                .entry(episode)
+                .or_default()
+                .extend(&download.resolutions);
+        }
+    }
+    let first_episode = individual.keys().chain(batched.keys()).min().copied()?;
+    let latest_episode = individual.keys().chain(batched.keys()).max().copied()?;
+    let show_resolutions: BTreeSet<u16> = individual
+        .values()
+        .chain(batched.values())
+        .flatten()
+        .copied()
+        .collect();
+
+    let mut missing_episodes = Vec::new();
+    let mut missing_resolutions = Vec::new();
+    for episode in first_episode..=latest_episode {
+        let resolutions: BTreeSet<_> = individual
+            .get(&episode)
+            .into_iter()
+            .chain(batched.get(&episode))
+            .flatten()
+            .collect();
+        if resolutions.is_empty() {
+            missing_episodes.push(episode);
+            continue;
+        }
+        let missing: Vec<_> = show_resolutions
+            .iter()
+            .filter(|resolution| !resolutions.contains(resolution))
+            .copied()
+            .collect();
+        if !missing.is_empty() {
+            missing_resolutions.push(MissingResolutions {
+                episode,
+                resolutions: missing,
+            });
+        }
+    }
+    let batch_only_episodes = batched
+        .keys()
+        .filter(|episode| !individual.contains_key(episode))
+        .copied()
+        .collect();
+    Some(Completeness {
+        title,
+        complete: missing_episodes.is_empty() && missing_resolutions.is_empty(),
+        first_episode,
+        latest_episode,
+        missing_episodes,
+        batch_only_episodes,
+        missing_resolutions,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn coverage(title: &str, episodes: RangeInclusive<u32>, resolutions: &[u16]) -> Coverage {
+        Coverage {
+            title: title.to_string(),
+            batch: episodes.start() != episodes.end(),
+            episodes,
+            resolutions: resolutions.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_completeness() {
+        let downloads = [
+            coverage("Frieren", 1..=1, &[720, 1080]),
+            coverage("Frieren", 2..=2, &[720, 1080]),
+            coverage("FRIEREN!", 4..=4, &[720, 1080]),
+            coverage("Frieren", 5..=6, &[1080]),
+            coverage("Oshi no Ko", 1..=2, &[1080]),
+        ];
+        let completeness = completeness(&downloads);
+        assert_eq!(
+            completeness[0],
+            Completeness {
+                title: "Frieren".to_string(),
+                complete: false,
+                first_episode: 1,
+                latest_episode: 6,
+                missing_episodes: vec![3],
+                batch_only_episodes: vec![5, 6],
+                missing_resolutions: vec![
+                    MissingResolutions {
+                        episode: 5,
+                        resolutions: vec![720],
+                    },
+                    MissingResolutions {
+                        episode: 6,
+                        resolutions: vec![720],
+                    },
+                ],
+            }
+        );
+        assert_eq!(completeness[1].title, "Oshi no Ko");
+        assert!(completeness[1].complete);
+        assert_eq!(completeness[1].batch_only_episodes, [1, 2]);
+    }
+}
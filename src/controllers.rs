@@ -1,2 +1,7 @@
+pub(crate) mod feed;
 pub(crate) mod grpc;
+pub(crate) mod ical;
+pub(crate) mod openapi;
 pub(crate) mod rest;
+pub(crate) mod torznab;
+pub(crate) mod websocket;
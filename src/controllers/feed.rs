@@ -0,0 +1,287 @@
+use std::cmp::Reverse;
+
+use atom_syndication as atom;
+use axum::http::header::HOST;
+use axum::http::{HeaderMap, Uri};
+use chrono::Utc;
+use serde::Deserialize;
+use utoipa::ToSchema;
+
+use crate::auth::API_KEY_PARAM;
+use crate::models::{Download, DownloadGroup, DownloadVariant};
+
+pub(crate) const BITTORRENT_TYPE: &str = "application/x-bittorrent";
+const FORWARDED_PROTO_HEADER: &str = "x-forwarded-proto";
+
+/// What the enclosures of feed items point to.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum EnclosureLink {
+    #[default]
+    Torrent,
+    /// The magnet link, falling back to the torrent file when it is not known.
+    Magnet,
+}
+
+/// The channel level details of a feed.
+#[derive(Debug, Clone)]
+pub(crate) struct FeedInfo {
+    pub(crate) title: String,
+    /// Absolute url the feed is served from.
+    pub(crate) url: String,
+    pub(crate) enclosure: EnclosureLink,
+}
+
+impl FeedInfo {
+    /// Describes the feed served for the request to `uri`, using the `Host` header to make the
+    /// url absolute. An api key in the query is left out, so the feed does not leak it.
+    pub(crate) fn new(
+        title: String,
+        headers: &HeaderMap,
+        uri: &Uri,
+        enclosure: EnclosureLink,
+    ) -> Self {
+        let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+        let scheme = header(FORWARDED_PROTO_HEADER).unwrap_or("http");
+        let host = header(HOST.as_str()).unwrap_or("localhost");
+        Self {
+            title,
+            url: format!("{scheme}://{host}{}", without_api_key(uri)),
+            enclosure,
+        }
+    }
+
+    fn enclosure_url<'d>(&self, download: &'d Download) -> &'d str {
+        match (self.enclosure, &download.magnet) {
+            (EnclosureLink::Magnet, Some(magnet)) => magnet,
+            _ => &download.torrent,
+        }
+    }
+}
+
+/// The path and query of `uri` without the api key parameter.
+fn without_api_key(uri: &Uri) -> String {
+    let Some(query) = uri.query() else {
+        return uri.path().to_string();
+    };
+    let params =
+        url::form_urlencoded::parse(query.as_bytes()).filter(|(name, _)| name != API_KEY_PARAM);
+    let query = url::form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(params)
+        .finish();
+    if query.is_empty() {
+        uri.path().to_string()
+    } else {
+        format!("{}?{query}", uri.path())
+    }
+}
+
+/// Every download of the groups, most recently published first.
+pub(crate) fn entries(groups: &[DownloadGroup]) -> Vec<(&DownloadGroup, &Download)> {
+    let mut entries: Vec<_> = groups
+        .iter()
+        .flat_map(|group| {
+            group
+                .downloads
+                .iter()
+                .map(move |download| (group, download))
+        })
+        .collect();
+    entries.sort_by_key(|(_, download)| Reverse(download.published_date));
+    entries
+}
+
+fn variant_name(variant: &DownloadVariant) -> &'static str {
+    match variant {
+        DownloadVariant::Batch(_) => "batch",
+        DownloadVariant::Episode(_) => "episode",
+        DownloadVariant::Movie => "movie",
+    }
+}
+
+pub(crate) fn rss(info: &FeedInfo, groups: &[DownloadGroup]) -> rss::Channel {
+    let items = entries(groups)
+        .into_iter()
+        .map(|(group, download)| rss::Item {
+            title: Some(download.file_name.clone()),
+            link: Some(download.comments.clone()),
+            description: Some(format!(
+                "{} in {}p, released by {}",
+                group.title, download.resolution, group.provider
+            )),
+            categories: vec![rss::Category {
+                name: variant_name(&group.variant).to_string(),
+                domain: None,
+            }],
+            guid: Some(rss::Guid {
+                value: download.comments.clone(),
+                permalink: true,
+            }),
+            pub_date: Some(download.published_date.to_rfc2822()),
+            enclosure: Some(rss::Enclosure {
+                url: info.enclosure_url(download).to_string(),
+                length: download.size.unwrap_or_default().to_string(),
+                mime_type: BITTORRENT_TYPE.to_string(),
+            }),
+            ..rss::Item::default()
+        })
+        .collect();
+    rss::Channel {
+        title: info.title.clone(),
+        link: info.url.clone(),
+        description: info.title.clone(),
+        last_build_date: groups
+            .iter()
+            .map(|group| group.updated_at)
+            .max()
+            .map(|updated_at| updated_at.to_rfc2822()),
+        items,
+        ..rss::Channel::default()
+    }
+}
+
+pub(crate) fn atom(info: &FeedInfo, groups: &[DownloadGroup]) -> atom::Feed {
+    let entries = entries(groups)
+        .into_iter()
+        .map(|(group, download)| atom::Entry {
+            title: atom::Text::plain(download.file_name.clone()),
+            id: download.comments.clone(),
+            updated: download.published_date.into(),
+            published: Some(download.published_date.into()),
+            authors: vec![atom::Person {
+                name: group.provider.clone(),
+                ..atom::Person::default()
+            }],
+            categories: vec![atom::Category {
+                term: variant_name(&group.variant).to_string(),
+                ..atom::Category::default()
+            }],
+            links: vec![
+                atom::Link {
+                    href: download.comments.clone(),
+                    rel: "alternate".to_string(),
+                    mime_type: Some("text/html".to_string()),
+                    ..atom::Link::default()
+                },
+                atom::Link {
+                    href: info.enclosure_url(download).to_string(),
+                    rel: "enclosure".to_string(),
+                    mime_type: Some(BITTORRENT_TYPE.to_string()),
+                    length: download.size.map(|size| size.to_string()),
+                    ..atom::Link::default()
+                },
+            ],
+            ..atom::Entry::default()
+        })
+        .collect();
+    atom::Feed {
+        title: atom::Text::plain(info.title.clone()),
+        id: info.url.clone(),
+        updated: groups
+            .iter()
+            .map(|group| group.updated_at)
+            .max()
+            .unwrap_or_else(Utc::now)
+            .into(),
+        links: vec![atom::Link {
+            href: info.url.clone(),
+            rel: "self".to_string(),
+            mime_type: Some("application/atom+xml".to_string()),
+            ..atom::Link::default()
+        }],
+        entries,
+        ..atom::Feed::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+
+    use super::*;
+    use crate::models::Episode;
+
+    fn info(enclosure: EnclosureLink) -> FeedInfo {
+        let mut headers = HeaderMap::new();
+        headers.insert(HOST, "anime.example".parse().unwrap());
+        let uri = "/v1/downloads/feed.rss?title=frieren".parse().unwrap();
+        FeedInfo::new("Downloads".to_string(), &headers, &uri, enclosure)
+    }
+
+    fn groups() -> Vec<DownloadGroup> {
+        let download = |resolution: u16, timestamp: i64| {
+            Download::fixture(resolution)
+                .with_size(u64::from(resolution) * 1024)
+                .with_published_date(DateTime::from_timestamp(timestamp, 0).unwrap())
+        };
+        vec![
+            DownloadGroup::fixture("Frieren")
+                .with_variant(DownloadVariant::Episode(Episode::fixture(7)))
+                .with_download(download(720, 1_700_000_000))
+                .with_download(
+                    download(1080, 1_700_000_100).with_magnet("magnet:?xt=urn:btih:1080"),
+                ),
+        ]
+    }
+
+    #[test]
+    fn test_feed_info_url() {
+        assert_eq!(
+            info(EnclosureLink::Torrent).url,
+            "http://anime.example/v1/downloads/feed.rss?title=frieren"
+        );
+    }
+
+    #[test]
+    fn test_feed_info_url_without_api_key() {
+        let headers = HeaderMap::new();
+        let url = |uri: &str| {
+            let uri = uri.parse().unwrap();
+            FeedInfo::new(String::new(), &headers, &uri, EnclosureLink::Torrent).url
+        };
+        assert_eq!(
+            url("/v1/downloads/feed.rss?apikey=secret&title=frieren"),
+            "http://localhost/v1/downloads/feed.rss?title=frieren"
+        );
+        assert_eq!(
+            url("/v1/downloads/feed.atom?apikey=secret"),
+            "http://localhost/v1/downloads/feed.atom"
+        );
+    }
+
+    #[test]
+    fn test_rss() {
+        let channel = rss(&info(EnclosureLink::Magnet), &groups());
+        let channel = rss::Channel::read_from(channel.to_string().as_bytes()).unwrap();
+        let enclosures: Vec<_> = channel
+            .items()
+            .iter()
+            .map(|item| item.enclosure().unwrap().url())
+            .collect();
+        // newest first, falling back to the torrent without a magnet
+        assert_eq!(
+            enclosures,
+            [
+                "magnet:?xt=urn:btih:1080",
+                "https://nyaa.si/download/720.torrent"
+            ]
+        );
+        assert_eq!(channel.items()[0].enclosure().unwrap().length(), "1105920");
+        assert_eq!(channel.items()[0].categories()[0].name(), "episode");
+    }
+
+    #[test]
+    fn test_atom() {
+        let feed = atom(&info(EnclosureLink::Torrent), &groups());
+        let feed = atom::Feed::read_from(feed.to_string().as_bytes()).unwrap();
+        assert_eq!(feed.entries().len(), 2);
+        let entry = &feed.entries()[0];
+        assert_eq!(entry.id(), "https://nyaa.si/view/1080");
+        let enclosure = entry
+            .links()
+            .iter()
+            .find(|link| link.rel() == "enclosure")
+            .unwrap();
+        assert_eq!(enclosure.href(), "https://nyaa.si/download/1080.torrent");
+    }
+}
@@ -1,51 +1,422 @@
+use std::net::SocketAddr;
+
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use chrono::DateTime;
+use futures::StreamExt;
+use reqwest::StatusCode;
+use sqlx::types::Uuid;
 use tokio::sync::broadcast::Sender;
 use tokio::sync::mpsc;
 use tokio_stream::wrappers::ReceiverStream;
 use tonic::Status;
-use tracing::warn;
+use tracing::{debug, error, warn};
+
+use proto::api::v2::{
+    DownloadCollection, GetDownloadRequest, GetShowRequest, ListDownloadsRequest,
+    ListDownloadsResponse, ListShowDownloadsRequest, SearchShowsRequest, SearchShowsResponse,
+    SubscribeRequest, VariantKind,
+};
+
+use crate::auth;
+use crate::datasource::repository;
+use crate::datasource::repository::api_keys::ApiKeyScope;
+use crate::datasource::repository::downloads::{Cursor, QueryOptions, Variant};
+use crate::models::{DownloadEvent, DownloadGroup, Show};
+use crate::rate_limit::{ClientId, RateLimits, SubscriptionPermit};
+use crate::state::{DBPool, ReqwestClient};
+use crate::subscription;
+use crate::subscription::filter;
+use crate::subscription::filter::{DownloadFilter, SubscriptionFilter, TitlePattern};
 
-use crate::models::DownloadGroup;
+pub(crate) mod health;
+mod v3;
+
+const DEFAULT_PAGE_SIZE: u32 = 25;
+const MAX_PAGE_SIZE: u32 = 100;
+const DEFAULT_SEARCH_PAGE_SIZE: u32 = 10;
+const MAX_SEARCH_PAGE_SIZE: u32 = 20;
 
 pub(crate) struct DownloadService {
-    pub(crate) sender: Sender<DownloadGroup>,
+    pub(crate) sender: Sender<DownloadEvent>,
+    pub(crate) pool: Option<DBPool>,
+    pub(crate) client: ReqwestClient,
+    pub(crate) client_buffer: usize,
+    pub(crate) rate_limits: RateLimits,
 }
 
-#[tonic::async_trait]
-impl proto::api::v2::downloads_server::Downloads for DownloadService {
-    type SubscribeStream = ReceiverStream<Result<proto::api::v2::DownloadCollection, Status>>;
-    async fn subscribe(
+impl DownloadService {
+    /// Builds the filter from the subscription parameters shared by all api versions.
+    async fn download_filter(
         &self,
-        request: tonic::Request<()>,
-    ) -> Result<tonic::Response<Self::SubscribeStream>, Status> {
-        let mut incoming = self.sender.subscribe();
-        let (tx, rx) = mpsc::channel(3);
+        title_patterns: &[String],
+        show_ids: &[u32],
+        variants: Vec<Variant>,
+        min_resolution: Option<u32>,
+        providers: &[String],
+    ) -> Result<DownloadFilter, Status> {
+        let min_resolution = min_resolution
+            .map(u16::try_from)
+            .transpose()
+            .map_err(|_| Status::invalid_argument("min_resolution is out of range"))?;
+        Ok(DownloadFilter {
+            titles: title_patterns
+                .iter()
+                .map(|pattern| TitlePattern::new(pattern))
+                .collect(),
+            show_titles: filter::show_titles(&self.client, show_ids).await.map_err(
+                |e| match e {
+                    kitsu::Error::Status(StatusCode::NOT_FOUND) => {
+                        Status::not_found("one of the requested shows does not exist")
+                    }
+                    e => Status::unavailable(e.to_string()),
+                },
+            )?,
+            variants,
+            min_resolution,
+            providers: providers.to_vec(),
+            ..DownloadFilter::default()
+        })
+    }
+
+    /// Opens a subscription of the client making `request`, if it is below its limit.
+    fn subscription_permit<T>(
+        &self,
+        request: &tonic::Request<T>,
+    ) -> Result<SubscriptionPermit, Status> {
+        let client = request
+            .extensions()
+            .get::<ClientId>()
+            .copied()
+            .unwrap_or(ClientId::Unknown);
+        self.rate_limits
+            .subscribe(client)
+            .ok_or_else(|| Status::resource_exhausted("too many open subscriptions"))
+    }
+
+    /// Streams the download events passing `filter` to the client, converted with `map`.
+    ///
+    /// The subscription stays counted against the client through `permit` until the stream ends.
+    fn subscription<T, F>(
+        &self,
+        permit: SubscriptionPermit,
+        remote_addr: Option<SocketAddr>,
+        since_sequence: Option<u64>,
+        mut filter: SubscriptionFilter,
+        map: F,
+    ) -> ReceiverStream<Result<T, Status>>
+    where
+        T: Send + 'static,
+        F: Fn(&DownloadFilter, DownloadEvent) -> T + Send + 'static,
+    {
+        let mut incoming = Box::pin(subscription::subscribe(
+            &self.sender,
+            self.pool.clone(),
+            since_sequence,
+        ));
+        let (tx, rx) = mpsc::channel(self.client_buffer);
         tokio::spawn(async move {
+            let _permit = permit;
             loop {
-                match incoming.recv().await {
-                    Ok(group) => {
-                        if tx.send(Ok(group.into())).await.is_err() {
-                            warn!(
-                                "failed to push downloads to client at {:?}",
-                                request.remote_addr()
-                            );
-                            break;
-                        }
-                    }
-                    Err(e) => {
-                        warn!("failed to receive new episode from shared sender: {e}");
-                        let message = Err(Status::unavailable(e.to_string()));
-                        if tx.send(message).await.is_err() {
-                            warn!(
-                                "failed to push error to client at {:?}",
-                                request.remote_addr()
-                            );
-                        }
-                        break;
-                    }
+                let event = tokio::select! {
+                    () = tx.closed() => break,
+                    event = incoming.next() => event,
+                };
+                let Some(event) = event else {
+                    debug!("download sender closed, ending stream for {remote_addr:?}");
+                    break;
+                };
+                let filter = filter.current().await;
+                let Some(event) = filter.apply(event) else {
+                    continue;
+                };
+                if tx.send(Ok(map(filter, event))).await.is_err() {
+                    warn!("failed to push downloads to client at {remote_addr:?}");
+                    break;
                 }
             }
         });
+        ReceiverStream::new(rx)
+    }
+
+    fn pool(&self) -> Result<DBPool, Status> {
+        pool(self.pool.as_ref())
+    }
+}
+
+pub(crate) struct ShowService {
+    pub(crate) pool: Option<DBPool>,
+    pub(crate) client: ReqwestClient,
+}
+
+fn pool(pool: Option<&DBPool>) -> Result<DBPool, Status> {
+    pool.cloned()
+        .ok_or_else(|| Status::unimplemented("download history is not available"))
+}
+
+async fn find_downloads(
+    pool: DBPool,
+    variant: Option<Variant>,
+    options: QueryOptions,
+) -> Result<Vec<DownloadGroup>, Status> {
+    repository::downloads::get_with_downloads(pool, variant, Some(options))
+        .await
+        .map_err(|e| {
+            error!(error = ?e, "failed to query downloads");
+            Status::internal("failed to query downloads")
+        })
+}
+
+/// A page of downloads, independent of the api version it is returned from.
+struct Page {
+    downloads: Vec<DownloadGroup>,
+    /// Empty when there are no further pages.
+    next_page_token: String,
+}
+
+impl From<Page> for ListDownloadsResponse {
+    fn from(value: Page) -> Self {
+        Self {
+            downloads: value.downloads.into_iter().map(Into::into).collect(),
+            next_page_token: value.next_page_token,
+        }
+    }
+}
+
+/// Loads a page of downloads matching `options`, continuing after `page_token` when set.
+async fn list_page(
+    pool: DBPool,
+    variant: Option<Variant>,
+    options: QueryOptions,
+    page_size: u32,
+    page_token: &str,
+) -> Result<Page, Status> {
+    let page_size = match page_size {
+        0 => DEFAULT_PAGE_SIZE,
+        size => size.min(MAX_PAGE_SIZE),
+    };
+    let after = Some(page_token)
+        .filter(|token| !token.is_empty())
+        .map(decode_page_token)
+        .transpose()?;
+    let options = QueryOptions {
+        after,
+        // one more than requested to find out whether there is a next page
+        limit: Some(page_size + 1),
+        ..options
+    };
+    let mut downloads = find_downloads(pool, variant, options).await?;
+    let mut next_page_token = String::new();
+    if downloads.len() > page_size as usize {
+        downloads.truncate(page_size as usize);
+        if let Some(group) = downloads.last()
+            && let Some(id) = group.id
+        {
+            next_page_token = encode_page_token(Cursor {
+                updated_at: group.updated_at,
+                id,
+            });
+        }
+    }
+    Ok(Page {
+        downloads,
+        next_page_token,
+    })
+}
+
+/// Loads a single download by the string form of its id.
+async fn get_download(pool: DBPool, id: &str) -> Result<DownloadGroup, Status> {
+    let id: Uuid = id
+        .parse()
+        .map_err(|_| Status::invalid_argument("id is not a valid uuid"))?;
+    let options = QueryOptions {
+        id: Some(id),
+        limit: Some(1),
+        ..QueryOptions::default()
+    };
+    find_downloads(pool, None, options)
+        .await?
+        .into_iter()
+        .next()
+        .ok_or_else(|| Status::not_found("download not found"))
+}
+
+fn kitsu_status(error: kitsu::Error) -> Status {
+    match error {
+        kitsu::Error::Status(StatusCode::NOT_FOUND) => Status::not_found("show not found"),
+        e => Status::unavailable(e.to_string()),
+    }
+}
+
+fn show(anime: kitsu::models::Anime) -> Result<proto::api::v2::Show, Status> {
+    let show: Show = anime
+        .try_into()
+        .map_err(|_| Status::internal("kitsu returned an invalid show id"))?;
+    Ok(show.into())
+}
+
+fn variant(kind: VariantKind) -> Option<Variant> {
+    match kind {
+        VariantKind::Unspecified => None,
+        VariantKind::Batch => Some(Variant::Batch),
+        VariantKind::Episode => Some(Variant::Episode),
+        VariantKind::Movie => Some(Variant::Movie),
+    }
+}
+
+fn encode_page_token(cursor: Cursor) -> String {
+    URL_SAFE_NO_PAD.encode(format!("{}|{}", cursor.updated_at.to_rfc3339(), cursor.id))
+}
+
+fn decode_page_token(token: &str) -> Result<Cursor, Status> {
+    let invalid = || Status::invalid_argument("invalid page_token");
+    let decoded = URL_SAFE_NO_PAD.decode(token).map_err(|_| invalid())?;
+    let decoded = String::from_utf8(decoded).map_err(|_| invalid())?;
+    let (updated_at, id) = decoded.split_once('|').ok_or_else(invalid)?;
+    Ok(Cursor {
+        updated_at: DateTime::parse_from_rfc3339(updated_at)
+            .map_err(|_| invalid())?
+            .to_utc(),
+        id: id.parse().map_err(|_| invalid())?,
+    })
+}
+
+#[tonic::async_trait]
+impl proto::api::v2::downloads_server::Downloads for DownloadService {
+    type SubscribeStream = ReceiverStream<Result<proto::api::v2::DownloadCollection, Status>>;
+    async fn subscribe(
+        &self,
+        request: tonic::Request<SubscribeRequest>,
+    ) -> Result<tonic::Response<Self::SubscribeStream>, Status> {
+        auth::authorize(&request, ApiKeyScope::Subscribe)?;
+        let permit = self.subscription_permit(&request)?;
+        let remote_addr = request.remote_addr();
+        let request = request.into_inner();
+        let filter = self
+            .download_filter(
+                &request.title_patterns,
+                &request.show_ids,
+                request.variants().filter_map(variant).collect(),
+                request.min_resolution,
+                &request.providers,
+            )
+            .await?;
+        let stream = self.subscription(
+            permit,
+            remote_addr,
+            request.since_sequence,
+            filter.into(),
+            |_, event| event.into(),
+        );
+        Ok(tonic::Response::new(stream))
+    }
+
+    async fn list_downloads(
+        &self,
+        request: tonic::Request<ListDownloadsRequest>,
+    ) -> Result<tonic::Response<ListDownloadsResponse>, Status> {
+        auth::authorize(&request, ApiKeyScope::Read)?;
+        let request = request.into_inner();
+        let options = QueryOptions {
+            title: request.title.clone(),
+            provider: request.provider.clone(),
+            ..QueryOptions::default()
+        };
+        let page = list_page(
+            self.pool()?,
+            variant(request.variant()),
+            options,
+            request.page_size,
+            &request.page_token,
+        )
+        .await?;
+        Ok(tonic::Response::new(page.into()))
+    }
+
+    async fn get_download(
+        &self,
+        request: tonic::Request<GetDownloadRequest>,
+    ) -> Result<tonic::Response<DownloadCollection>, Status> {
+        auth::authorize(&request, ApiKeyScope::Read)?;
+        let group = get_download(self.pool()?, &request.get_ref().id).await?;
+        Ok(tonic::Response::new(group.into()))
+    }
+}
+
+#[tonic::async_trait]
+impl proto::api::v2::shows_server::Shows for ShowService {
+    async fn get_show(
+        &self,
+        request: tonic::Request<GetShowRequest>,
+    ) -> Result<tonic::Response<proto::api::v2::Show>, Status> {
+        auth::authorize(&request, ApiKeyScope::Read)?;
+        let anime = kitsu::anime::single(&self.client, request.get_ref().id)
+            .await
+            .map_err(kitsu_status)?;
+        Ok(tonic::Response::new(show(anime.data)?))
+    }
+
+    async fn search_shows(
+        &self,
+        request: tonic::Request<SearchShowsRequest>,
+    ) -> Result<tonic::Response<SearchShowsResponse>, Status> {
+        auth::authorize(&request, ApiKeyScope::Read)?;
+        let request = request.into_inner();
+        if request.query.trim().is_empty() {
+            return Err(Status::invalid_argument("query must not be empty"));
+        }
+        let page_size = match request.page_size {
+            0 => DEFAULT_SEARCH_PAGE_SIZE,
+            size => size.min(MAX_SEARCH_PAGE_SIZE),
+        };
+        let anime = kitsu::anime::search(&self.client, &request.query, page_size)
+            .await
+            .map_err(kitsu_status)?;
+        let shows: Result<Vec<_>, _> = anime.data.into_iter().map(show).collect();
+        Ok(tonic::Response::new(SearchShowsResponse { shows: shows? }))
+    }
+
+    async fn list_show_downloads(
+        &self,
+        request: tonic::Request<ListShowDownloadsRequest>,
+    ) -> Result<tonic::Response<ListDownloadsResponse>, Status> {
+        auth::authorize(&request, ApiKeyScope::Read)?;
+        let request = request.into_inner();
+        let pool = pool(self.pool.as_ref())?;
+        let titles = filter::show_titles(&self.client, &[request.show_id])
+            .await
+            .map_err(kitsu_status)?;
+        let options = QueryOptions {
+            normalized_titles: titles
+                .iter()
+                .map(|title| title.normalized().to_string())
+                .collect(),
+            ..QueryOptions::default()
+        };
+        let page = list_page(
+            pool,
+            variant(request.variant()),
+            options,
+            request.page_size,
+            &request.page_token,
+        )
+        .await?;
+        Ok(tonic::Response::new(page.into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        Ok(tonic::Response::new(ReceiverStream::new(rx)))
+    #[test]
+    fn test_page_token_round_trip() {
+        let cursor = Cursor {
+            updated_at: DateTime::from_timestamp(1_700_000_000, 123_456_000).unwrap(),
+            id: Uuid::from_u128(0x1111),
+        };
+        let token = encode_page_token(cursor);
+        assert_eq!(decode_page_token(&token).unwrap(), cursor);
+        assert!(decode_page_token("not a token").is_err());
     }
 }
@@ -0,0 +1,69 @@
+use std::time::Duration;
+
+use sqlx::Connection;
+use tokio::time::{MissedTickBehavior, interval, timeout};
+use tonic_health::ServingStatus;
+use tonic_health::server::HealthReporter;
+use tracing::{debug, warn};
+
+use crate::jobs::poller::PollerHealth;
+use crate::state::DBPool;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(15);
+const DATABASE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Periodically reports the health of the database and the poller for the overall server, the
+/// empty service name, as well as for each of `services`.
+pub(crate) async fn monitor(
+    reporter: HealthReporter,
+    services: Vec<&'static str>,
+    pool: Option<DBPool>,
+    poller: PollerHealth,
+) {
+    let mut interval = interval(CHECK_INTERVAL);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut last_status = None;
+    loop {
+        interval.tick().await;
+        let database = match &pool {
+            Some(pool) => database_healthy(pool).await,
+            None => true,
+        };
+        let status = if database && poller.is_healthy() {
+            ServingStatus::Serving
+        } else {
+            ServingStatus::NotServing
+        };
+        if last_status != Some(status) {
+            debug!(
+                ?status,
+                database,
+                poller = poller.is_healthy(),
+                "health changed"
+            );
+        }
+        last_status = Some(status);
+        reporter.set_service_status("", status).await;
+        for service in &services {
+            reporter.set_service_status(service, status).await;
+        }
+    }
+}
+
+async fn database_healthy(pool: &DBPool) -> bool {
+    let ping = async {
+        let mut connection = pool.acquire().await?;
+        connection.ping().await
+    };
+    match timeout(DATABASE_TIMEOUT, ping).await {
+        Ok(Ok(())) => true,
+        Ok(Err(e)) => {
+            warn!(error = ?e, "database health check failed");
+            false
+        }
+        Err(_) => {
+            warn!("database health check timed out");
+            false
+        }
+    }
+}
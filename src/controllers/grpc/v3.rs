@@ -0,0 +1,140 @@
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::Status;
+use tracing::error;
+
+use proto::api::v3::{
+    DownloadCollection, GetDownloadRequest, ListDownloadsRequest, ListDownloadsResponse,
+    SubscribeRequest, VariantKind,
+};
+
+use crate::auth;
+use crate::datasource::repository;
+use crate::datasource::repository::api_keys::ApiKeyScope;
+use crate::datasource::repository::downloads::{QueryOptions, Variant};
+use crate::subscription::filter;
+use crate::subscription::filter::SubscriptionFilter;
+
+use super::{DownloadService, Page, get_download, kitsu_status, list_page};
+
+impl From<Page> for ListDownloadsResponse {
+    fn from(value: Page) -> Self {
+        Self {
+            downloads: value.downloads.into_iter().map(Into::into).collect(),
+            next_page_token: value.next_page_token,
+        }
+    }
+}
+
+fn variant(kind: VariantKind) -> Option<Variant> {
+    match kind {
+        VariantKind::Unspecified => None,
+        VariantKind::Batch => Some(Variant::Batch),
+        VariantKind::Episode => Some(Variant::Episode),
+        VariantKind::Movie => Some(Variant::Movie),
+    }
+}
+
+#[tonic::async_trait]
+impl proto::api::v3::downloads_server::Downloads for DownloadService {
+    type SubscribeStream = ReceiverStream<Result<DownloadCollection, Status>>;
+    async fn subscribe(
+        &self,
+        request: tonic::Request<SubscribeRequest>,
+    ) -> Result<tonic::Response<Self::SubscribeStream>, Status> {
+        auth::authorize(&request, ApiKeyScope::Subscribe)?;
+        // the watchlist belongs to the calling key
+        let api_key_id = if request.get_ref().watchlist {
+            Some(auth::key_id(&request)?)
+        } else {
+            None
+        };
+        let permit = self.subscription_permit(&request)?;
+        let remote_addr = request.remote_addr();
+        let request = request.into_inner();
+        let filter = self
+            .download_filter(
+                &request.title_patterns,
+                &request.show_ids,
+                request.variants().filter_map(variant).collect(),
+                request.min_resolution,
+                &request.providers,
+            )
+            .await?;
+        let filter = if let Some(api_key_id) = api_key_id {
+            let pool = self.pool()?;
+            let entries = repository::watchlist::all(&pool, api_key_id)
+                .await
+                .map_err(|e| {
+                    error!(error = ?e, "failed to query the watchlist");
+                    Status::internal("failed to query the watchlist")
+                })?;
+            let titles = filter::watchlist_titles(&self.client, &entries)
+                .await
+                .map_err(kitsu_status)?;
+            SubscriptionFilter::watchlist(filter, titles, api_key_id, pool, self.client.clone())
+        } else {
+            filter.into()
+        };
+        let stream = self.subscription(
+            permit,
+            remote_addr,
+            request.since_sequence,
+            filter,
+            |filter, event| {
+                let show_id = filter.show_id(&event.group.title);
+                DownloadCollection {
+                    show_id,
+                    ..event.into()
+                }
+            },
+        );
+        Ok(tonic::Response::new(stream))
+    }
+
+    async fn list_downloads(
+        &self,
+        request: tonic::Request<ListDownloadsRequest>,
+    ) -> Result<tonic::Response<ListDownloadsResponse>, Status> {
+        auth::authorize(&request, ApiKeyScope::Read)?;
+        let request = request.into_inner();
+        let pool = self.pool()?;
+        let normalized_titles = match request.show_id {
+            Some(show_id) => filter::show_titles(&self.client, &[show_id])
+                .await
+                .map_err(kitsu_status)?
+                .iter()
+                .map(|title| title.normalized().to_string())
+                .collect(),
+            None => Vec::new(),
+        };
+        let options = QueryOptions {
+            title: request.title.clone(),
+            provider: request.provider.clone(),
+            normalized_titles,
+            latest_versions: request.latest_versions,
+            ..QueryOptions::default()
+        };
+        let page = list_page(
+            pool,
+            variant(request.variant()),
+            options,
+            request.page_size,
+            &request.page_token,
+        )
+        .await?;
+        let mut response = ListDownloadsResponse::from(page);
+        for download in &mut response.downloads {
+            download.show_id = request.show_id;
+        }
+        Ok(tonic::Response::new(response))
+    }
+
+    async fn get_download(
+        &self,
+        request: tonic::Request<GetDownloadRequest>,
+    ) -> Result<tonic::Response<DownloadCollection>, Status> {
+        auth::authorize(&request, ApiKeyScope::Read)?;
+        let group = get_download(self.pool()?, &request.get_ref().id).await?;
+        Ok(tonic::Response::new(group.into()))
+    }
+}
@@ -0,0 +1,136 @@
+//! Renders episode releases as an iCalendar, see <https://www.rfc-editor.org/rfc/rfc5545>.
+use chrono::{DateTime, Utc};
+
+use crate::schedule::Release;
+use crate::subscription::filter::ShowTitle;
+
+pub(crate) const CALENDAR_TYPE: &str = "text/calendar; charset=utf-8";
+/// Lines longer than this many octets are folded onto the next line.
+const MAX_LINE_LENGTH: usize = 75;
+const EVENT_DURATION: &str = "PT30M";
+
+/// A calendar with an event for every episode that was released or is expected to be.
+///
+/// Events of the same episode share their uid, so calendar apps replace an expected episode with
+/// the actual release once it is downloaded.
+pub(crate) fn calendar(
+    name: &str,
+    released: &[Release],
+    expected: &[Release],
+    now: DateTime<Utc>,
+) -> String {
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        "PRODID:-//anime-service//releases//EN".to_string(),
+        "CALSCALE:GREGORIAN".to_string(),
+        format!("X-WR-CALNAME:{}", escape(name)),
+    ];
+    let events = released
+        .iter()
+        .map(|release| (release, false))
+        .chain(expected.iter().map(|release| (release, true)));
+    for (release, is_expected) in events {
+        lines.extend([
+            "BEGIN:VEVENT".to_string(),
+            format!(
+                "UID:{}-{}@anime-service",
+                ShowTitle::new(&release.title).normalized(),
+                release.episode
+            ),
+            format!("DTSTAMP:{}", timestamp(now)),
+            format!("DTSTART:{}", timestamp(release.released_at)),
+            format!("DURATION:{EVENT_DURATION}"),
+            format!(
+                "SUMMARY:{}",
+                escape(&format!("{} - Episode {}", release.title, release.episode))
+            ),
+            "TRANSP:TRANSPARENT".to_string(),
+        ]);
+        if is_expected {
+            lines.extend([
+                "STATUS:TENTATIVE".to_string(),
+                "DESCRIPTION:Expected from the weekly release schedule".to_string(),
+            ]);
+        } else {
+            lines.push("STATUS:CONFIRMED".to_string());
+        }
+        lines.push("END:VEVENT".to_string());
+    }
+    lines.push("END:VCALENDAR".to_string());
+    lines.iter().map(|line| fold(line) + "\r\n").collect()
+}
+
+fn timestamp(date: DateTime<Utc>) -> String {
+    date.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+/// Splits a content line into lines of at most 75 octets, continuing on lines starting with a space.
+fn fold(line: &str) -> String {
+    let mut folded = String::with_capacity(line.len());
+    let mut length = 0;
+    for c in line.chars() {
+        if length + c.len_utf8() > MAX_LINE_LENGTH {
+            folded.push_str("\r\n ");
+            length = 1;
+        }
+        folded.push(c);
+        length += c.len_utf8();
+    }
+    folded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(
+            escape("Re:Zero; Season 2, Part\\2"),
+            "Re:Zero\\; Season 2\\, Part\\\\2"
+        );
+    }
+
+    #[test]
+    fn test_fold() {
+        let line = format!("SUMMARY:{}", "é".repeat(40));
+        let folded = fold(&line);
+        assert!(
+            folded
+                .split("\r\n")
+                .all(|line| line.len() <= MAX_LINE_LENGTH)
+        );
+        assert_eq!(folded.replace("\r\n ", ""), line);
+    }
+
+    #[test]
+    fn test_calendar() {
+        let release = |episode, timestamp| Release {
+            title: "Sousou no Frieren".to_string(),
+            episode,
+            released_at: DateTime::from_timestamp(timestamp, 0).unwrap(),
+        };
+        let now = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let calendar = calendar(
+            "Frieren releases",
+            &[release(7, 1_699_900_000)],
+            &[release(8, 1_700_504_800)],
+            now,
+        );
+        assert!(calendar.starts_with("BEGIN:VCALENDAR\r\nVERSION:2.0\r\n"));
+        assert!(calendar.ends_with("END:VCALENDAR\r\n"));
+        assert_eq!(calendar.matches("BEGIN:VEVENT").count(), 2);
+        assert!(calendar.contains("UID:sousounofrieren-8@anime-service\r\n"));
+        assert!(calendar.contains("DTSTART:20231113T182640Z\r\n"));
+        assert!(calendar.contains("SUMMARY:Sousou no Frieren - Episode 7\r\n"));
+        assert_eq!(calendar.matches("STATUS:TENTATIVE").count(), 1);
+    }
+}
@@ -0,0 +1,141 @@
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+use crate::auth::API_KEY_PARAM;
+use crate::controllers::rest::{
+    anime, api_keys, batch, calendar, downloads, episode, movie, schedules, watchlist, webhooks,
+};
+
+/// The `OpenAPI` document of the REST endpoints, served at `/v1/openapi.json`.
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "anime-service",
+        description = "Anime shows from Kitsu and the downloads tracked for them"
+    ),
+    paths(
+        anime::find,
+        anime::by_id,
+        downloads::find_downloads,
+        downloads::rss_feed,
+        downloads::atom_feed,
+        downloads::get_downloads_events,
+        downloads::by_id,
+        downloads::gaps,
+        downloads::by_show,
+        batch::find_downloads,
+        batch::rss_feed,
+        batch::atom_feed,
+        batch::get_downloads_events,
+        episode::find_downloads,
+        episode::rss_feed,
+        episode::atom_feed,
+        episode::get_downloads_events,
+        movie::find_downloads,
+        movie::rss_feed,
+        movie::atom_feed,
+        movie::get_downloads_events,
+        calendar::calendar,
+        schedules::this_week,
+        schedules::by_title,
+        webhooks::find,
+        webhooks::create,
+        webhooks::by_id,
+        webhooks::update,
+        webhooks::delete,
+        webhooks::deliveries,
+        watchlist::find,
+        watchlist::create,
+        watchlist::by_id,
+        watchlist::update,
+        watchlist::delete,
+        watchlist::downloads,
+        watchlist::get_downloads_events,
+        api_keys::find,
+        api_keys::create,
+        api_keys::by_id,
+        api_keys::delete,
+    ),
+    modifiers(&SecurityAddon),
+    security(("bearer" = []), ("api_key" = []), ("api_key_query" = [])),
+    tags(
+        (name = "shows", description = "Shows as known by Kitsu"),
+        (name = "downloads", description = "Tracked downloads and their updates"),
+        (name = "calendar", description = "Release schedules of the tracked shows"),
+        (name = "webhooks", description = "Outgoing webhooks for new downloads"),
+        (name = "watchlist", description = "Shows followed by each api key and their downloads"),
+        (name = "keys", description = "Api keys and the scopes they grant"),
+    )
+)]
+pub(crate) struct ApiDoc;
+
+/// Documents the ways an api key can be passed.
+struct SecurityAddon;
+
+impl Modify for SecurityAddon {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("x-api-key"))),
+        );
+        components.add_security_scheme(
+            "api_key_query",
+            SecurityScheme::ApiKey(ApiKey::Query(ApiKeyValue::new(API_KEY_PARAM))),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use serde_json::Value;
+
+    use super::*;
+
+    #[test]
+    fn test_download_variant_is_flattened_into_group() {
+        let doc = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        let schemas = &doc["components"]["schemas"];
+        assert!(
+            schemas["DownloadGroup"]["allOf"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .any(|schema| schema["$ref"] == "#/components/schemas/DownloadVariant")
+        );
+        let variant_tag = |schema: &Value| {
+            schema["allOf"]
+                .as_array()
+                .map_or(schema, |all_of| &all_of[1])
+                .pointer("/properties/variant/enum/0")
+                .cloned()
+        };
+        let tags: Vec<_> = schemas["DownloadVariant"]["oneOf"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(variant_tag)
+            .collect();
+        assert_eq!(tags, ["batch", "episode", "movie"]);
+    }
+
+    #[test]
+    fn test_operation_ids_are_unique() {
+        let doc = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        let operations: Vec<_> = doc["paths"]
+            .as_object()
+            .unwrap()
+            .values()
+            .flat_map(|item| item.as_object().unwrap().values())
+            .map(|operation| operation["operationId"].as_str().unwrap())
+            .collect();
+        let unique: HashSet<_> = operations.iter().collect();
+        assert_eq!(unique.len(), operations.len());
+    }
+}
@@ -1,14 +1,81 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+
+use ahash::RandomState;
+use async_stream::try_stream;
+use axum::http::header::CONTENT_TYPE;
+use axum::http::{HeaderMap, Uri};
+use axum::response::sse::{Event, KeepAlive};
+use axum::response::{IntoResponse, Response, Sse};
+use futures::{Stream, StreamExt};
 use serde::Deserialize;
+use tracing::error;
+use utoipa::IntoParams;
 
+use crate::controllers::feed;
+use crate::controllers::feed::{EnclosureLink, FeedInfo};
 use crate::datasource::repository;
 use crate::datasource::repository::downloads::{QueryOptions, Variant};
 use crate::errors::Error;
-use crate::models::DownloadGroup;
-use crate::state::DBPool;
+use crate::models::{DownloadEvent, DownloadGroup, DownloadLinks, download_link};
+use crate::rate_limit::ClientId;
+use crate::state::{AppState, DBPool};
+use crate::subscription;
+use crate::subscription::filter::{DownloadFilter, SubscriptionFilter, TitlePattern};
+
+const LAST_EVENT_ID_HEADER: &str = "last-event-id";
+const RSS_TYPE: &str = "application/rss+xml; charset=utf-8";
+const ATOM_TYPE: &str = "application/atom+xml; charset=utf-8";
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub(crate) struct DownloadQuery {
+    /// Case-insensitive prefix of the title.
+    title: Option<String>,
+    /// Leave out episodes of which a newer version was released by the same provider.
+    #[serde(default)]
+    latest_versions: bool,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct DownloadEventsQuery {
+    /// Case-insensitive prefix of the title.
+    title: Option<String>,
+    /// Only send downloads of exactly this resolution.
+    resolution: Option<u16>,
+    /// Only send downloads of this provider, compared case-insensitively.
+    provider: Option<String>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct FeedQuery {
+    /// Case-insensitive prefix of the title.
     title: Option<String>,
+    /// Leave out episodes of which a newer version was released by the same provider.
+    #[serde(default)]
+    latest_versions: bool,
+    /// What the enclosures of the items link to.
+    #[serde(default)]
+    #[param(inline)]
+    enclosure: EnclosureLink,
+}
+
+impl DownloadEventsQuery {
+    fn into_filter(self, variant: Option<Variant>) -> DownloadFilter {
+        DownloadFilter {
+            titles: self
+                .title
+                .map(|title| TitlePattern::prefix(&title))
+                .into_iter()
+                .collect(),
+            variants: variant.into_iter().collect(),
+            resolution: self.resolution,
+            providers: self.provider.into_iter().collect(),
+            ..DownloadFilter::default()
+        }
+    }
 }
 
 async fn find_downloads(
@@ -18,18 +85,150 @@ async fn find_downloads(
 ) -> Result<Vec<DownloadGroup>, Error> {
     let options = QueryOptions {
         title: params.title,
+        latest_versions: params.latest_versions,
+        ..QueryOptions::default()
     };
     let downloads = repository::downloads::get_with_downloads(pool, variant, Some(options)).await?;
     Ok(downloads)
 }
 
+/// Links every group to itself and the batches or episodes it overlaps with.
+async fn with_links(
+    pool: &DBPool,
+    mut groups: Vec<DownloadGroup>,
+) -> Result<Vec<DownloadGroup>, Error> {
+    let ids: Vec<_> = groups.iter().filter_map(|group| group.id).collect();
+    let mut links: HashMap<_, _, RandomState> =
+        ids.iter().map(|&id| (id, DownloadLinks::new(id))).collect();
+    for related in repository::downloads::related(pool, &ids).await? {
+        let Some(links) = links.get_mut(&related.download_id) else {
+            continue;
+        };
+        let link = download_link(related.related_id);
+        match related.variant {
+            Variant::Batch => links.batches.push(link),
+            Variant::Episode | Variant::Movie => links.episodes.push(link),
+        }
+    }
+    for group in &mut groups {
+        group.links = group.id.and_then(|id| links.remove(&id));
+    }
+    Ok(groups)
+}
+
+/// Loads the same downloads as the JSON listing and describes the feed they are served in.
+async fn feed_downloads(
+    params: FeedQuery,
+    pool: DBPool,
+    headers: &HeaderMap,
+    uri: &Uri,
+    variant: Option<Variant>,
+) -> Result<(FeedInfo, Vec<DownloadGroup>), Error> {
+    let title = match variant {
+        None => "Downloads",
+        Some(Variant::Batch) => "Batch downloads",
+        Some(Variant::Episode) => "Episode downloads",
+        Some(Variant::Movie) => "Movie downloads",
+    };
+    let info = FeedInfo::new(title.to_string(), headers, uri, params.enclosure);
+    let query = DownloadQuery {
+        title: params.title,
+        latest_versions: params.latest_versions,
+    };
+    let downloads = find_downloads(query, pool, variant).await?;
+    Ok((info, downloads))
+}
+
+async fn rss_feed(
+    params: FeedQuery,
+    pool: DBPool,
+    headers: &HeaderMap,
+    uri: &Uri,
+    variant: Option<Variant>,
+) -> Result<Response, Error> {
+    let (info, downloads) = feed_downloads(params, pool, headers, uri, variant).await?;
+    let channel = feed::rss(&info, &downloads);
+    Ok(([(CONTENT_TYPE, RSS_TYPE)], channel.to_string()).into_response())
+}
+
+async fn atom_feed(
+    params: FeedQuery,
+    pool: DBPool,
+    headers: &HeaderMap,
+    uri: &Uri,
+    variant: Option<Variant>,
+) -> Result<Response, Error> {
+    let (info, downloads) = feed_downloads(params, pool, headers, uri, variant).await?;
+    let feed = feed::atom(&info, &downloads);
+    Ok(([(CONTENT_TYPE, ATOM_TYPE)], feed.to_string()).into_response())
+}
+
+fn get_downloads_events(
+    state: AppState,
+    headers: &HeaderMap,
+    client: ClientId,
+    mut filter: SubscriptionFilter,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>> + use<>>, Error> {
+    let permit = state
+        .rate_limits
+        .subscribe(client)
+        .ok_or(Error::TooManySubscriptions)?;
+    let mut events = Box::pin(subscription::subscribe(
+        &state.downloads_channel,
+        Some(state.pool),
+        last_event_id(headers),
+    ));
+    let stream = try_stream! {
+        // released once the client disconnects and the stream is dropped
+        let _permit = permit;
+        while let Some(i) = events.next().await {
+            let Some(i) = filter.current().await.apply(i) else {
+                continue;
+            };
+            match download_event(i) {
+                Ok(event) => yield event,
+                Err(e) => error!(error = ?e, "failed to serialize"),
+            }
+        }
+    };
+    Ok(Sse::new(stream).keep_alive(KeepAlive::new()))
+}
+
+fn last_event_id(headers: &HeaderMap) -> Option<u64> {
+    headers
+        .get(LAST_EVENT_ID_HEADER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+fn download_event(event: DownloadEvent) -> Result<Event, axum::Error> {
+    Event::default()
+        .event(event.kind.name())
+        .id(event.sequence.to_string())
+        .json_data(event.group)
+}
+
 pub(crate) mod anime {
-    use crate::errors::Error;
+    use crate::errors::{Error, ErrorResponse};
     use crate::models;
     use crate::state::ReqwestClient;
     use axum::Json;
     use axum::extract::{Path, State};
 
+    #[utoipa::path(
+        get,
+        path = "/v1/shows/{id}",
+        operation_id = "get_show",
+        tag = "shows",
+        params(("id" = u32, Path, description = "Kitsu id of the show")),
+        responses(
+            (status = 200, body = models::Show),
+            (status = 404, body = ErrorResponse),
+        ),
+    )]
     pub(crate) async fn by_id(
         Path(id): Path<u32>,
         State(reqwest): State<ReqwestClient>,
@@ -39,6 +238,13 @@ pub(crate) mod anime {
         Ok(Json(show))
     }
 
+    #[utoipa::path(
+        get,
+        path = "/v1/shows",
+        operation_id = "list_shows",
+        tag = "shows",
+        responses((status = 200, body = Vec<models::Show>)),
+    )]
     pub(crate) async fn find(
         State(reqwest): State<ReqwestClient>,
     ) -> Result<Json<Vec<models::Show>>, Error> {
@@ -51,182 +257,1306 @@ pub(crate) mod anime {
 pub(crate) mod batch {
     use std::convert::Infallible;
 
-    use async_stream::try_stream;
+    use axum::Extension;
     use axum::Json;
-    use axum::extract::{Query, State};
-    use axum::response::Sse;
-    use axum::response::sse::{Event, KeepAlive};
+    use axum::extract::{OriginalUri, Query, State};
+    use axum::http::HeaderMap;
+    use axum::response::sse::Event;
+    use axum::response::{Response, Sse};
     use futures::Stream;
-    use tracing::error;
 
-    use crate::controllers::rest::DownloadQuery;
+    use crate::controllers::rest::{DownloadEventsQuery, DownloadQuery, FeedQuery};
     use crate::datasource::repository::downloads::Variant;
     use crate::errors::Error;
-    use crate::models::{DownloadGroup, DownloadVariant};
+    use crate::models::DownloadGroup;
+    use crate::rate_limit::ClientId;
     use crate::state::{AppState, DBPool};
 
+    #[utoipa::path(
+        get,
+        path = "/v1/downloads/batches",
+        operation_id = "list_batches",
+        tag = "downloads",
+        params(DownloadQuery),
+        responses((status = 200, description = "The most recently updated batch downloads", body = Vec<DownloadGroup>)),
+    )]
     pub(crate) async fn find_downloads(
         Query(params): Query<DownloadQuery>,
         State(pool): State<DBPool>,
     ) -> Result<Json<Vec<DownloadGroup>>, Error> {
-        let downloads = super::find_downloads(params, pool, Some(Variant::Batch)).await?;
-        Ok(Json(downloads))
+        let downloads = super::find_downloads(params, pool.clone(), Some(Variant::Batch)).await?;
+        Ok(Json(super::with_links(&pool, downloads).await?))
+    }
+
+    #[utoipa::path(
+        get,
+        path = "/v1/downloads/batches/feed.rss",
+        operation_id = "rss_batches",
+        tag = "downloads",
+        params(FeedQuery),
+        responses((
+            status = 200,
+            description = "RSS feed of the most recently published batch downloads",
+            content_type = "application/rss+xml",
+            body = String,
+        )),
+    )]
+    pub(crate) async fn rss_feed(
+        Query(params): Query<FeedQuery>,
+        State(pool): State<DBPool>,
+        OriginalUri(uri): OriginalUri,
+        headers: HeaderMap,
+    ) -> Result<Response, Error> {
+        super::rss_feed(params, pool, &headers, &uri, Some(Variant::Batch)).await
     }
 
+    #[utoipa::path(
+        get,
+        path = "/v1/downloads/batches/feed.atom",
+        operation_id = "atom_batches",
+        tag = "downloads",
+        params(FeedQuery),
+        responses((
+            status = 200,
+            description = "Atom feed of the most recently published batch downloads",
+            content_type = "application/atom+xml",
+            body = String,
+        )),
+    )]
+    pub(crate) async fn atom_feed(
+        Query(params): Query<FeedQuery>,
+        State(pool): State<DBPool>,
+        OriginalUri(uri): OriginalUri,
+        headers: HeaderMap,
+    ) -> Result<Response, Error> {
+        super::atom_feed(params, pool, &headers, &uri, Some(Variant::Batch)).await
+    }
+
+    #[utoipa::path(
+        get,
+        path = "/v1/downloads/batches/updates",
+        operation_id = "stream_batches",
+        tag = "downloads",
+        params(
+            DownloadEventsQuery,
+            ("last-event-id" = Option<u64>, Header, description = "Resume after this event"),
+        ),
+        responses((
+            status = 200,
+            description = "Server-sent `download` events of new batch downloads, or `revised` ones for new versions of episodes",
+            content_type = "text/event-stream",
+            body = DownloadGroup,
+        )),
+    )]
     pub(crate) async fn get_downloads_events(
         State(state): State<AppState>,
-    ) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
-        let mut rx = state.downloads_channel.subscribe();
-        let stream = try_stream! {
-            loop {
-                match rx.recv().await {
-                    Ok(i) => if let DownloadVariant::Batch(_) = i.variant {
-                        match Event::default().event("download").json_data(i) {
-                            Ok(event) => yield  event,
-                            Err(e) => error!(error = ?e, "failed to serialize"),
-                        }
-                    }
-                    Err(e) => error!(error = ?e, "sender closed"),
-                }
-            }
-        };
-        Sse::new(stream).keep_alive(KeepAlive::new())
+        Extension(client): Extension<ClientId>,
+        Query(params): Query<DownloadEventsQuery>,
+        headers: HeaderMap,
+    ) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, Error> {
+        super::get_downloads_events(
+            state,
+            &headers,
+            client,
+            params.into_filter(Some(Variant::Batch)).into(),
+        )
     }
 }
 
 pub(crate) mod episode {
     use std::convert::Infallible;
 
-    use async_stream::try_stream;
+    use axum::Extension;
     use axum::Json;
-    use axum::extract::{Query, State};
-    use axum::response::Sse;
-    use axum::response::sse::{Event, KeepAlive};
+    use axum::extract::{OriginalUri, Query, State};
+    use axum::http::HeaderMap;
+    use axum::response::sse::Event;
+    use axum::response::{Response, Sse};
     use futures::Stream;
-    use tracing::error;
 
-    use crate::controllers::rest::DownloadQuery;
+    use crate::controllers::rest::{DownloadEventsQuery, DownloadQuery, FeedQuery};
     use crate::datasource::repository::downloads::Variant;
     use crate::errors::Error;
-    use crate::models::{DownloadGroup, DownloadVariant};
+    use crate::models::DownloadGroup;
+    use crate::rate_limit::ClientId;
     use crate::state::{AppState, DBPool};
 
+    #[utoipa::path(
+        get,
+        path = "/v1/downloads/episodes",
+        operation_id = "list_episodes",
+        tag = "downloads",
+        params(DownloadQuery),
+        responses((status = 200, description = "The most recently updated episode downloads", body = Vec<DownloadGroup>)),
+    )]
     pub(crate) async fn find_downloads(
         Query(params): Query<DownloadQuery>,
         State(pool): State<DBPool>,
     ) -> Result<Json<Vec<DownloadGroup>>, Error> {
-        let downloads = super::find_downloads(params, pool, Some(Variant::Episode)).await?;
-        Ok(Json(downloads))
+        let downloads = super::find_downloads(params, pool.clone(), Some(Variant::Episode)).await?;
+        Ok(Json(super::with_links(&pool, downloads).await?))
     }
 
+    #[utoipa::path(
+        get,
+        path = "/v1/downloads/episodes/feed.rss",
+        operation_id = "rss_episodes",
+        tag = "downloads",
+        params(FeedQuery),
+        responses((
+            status = 200,
+            description = "RSS feed of the most recently published episode downloads",
+            content_type = "application/rss+xml",
+            body = String,
+        )),
+    )]
+    pub(crate) async fn rss_feed(
+        Query(params): Query<FeedQuery>,
+        State(pool): State<DBPool>,
+        OriginalUri(uri): OriginalUri,
+        headers: HeaderMap,
+    ) -> Result<Response, Error> {
+        super::rss_feed(params, pool, &headers, &uri, Some(Variant::Episode)).await
+    }
+
+    #[utoipa::path(
+        get,
+        path = "/v1/downloads/episodes/feed.atom",
+        operation_id = "atom_episodes",
+        tag = "downloads",
+        params(FeedQuery),
+        responses((
+            status = 200,
+            description = "Atom feed of the most recently published episode downloads",
+            content_type = "application/atom+xml",
+            body = String,
+        )),
+    )]
+    pub(crate) async fn atom_feed(
+        Query(params): Query<FeedQuery>,
+        State(pool): State<DBPool>,
+        OriginalUri(uri): OriginalUri,
+        headers: HeaderMap,
+    ) -> Result<Response, Error> {
+        super::atom_feed(params, pool, &headers, &uri, Some(Variant::Episode)).await
+    }
+
+    #[utoipa::path(
+        get,
+        path = "/v1/downloads/episodes/updates",
+        operation_id = "stream_episodes",
+        tag = "downloads",
+        params(
+            DownloadEventsQuery,
+            ("last-event-id" = Option<u64>, Header, description = "Resume after this event"),
+        ),
+        responses((
+            status = 200,
+            description = "Server-sent `download` events of new episode downloads, or `revised` ones for new versions of episodes",
+            content_type = "text/event-stream",
+            body = DownloadGroup,
+        )),
+    )]
     pub(crate) async fn get_downloads_events(
         State(state): State<AppState>,
-    ) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
-        let mut rx = state.downloads_channel.subscribe();
-        let stream = try_stream! {
-            loop {
-                match rx.recv().await {
-                    Ok(i) => if let DownloadVariant::Episode(_) = i.variant { match Event::default().event("download").json_data(i) {
-                        Ok(event) => yield  event,
-                        Err(e) => error!(error = ?e, "failed to serialize"),
-                    } }
-                    Err(e) => error!(error = ?e, "sender closed"),
-                }
-            }
-        };
-        Sse::new(stream).keep_alive(KeepAlive::new())
+        Extension(client): Extension<ClientId>,
+        Query(params): Query<DownloadEventsQuery>,
+        headers: HeaderMap,
+    ) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, Error> {
+        super::get_downloads_events(
+            state,
+            &headers,
+            client,
+            params.into_filter(Some(Variant::Episode)).into(),
+        )
     }
 }
 
 pub(crate) mod movie {
     use std::convert::Infallible;
 
-    use async_stream::try_stream;
+    use axum::Extension;
     use axum::Json;
-    use axum::extract::{Query, State};
-    use axum::response::Sse;
-    use axum::response::sse::{Event, KeepAlive};
+    use axum::extract::{OriginalUri, Query, State};
+    use axum::http::HeaderMap;
+    use axum::response::sse::Event;
+    use axum::response::{Response, Sse};
     use futures::Stream;
-    use tracing::error;
 
-    use crate::controllers::rest::DownloadQuery;
+    use crate::controllers::rest::{DownloadEventsQuery, DownloadQuery, FeedQuery};
     use crate::datasource::repository::downloads::Variant;
     use crate::errors::Error;
-    use crate::models::{DownloadGroup, DownloadVariant};
+    use crate::models::DownloadGroup;
+    use crate::rate_limit::ClientId;
     use crate::state::{AppState, DBPool};
 
+    #[utoipa::path(
+        get,
+        path = "/v1/downloads/movies",
+        operation_id = "list_movies",
+        tag = "downloads",
+        params(DownloadQuery),
+        responses((status = 200, description = "The most recently updated movie downloads", body = Vec<DownloadGroup>)),
+    )]
     pub(crate) async fn find_downloads(
         Query(params): Query<DownloadQuery>,
         State(pool): State<DBPool>,
     ) -> Result<Json<Vec<DownloadGroup>>, Error> {
-        let downloads = super::find_downloads(params, pool, Some(Variant::Movie)).await?;
-        Ok(Json(downloads))
+        let downloads = super::find_downloads(params, pool.clone(), Some(Variant::Movie)).await?;
+        Ok(Json(super::with_links(&pool, downloads).await?))
+    }
+
+    #[utoipa::path(
+        get,
+        path = "/v1/downloads/movies/feed.rss",
+        operation_id = "rss_movies",
+        tag = "downloads",
+        params(FeedQuery),
+        responses((
+            status = 200,
+            description = "RSS feed of the most recently published movie downloads",
+            content_type = "application/rss+xml",
+            body = String,
+        )),
+    )]
+    pub(crate) async fn rss_feed(
+        Query(params): Query<FeedQuery>,
+        State(pool): State<DBPool>,
+        OriginalUri(uri): OriginalUri,
+        headers: HeaderMap,
+    ) -> Result<Response, Error> {
+        super::rss_feed(params, pool, &headers, &uri, Some(Variant::Movie)).await
+    }
+
+    #[utoipa::path(
+        get,
+        path = "/v1/downloads/movies/feed.atom",
+        operation_id = "atom_movies",
+        tag = "downloads",
+        params(FeedQuery),
+        responses((
+            status = 200,
+            description = "Atom feed of the most recently published movie downloads",
+            content_type = "application/atom+xml",
+            body = String,
+        )),
+    )]
+    pub(crate) async fn atom_feed(
+        Query(params): Query<FeedQuery>,
+        State(pool): State<DBPool>,
+        OriginalUri(uri): OriginalUri,
+        headers: HeaderMap,
+    ) -> Result<Response, Error> {
+        super::atom_feed(params, pool, &headers, &uri, Some(Variant::Movie)).await
     }
 
+    #[utoipa::path(
+        get,
+        path = "/v1/downloads/movies/updates",
+        operation_id = "stream_movies",
+        tag = "downloads",
+        params(
+            DownloadEventsQuery,
+            ("last-event-id" = Option<u64>, Header, description = "Resume after this event"),
+        ),
+        responses((
+            status = 200,
+            description = "Server-sent `download` events of new movie downloads, or `revised` ones for new versions of episodes",
+            content_type = "text/event-stream",
+            body = DownloadGroup,
+        )),
+    )]
     pub(crate) async fn get_downloads_events(
         State(state): State<AppState>,
-    ) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
-        let mut rx = state.downloads_channel.subscribe();
-        let stream = try_stream! {
-            loop {
-                match rx.recv().await {
-                    Ok(i) => if let DownloadVariant::Movie = i.variant { match Event::default().event("download").json_data(i) {
-                        Ok(event) => yield  event,
-                        Err(e) => error!(error = ?e, "failed to serialize"),
-                    } }
-                    Err(e) => error!(error = ?e, "sender closed"),
-                }
-            }
-        };
-        Sse::new(stream).keep_alive(KeepAlive::new())
+        Extension(client): Extension<ClientId>,
+        Query(params): Query<DownloadEventsQuery>,
+        headers: HeaderMap,
+    ) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, Error> {
+        super::get_downloads_events(
+            state,
+            &headers,
+            client,
+            params.into_filter(Some(Variant::Movie)).into(),
+        )
     }
 }
 
 pub mod downloads {
     use std::convert::Infallible;
 
-    use async_stream::try_stream;
+    use axum::Extension;
     use axum::Json;
-    use axum::extract::{Query, State};
-    use axum::response::Sse;
-    use axum::response::sse::{Event, KeepAlive};
+    use axum::extract::{OriginalUri, Path, Query, State};
+    use axum::http::HeaderMap;
+    use axum::response::sse::Event;
+    use axum::response::{Response, Sse};
     use futures::Stream;
-    use tracing::error;
+    use serde::Deserialize;
+    use utoipa::IntoParams;
+    use uuid::Uuid;
 
-    use crate::controllers::rest::DownloadQuery;
+    use crate::completeness;
+    use crate::controllers::rest::{DownloadEventsQuery, DownloadQuery, FeedQuery};
     use crate::datasource::repository;
     use crate::datasource::repository::downloads::QueryOptions;
-    use crate::errors::Error;
-    use crate::models::DownloadGroup;
+    use crate::errors::{Error, ErrorResponse};
+    use crate::models::{Completeness, DownloadGroup, ShowDownloads};
+    use crate::rate_limit::ClientId;
     use crate::state::{AppState, DBPool};
+    use crate::subscription::filter::ShowTitle;
 
+    const SHOW_DOWNLOADS_LIMIT: u32 = 500;
+
+    #[derive(Debug, Deserialize, IntoParams)]
+    #[into_params(parameter_in = Query)]
+    pub(crate) struct GapsQuery {
+        /// Case-insensitive prefix of the title.
+        title: String,
+    }
+
+    #[utoipa::path(
+        get,
+        path = "/v1/downloads",
+        operation_id = "list_downloads",
+        tag = "downloads",
+        params(DownloadQuery),
+        responses((status = 200, description = "The most recently updated downloads", body = Vec<DownloadGroup>)),
+    )]
     pub(crate) async fn find_downloads(
         Query(params): Query<DownloadQuery>,
         State(pool): State<DBPool>,
     ) -> Result<Json<Vec<DownloadGroup>>, Error> {
+        let downloads = super::find_downloads(params, pool.clone(), None).await?;
+        Ok(Json(super::with_links(&pool, downloads).await?))
+    }
+
+    #[utoipa::path(
+        get,
+        path = "/v1/downloads/{id}",
+        operation_id = "get_download",
+        tag = "downloads",
+        params(("id" = Uuid, Path, description = "Id of the download")),
+        responses(
+            (status = 200, body = DownloadGroup),
+            (status = 404, body = ErrorResponse),
+        ),
+    )]
+    pub(crate) async fn by_id(
+        Path(id): Path<Uuid>,
+        State(pool): State<DBPool>,
+    ) -> Result<Json<DownloadGroup>, Error> {
         let options = QueryOptions {
-            title: params.title,
+            id: Some(id),
+            limit: Some(1),
+            ..QueryOptions::default()
+        };
+        let downloads =
+            repository::downloads::get_with_downloads(pool.clone(), None, Some(options)).await?;
+        let download = super::with_links(&pool, downloads)
+            .await?
+            .into_iter()
+            .next()
+            .ok_or(Error::NotFound("download"))?;
+        Ok(Json(download))
+    }
+
+    #[utoipa::path(
+        get,
+        path = "/v1/downloads/gaps",
+        operation_id = "list_download_gaps",
+        tag = "downloads",
+        params(GapsQuery),
+        responses((
+            status = 200,
+            description = "The missing episodes and resolutions of the shows with a matching title",
+            body = Vec<Completeness>,
+        )),
+    )]
+    pub(crate) async fn gaps(
+        Query(params): Query<GapsQuery>,
+        State(pool): State<DBPool>,
+    ) -> Result<Json<Vec<Completeness>>, Error> {
+        let coverage = repository::downloads::coverage(&pool, Some(&params.title), &[]).await?;
+        Ok(Json(completeness::completeness(&coverage)))
+    }
+
+    #[utoipa::path(
+        get,
+        path = "/v1/shows/{title}/downloads",
+        operation_id = "list_show_downloads",
+        tag = "downloads",
+        params(("title" = String, Path, description = "Title of the show, ignoring case and punctuation")),
+        responses(
+            (
+                status = 200,
+                description = "The 500 most recently updated downloads of the show and what is missing from them",
+                body = ShowDownloads,
+            ),
+            (status = 404, body = ErrorResponse),
+        ),
+    )]
+    pub(crate) async fn by_show(
+        Path(title): Path<String>,
+        State(pool): State<DBPool>,
+    ) -> Result<Json<ShowDownloads>, Error> {
+        let normalized_titles = vec![ShowTitle::new(&title).normalized().to_string()];
+        let coverage = repository::downloads::coverage(&pool, None, &normalized_titles).await?;
+        let options = QueryOptions {
+            normalized_titles,
+            limit: Some(SHOW_DOWNLOADS_LIMIT),
+            ..QueryOptions::default()
         };
         let downloads =
             repository::downloads::get_with_downloads(pool.clone(), None, Some(options)).await?;
-        Ok(Json(downloads))
+        if downloads.is_empty() {
+            return Err(Error::NotFound("show"));
+        }
+        Ok(Json(ShowDownloads {
+            completeness: completeness::completeness(&coverage).into_iter().next(),
+            downloads: super::with_links(&pool, downloads).await?,
+        }))
+    }
+
+    #[utoipa::path(
+        get,
+        path = "/v1/downloads/feed.rss",
+        operation_id = "rss_downloads",
+        tag = "downloads",
+        params(FeedQuery),
+        responses((
+            status = 200,
+            description = "RSS feed of the most recently published downloads",
+            content_type = "application/rss+xml",
+            body = String,
+        )),
+    )]
+    pub(crate) async fn rss_feed(
+        Query(params): Query<FeedQuery>,
+        State(pool): State<DBPool>,
+        OriginalUri(uri): OriginalUri,
+        headers: HeaderMap,
+    ) -> Result<Response, Error> {
+        super::rss_feed(params, pool, &headers, &uri, None).await
+    }
+
+    #[utoipa::path(
+        get,
+        path = "/v1/downloads/feed.atom",
+        operation_id = "atom_downloads",
+        tag = "downloads",
+        params(FeedQuery),
+        responses((
+            status = 200,
+            description = "Atom feed of the most recently published downloads",
+            content_type = "application/atom+xml",
+            body = String,
+        )),
+    )]
+    pub(crate) async fn atom_feed(
+        Query(params): Query<FeedQuery>,
+        State(pool): State<DBPool>,
+        OriginalUri(uri): OriginalUri,
+        headers: HeaderMap,
+    ) -> Result<Response, Error> {
+        super::atom_feed(params, pool, &headers, &uri, None).await
     }
 
+    #[utoipa::path(
+        get,
+        path = "/v1/downloads/updates",
+        operation_id = "stream_downloads",
+        tag = "downloads",
+        params(
+            DownloadEventsQuery,
+            ("last-event-id" = Option<u64>, Header, description = "Resume after this event"),
+        ),
+        responses((
+            status = 200,
+            description = "Server-sent `download` events of new downloads, or `revised` ones for new versions of episodes",
+            content_type = "text/event-stream",
+            body = DownloadGroup,
+        )),
+    )]
     pub(crate) async fn get_downloads_events(
         State(state): State<AppState>,
-    ) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
-        let mut rx = state.downloads_channel.subscribe();
-        let stream = try_stream! {
-            loop {
-                match rx.recv().await {
-                    Ok(i) => match Event::default().event("download").json_data(i) {
-                        Ok(event) => yield event,
-                        Err(e) => error!(error = ?e, "failed to serialize"),
-                    }
-                    Err(e) => error!(error = ?e, "sender closed"),
-                }
+        Extension(client): Extension<ClientId>,
+        Query(params): Query<DownloadEventsQuery>,
+        headers: HeaderMap,
+    ) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, Error> {
+        super::get_downloads_events(state, &headers, client, params.into_filter(None).into())
+    }
+}
+
+pub(crate) mod calendar {
+    use axum::extract::{Query, State};
+    use axum::http::header::CONTENT_TYPE;
+    use axum::response::{IntoResponse, Response};
+    use chrono::Utc;
+    use serde::Deserialize;
+    use utoipa::IntoParams;
+
+    use crate::controllers::ical;
+    use crate::datasource::repository;
+    use crate::errors::Error;
+    use crate::schedule;
+    use crate::state::DBPool;
+
+    const EXPECTED_EPISODES: u32 = 4;
+
+    #[derive(Debug, Deserialize, IntoParams)]
+    #[into_params(parameter_in = Query)]
+    pub(crate) struct CalendarQuery {
+        /// Case-insensitive prefix of the title, to only include a single show.
+        title: Option<String>,
+    }
+
+    #[utoipa::path(
+        get,
+        path = "/v1/calendar.ics",
+        operation_id = "get_calendar",
+        tag = "calendar",
+        params(CalendarQuery),
+        responses((
+            status = 200,
+            description = "iCalendar of the episodes released in the last half year and the ones \
+                expected next for shows releasing weekly",
+            content_type = "text/calendar",
+            body = String,
+        )),
+    )]
+    pub(crate) async fn calendar(
+        Query(params): Query<CalendarQuery>,
+        State(pool): State<DBPool>,
+    ) -> Result<Response, Error> {
+        let now = Utc::now();
+        let released = repository::downloads::episode::releases(
+            &pool,
+            params.title.as_deref(),
+            &(now - schedule::HISTORY),
+        )
+        .await?;
+        let expected: Vec<_> = schedule::by_show(&released)
+            .flat_map(|releases| schedule::predict(releases, now, EXPECTED_EPISODES))
+            .collect();
+        let name = params.title.map_or_else(
+            || "Anime releases".to_string(),
+            |title| format!("{title} releases"),
+        );
+        let calendar = ical::calendar(&name, &released, &expected, now);
+        Ok(([(CONTENT_TYPE, ical::CALENDAR_TYPE)], calendar).into_response())
+    }
+}
+
+pub(crate) mod schedules {
+    use axum::Json;
+    use axum::extract::{Path, State};
+    use chrono::Utc;
+
+    use crate::datasource::repository;
+    use crate::errors::{Error, ErrorResponse};
+    use crate::models::ShowSchedule;
+    use crate::schedule;
+    use crate::state::DBPool;
+    use crate::subscription::filter::ShowTitle;
+
+    #[utoipa::path(
+        get,
+        path = "/v1/shows/schedule",
+        operation_id = "get_week_schedule",
+        tag = "calendar",
+        responses((
+            status = 200,
+            description = "The shows airing this week, ordered by when in the week they release",
+            body = Vec<ShowSchedule>,
+        )),
+    )]
+    pub(crate) async fn this_week(
+        State(pool): State<DBPool>,
+    ) -> Result<Json<Vec<ShowSchedule>>, Error> {
+        let now = Utc::now();
+        let releases =
+            repository::downloads::episode::releases(&pool, None, &(now - schedule::HISTORY))
+                .await?;
+        Ok(Json(schedule::this_week(&releases, now)))
+    }
+
+    #[utoipa::path(
+        get,
+        path = "/v1/shows/{title}/schedule",
+        operation_id = "get_show_schedule",
+        tag = "calendar",
+        params(("title" = String, Path, description = "Title of the show, ignoring case and punctuation")),
+        responses(
+            (status = 200, body = ShowSchedule),
+            (status = 404, body = ErrorResponse),
+        ),
+    )]
+    pub(crate) async fn by_title(
+        Path(title): Path<String>,
+        State(pool): State<DBPool>,
+    ) -> Result<Json<ShowSchedule>, Error> {
+        let now = Utc::now();
+        let releases =
+            repository::downloads::episode::releases(&pool, None, &(now - schedule::HISTORY))
+                .await?;
+        let show = ShowTitle::new(&title);
+        schedule::by_show(&releases)
+            .find(|releases| show.matches(&releases[0].title))
+            .and_then(|releases| schedule::schedule(releases, now))
+            .map(Json)
+            .ok_or(Error::NotFound("schedule"))
+    }
+}
+
+pub(crate) mod webhooks {
+    use axum::Json;
+    use axum::extract::{Path, Query, State};
+    use axum::http::StatusCode;
+    use axum::response::NoContent;
+    use serde::Deserialize;
+    use url::Url;
+    use utoipa::{IntoParams, ToSchema};
+    use uuid::Uuid;
+
+    use crate::datasource::repository;
+    use crate::datasource::repository::webhooks::{NewWebhook, WebhookFormat, WebhookUpdate};
+    use crate::errors::{Error, ErrorResponse};
+    use crate::models::{Webhook, WebhookDelivery};
+    use crate::state::DBPool;
+
+    const MIN_SECRET_LENGTH: usize = 16;
+    const DEFAULT_DELIVERIES_LIMIT: u32 = 50;
+    const MAX_DELIVERIES_LIMIT: u32 = 500;
+
+    #[derive(Debug, Deserialize, ToSchema)]
+    pub(crate) struct CreateWebhook {
+        /// The http or https url deliveries are posted to.
+        url: Url,
+        /// Signs deliveries when set, at least 16 characters. Required for generic webhooks.
+        secret: Option<String>,
+        #[serde(default)]
+        format: WebhookFormat,
+        #[serde(default)]
+        title_patterns: Vec<String>,
+        #[serde(default = "enabled_default")]
+        #[schema(default = true)]
+        enabled: bool,
+    }
+
+    #[derive(Debug, Deserialize, ToSchema)]
+    pub(crate) struct UpdateWebhook {
+        url: Option<Url>,
+        secret: Option<String>,
+        format: Option<WebhookFormat>,
+        title_patterns: Option<Vec<String>>,
+        enabled: Option<bool>,
+    }
+
+    #[derive(Debug, Deserialize, IntoParams)]
+    #[into_params(parameter_in = Query)]
+    pub(crate) struct DeliveriesQuery {
+        /// Number of deliveries to return, defaults to 50 and at most 500.
+        limit: Option<u32>,
+    }
+
+    fn enabled_default() -> bool {
+        true
+    }
+
+    fn validate_url(url: &Url) -> Result<(), Error> {
+        match url.scheme() {
+            "http" | "https" => Ok(()),
+            scheme => Err(Error::BadRequest(format!(
+                "unsupported webhook url scheme: {scheme}"
+            ))),
+        }
+    }
+
+    fn validate_secret(secret: &str) -> Result<(), Error> {
+        if secret.len() < MIN_SECRET_LENGTH {
+            return Err(Error::BadRequest(format!(
+                "webhook secret must be at least {MIN_SECRET_LENGTH} characters"
+            )));
+        }
+        Ok(())
+    }
+
+    fn missing_secret() -> Error {
+        Error::BadRequest("generic webhooks require a secret".to_string())
+    }
+
+    #[utoipa::path(
+        get,
+        path = "/v1/webhooks",
+        operation_id = "list_webhooks",
+        tag = "webhooks",
+        responses((status = 200, body = Vec<Webhook>)),
+    )]
+    pub(crate) async fn find(State(pool): State<DBPool>) -> Result<Json<Vec<Webhook>>, Error> {
+        let webhooks = repository::webhooks::all(&pool).await?;
+        Ok(Json(webhooks))
+    }
+
+    #[utoipa::path(
+        post,
+        path = "/v1/webhooks",
+        operation_id = "create_webhook",
+        tag = "webhooks",
+        request_body = CreateWebhook,
+        responses(
+            (status = 201, body = Webhook),
+            (status = 400, body = ErrorResponse),
+        ),
+    )]
+    pub(crate) async fn create(
+        State(pool): State<DBPool>,
+        Json(body): Json<CreateWebhook>,
+    ) -> Result<(StatusCode, Json<Webhook>), Error> {
+        validate_url(&body.url)?;
+        match (&body.secret, body.format) {
+            (Some(secret), _) => validate_secret(secret)?,
+            (None, WebhookFormat::Generic) => return Err(missing_secret()),
+            (None, _) => {}
+        }
+        let webhook = NewWebhook {
+            url: body.url,
+            secret: body.secret,
+            format: body.format,
+            title_patterns: body.title_patterns,
+            enabled: body.enabled,
+        };
+        let webhook = repository::webhooks::insert(&pool, &webhook).await?;
+        Ok((StatusCode::CREATED, Json(webhook)))
+    }
+
+    #[utoipa::path(
+        get,
+        path = "/v1/webhooks/{id}",
+        operation_id = "get_webhook",
+        tag = "webhooks",
+        params(("id" = Uuid, Path, description = "Id of the webhook")),
+        responses(
+            (status = 200, body = Webhook),
+            (status = 404, body = ErrorResponse),
+        ),
+    )]
+    pub(crate) async fn by_id(
+        Path(id): Path<Uuid>,
+        State(pool): State<DBPool>,
+    ) -> Result<Json<Webhook>, Error> {
+        let webhook = repository::webhooks::by_id(&pool, id)
+            .await?
+            .ok_or(Error::NotFound("webhook"))?;
+        Ok(Json(webhook))
+    }
+
+    #[utoipa::path(
+        patch,
+        path = "/v1/webhooks/{id}",
+        operation_id = "update_webhook",
+        tag = "webhooks",
+        params(("id" = Uuid, Path, description = "Id of the webhook")),
+        request_body = UpdateWebhook,
+        responses(
+            (status = 200, body = Webhook),
+            (status = 400, body = ErrorResponse),
+            (status = 404, body = ErrorResponse),
+        ),
+    )]
+    pub(crate) async fn update(
+        Path(id): Path<Uuid>,
+        State(pool): State<DBPool>,
+        Json(body): Json<UpdateWebhook>,
+    ) -> Result<Json<Webhook>, Error> {
+        if let Some(url) = &body.url {
+            validate_url(url)?;
+        }
+        if let Some(secret) = &body.secret {
+            validate_secret(secret)?;
+        } else if body.format == Some(WebhookFormat::Generic) {
+            let webhook = repository::webhooks::by_id(&pool, id)
+                .await?
+                .ok_or(Error::NotFound("webhook"))?;
+            if !webhook.signed {
+                return Err(missing_secret());
+            }
+        }
+        let update = WebhookUpdate {
+            url: body.url,
+            secret: body.secret,
+            format: body.format,
+            title_patterns: body.title_patterns,
+            enabled: body.enabled,
+        };
+        let webhook = repository::webhooks::update(&pool, id, &update)
+            .await?
+            .ok_or(Error::NotFound("webhook"))?;
+        Ok(Json(webhook))
+    }
+
+    #[utoipa::path(
+        delete,
+        path = "/v1/webhooks/{id}",
+        operation_id = "delete_webhook",
+        tag = "webhooks",
+        params(("id" = Uuid, Path, description = "Id of the webhook")),
+        responses(
+            (status = 204, description = "The webhook was deleted"),
+            (status = 404, body = ErrorResponse),
+        ),
+    )]
+    pub(crate) async fn delete(
+        Path(id): Path<Uuid>,
+        State(pool): State<DBPool>,
+    ) -> Result<NoContent, Error> {
+        if !repository::webhooks::delete(&pool, id).await? {
+            return Err(Error::NotFound("webhook"));
+        }
+        Ok(NoContent)
+    }
+
+    #[utoipa::path(
+        get,
+        path = "/v1/webhooks/{id}/deliveries",
+        operation_id = "list_webhook_deliveries",
+        tag = "webhooks",
+        params(("id" = Uuid, Path, description = "Id of the webhook"), DeliveriesQuery),
+        responses(
+            (status = 200, description = "The most recent delivery attempts", body = Vec<WebhookDelivery>),
+            (status = 404, body = ErrorResponse),
+        ),
+    )]
+    pub(crate) async fn deliveries(
+        Path(id): Path<Uuid>,
+        Query(params): Query<DeliveriesQuery>,
+        State(pool): State<DBPool>,
+    ) -> Result<Json<Vec<WebhookDelivery>>, Error> {
+        if repository::webhooks::by_id(&pool, id).await?.is_none() {
+            return Err(Error::NotFound("webhook"));
+        }
+        let limit = params
+            .limit
+            .unwrap_or(DEFAULT_DELIVERIES_LIMIT)
+            .min(MAX_DELIVERIES_LIMIT);
+        let deliveries = repository::webhooks::deliveries(&pool, id, limit).await?;
+        Ok(Json(deliveries))
+    }
+}
+
+pub(crate) mod watchlist {
+    use std::collections::HashSet;
+    use std::convert::Infallible;
+
+    use ahash::RandomState;
+    use axum::Extension;
+    use axum::Json;
+    use axum::extract::{Path, Query, State};
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::response::sse::Event;
+    use axum::response::{NoContent, Sse};
+    use futures::Stream;
+    use serde::{Deserialize, Deserializer};
+    use utoipa::ToSchema;
+    use uuid::Uuid;
+
+    use crate::auth::Access;
+    use crate::controllers::rest::DownloadQuery;
+    use crate::datasource::repository;
+    use crate::datasource::repository::downloads::QueryOptions;
+    use crate::datasource::repository::watchlist::NewWatchlistEntry;
+    use crate::errors::{Error, ErrorResponse};
+    use crate::models::{DownloadGroup, WatchlistEntry};
+    use crate::rate_limit::ClientId;
+    use crate::state::{AppState, DBPool, ReqwestClient};
+    use crate::subscription::filter;
+    use crate::subscription::filter::{DownloadFilter, ShowTitle, SubscriptionFilter};
+
+    #[derive(Debug, Deserialize, ToSchema)]
+    pub(crate) struct CreateWatchlistEntry {
+        /// Title of the show, ignoring case and punctuation. Exclusive with `show_id`.
+        title: Option<String>,
+        /// Kitsu id of the show. Exclusive with `title`.
+        show_id: Option<u32>,
+        /// Only include this resolution of downloads released in it.
+        resolution: Option<u16>,
+    }
+
+    #[derive(Debug, Deserialize, ToSchema)]
+    pub(crate) struct UpdateWatchlistEntry {
+        /// The preferred resolution, or `null` to include every resolution. Left as is when
+        /// omitted.
+        #[serde(default)]
+        #[schema(value_type = Option<u16>)]
+        resolution: Patch<u16>,
+    }
+
+    /// A field of a partial update, which keeps its value when omitted and is cleared by `null`.
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+    enum Patch<T> {
+        #[default]
+        Keep,
+        Set(Option<T>),
+    }
+
+    impl<'de, T: Deserialize<'de>> Deserialize<'de> for Patch<T> {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            Option::deserialize(deserializer).map(Self::Set)
+        }
+    }
+
+    /// The titles of the shows followed by the api key, in their preferred resolutions.
+    async fn watchlist_titles(
+        pool: &DBPool,
+        client: &ReqwestClient,
+        api_key_id: Uuid,
+    ) -> Result<Vec<ShowTitle>, Error> {
+        let entries = repository::watchlist::all(pool, api_key_id).await?;
+        Ok(filter::watchlist_titles(client, &entries).await?)
+    }
+
+    /// Filters the downloads to the shows followed by the api key, in their preferred resolutions.
+    pub(crate) async fn watchlist_filter(
+        pool: &DBPool,
+        client: &ReqwestClient,
+        api_key_id: Uuid,
+    ) -> Result<DownloadFilter, Error> {
+        let titles = watchlist_titles(pool, client, api_key_id).await?;
+        Ok(DownloadFilter::default().with_watchlist(titles))
+    }
+
+    #[utoipa::path(
+        get,
+        path = "/v1/watchlist",
+        operation_id = "list_watchlist",
+        tag = "watchlist",
+        responses((status = 200, body = Vec<WatchlistEntry>)),
+    )]
+    pub(crate) async fn find(
+        State(pool): State<DBPool>,
+        Extension(access): Extension<Access>,
+    ) -> Result<Json<Vec<WatchlistEntry>>, Error> {
+        let entries = repository::watchlist::all(&pool, access.key_id()?).await?;
+        Ok(Json(entries))
+    }
+
+    #[utoipa::path(
+        post,
+        path = "/v1/watchlist",
+        operation_id = "create_watchlist_entry",
+        tag = "watchlist",
+        request_body = CreateWatchlistEntry,
+        responses(
+            (status = 201, body = WatchlistEntry),
+            (status = 400, body = ErrorResponse),
+            (status = 404, description = "The Kitsu show does not exist", body = ErrorResponse),
+        ),
+    )]
+    pub(crate) async fn create(
+        State(pool): State<DBPool>,
+        State(client): State<ReqwestClient>,
+        Extension(access): Extension<Access>,
+        Json(body): Json<CreateWatchlistEntry>,
+    ) -> Result<(StatusCode, Json<WatchlistEntry>), Error> {
+        let api_key_id = access.key_id()?;
+        let title = body.title.filter(|title| !title.trim().is_empty());
+        // looked up once, so following the show costs no Kitsu requests when filtering downloads
+        let show_titles = match (&title, body.show_id) {
+            (Some(_), None) => Vec::new(),
+            (None, Some(show_id)) => filter::kitsu_titles(&client, show_id).await?,
+            (Some(_), Some(_)) | (None, None) => {
+                return Err(Error::BadRequest(
+                    "exactly one of title and show_id is required".to_string(),
+                ));
             }
         };
-        Sse::new(stream).keep_alive(KeepAlive::new())
+        let entry = NewWatchlistEntry {
+            title,
+            show_id: body.show_id,
+            show_titles,
+            resolution: body.resolution,
+        };
+        let entry = repository::watchlist::insert(&pool, api_key_id, &entry).await?;
+        Ok((StatusCode::CREATED, Json(entry)))
+    }
+
+    #[utoipa::path(
+        get,
+        path = "/v1/watchlist/{id}",
+        operation_id = "get_watchlist_entry",
+        tag = "watchlist",
+        params(("id" = Uuid, Path, description = "Id of the watchlist entry")),
+        responses(
+            (status = 200, body = WatchlistEntry),
+            (status = 404, body = ErrorResponse),
+        ),
+    )]
+    pub(crate) async fn by_id(
+        Path(id): Path<Uuid>,
+        State(pool): State<DBPool>,
+        Extension(access): Extension<Access>,
+    ) -> Result<Json<WatchlistEntry>, Error> {
+        let entry = repository::watchlist::by_id(&pool, id, access.key_id()?)
+            .await?
+            .ok_or(Error::NotFound("watchlist entry"))?;
+        Ok(Json(entry))
+    }
+
+    #[utoipa::path(
+        patch,
+        path = "/v1/watchlist/{id}",
+        operation_id = "update_watchlist_entry",
+        tag = "watchlist",
+        params(("id" = Uuid, Path, description = "Id of the watchlist entry")),
+        request_body = UpdateWatchlistEntry,
+        responses(
+            (status = 200, body = WatchlistEntry),
+            (status = 404, body = ErrorResponse),
+        ),
+    )]
+    pub(crate) async fn update(
+        Path(id): Path<Uuid>,
+        State(pool): State<DBPool>,
+        Extension(access): Extension<Access>,
+        Json(body): Json<UpdateWatchlistEntry>,
+    ) -> Result<Json<WatchlistEntry>, Error> {
+        let api_key_id = access.key_id()?;
+        let entry = match body.resolution {
+            Patch::Set(resolution) => {
+                repository::watchlist::update_resolution(&pool, id, api_key_id, resolution).await?
+            }
+            Patch::Keep => repository::watchlist::by_id(&pool, id, api_key_id).await?,
+        };
+        Ok(Json(entry.ok_or(Error::NotFound("watchlist entry"))?))
+    }
+
+    #[utoipa::path(
+        delete,
+        path = "/v1/watchlist/{id}",
+        operation_id = "delete_watchlist_entry",
+        tag = "watchlist",
+        params(("id" = Uuid, Path, description = "Id of the watchlist entry")),
+        responses(
+            (status = 204, description = "The show is no longer followed"),
+            (status = 404, body = ErrorResponse),
+        ),
+    )]
+    pub(crate) async fn delete(
+        Path(id): Path<Uuid>,
+        State(pool): State<DBPool>,
+        Extension(access): Extension<Access>,
+    ) -> Result<NoContent, Error> {
+        if !repository::watchlist::delete(&pool, id, access.key_id()?).await? {
+            return Err(Error::NotFound("watchlist entry"));
+        }
+        Ok(NoContent)
+    }
+
+    #[utoipa::path(
+        get,
+        path = "/v1/me/downloads",
+        operation_id = "list_watchlist_downloads",
+        tag = "watchlist",
+        params(DownloadQuery),
+        responses((
+            status = 200,
+            description = "The most recently updated downloads of the followed shows, in their preferred resolutions",
+            body = Vec<DownloadGroup>,
+        )),
+    )]
+    pub(crate) async fn downloads(
+        Query(params): Query<DownloadQuery>,
+        State(pool): State<DBPool>,
+        State(client): State<ReqwestClient>,
+        Extension(access): Extension<Access>,
+    ) -> Result<Json<Vec<DownloadGroup>>, Error> {
+        let filter = watchlist_filter(&pool, &client, access.key_id()?).await?;
+        let normalized_titles: HashSet<_, RandomState> = filter
+            .show_titles
+            .iter()
+            .map(|show| show.normalized().to_string())
+            .collect();
+        if normalized_titles.is_empty() {
+            return Ok(Json(Vec::new()));
+        }
+        let options = QueryOptions {
+            title: params.title,
+            normalized_titles: normalized_titles.into_iter().collect(),
+            latest_versions: params.latest_versions,
+            ..QueryOptions::default()
+        };
+        let downloads =
+            repository::downloads::get_with_downloads(pool.clone(), None, Some(options)).await?;
+        let downloads = downloads
+            .into_iter()
+            .filter_map(|group| filter.apply_group(group))
+            .collect();
+        Ok(Json(super::with_links(&pool, downloads).await?))
+    }
+
+    #[utoipa::path(
+        get,
+        path = "/v1/me/downloads/updates",
+        operation_id = "stream_watchlist_downloads",
+        tag = "watchlist",
+        params(("last-event-id" = Option<u64>, Header, description = "Resume after this event")),
+        responses((
+            status = 200,
+            description = "Server-sent `download` and `revised` events of the followed shows, in their preferred resolutions. The watchlist is read again at most once a minute as events arrive, so changes to it apply to open streams",
+            content_type = "text/event-stream",
+            body = DownloadGroup,
+        )),
+    )]
+    pub(crate) async fn get_downloads_events(
+        State(state): State<AppState>,
+        Extension(client): Extension<ClientId>,
+        Extension(access): Extension<Access>,
+        headers: HeaderMap,
+    ) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, Error> {
+        let api_key_id = access.key_id()?;
+        let titles = watchlist_titles(&state.pool, &state.client, api_key_id).await?;
+        let filter = SubscriptionFilter::watchlist(
+            DownloadFilter::default(),
+            titles,
+            api_key_id,
+            state.pool.clone(),
+            state.client.clone(),
+        );
+        super::get_downloads_events(state, &headers, client, filter)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn update(body: &str) -> Patch<u16> {
+            let Json(update) = Json::<UpdateWatchlistEntry>::from_bytes(body.as_bytes()).unwrap();
+            update.resolution
+        }
+
+        #[test]
+        fn test_update_keeps_omitted_resolution() {
+            assert_eq!(update("{}"), Patch::Keep);
+            assert_eq!(update(r#"{"resolution":null}"#), Patch::Set(None));
+            assert_eq!(update(r#"{"resolution":720}"#), Patch::Set(Some(720)));
+        }
+    }
+}
+
+pub(crate) mod api_keys {
+    use axum::Json;
+    use axum::extract::{Path, State};
+    use axum::http::StatusCode;
+    use axum::response::NoContent;
+    use serde::Deserialize;
+    use utoipa::ToSchema;
+    use uuid::Uuid;
+
+    use crate::auth;
+    use crate::auth::ApiKeys;
+    use crate::datasource::repository;
+    use crate::datasource::repository::api_keys::{ApiKeyScope, NewApiKey};
+    use crate::errors::{Error, ErrorResponse};
+    use crate::models::{ApiKey, CreatedApiKey};
+    use crate::state::DBPool;
+
+    #[derive(Debug, Deserialize, ToSchema)]
+    pub(crate) struct CreateApiKey {
+        /// Describes who or what uses the key.
+        name: String,
+        scopes: Vec<ApiKeyScope>,
+    }
+
+    #[utoipa::path(
+        get,
+        path = "/v1/keys",
+        operation_id = "list_api_keys",
+        tag = "keys",
+        responses((status = 200, body = Vec<ApiKey>)),
+    )]
+    pub(crate) async fn find(State(pool): State<DBPool>) -> Result<Json<Vec<ApiKey>>, Error> {
+        let keys = repository::api_keys::all(&pool).await?;
+        Ok(Json(keys))
+    }
+
+    #[utoipa::path(
+        post,
+        path = "/v1/keys",
+        operation_id = "create_api_key",
+        tag = "keys",
+        request_body = CreateApiKey,
+        responses(
+            (status = 201, description = "The key, which is not returned again", body = CreatedApiKey),
+            (status = 400, body = ErrorResponse),
+        ),
+    )]
+    pub(crate) async fn create(
+        State(pool): State<DBPool>,
+        State(keys): State<ApiKeys>,
+        Json(body): Json<CreateApiKey>,
+    ) -> Result<(StatusCode, Json<CreatedApiKey>), Error> {
+        if body.name.trim().is_empty() {
+            return Err(Error::BadRequest(
+                "api key name must not be empty".to_string(),
+            ));
+        }
+        if body.scopes.is_empty() {
+            return Err(Error::BadRequest(
+                "api key must have at least one scope".to_string(),
+            ));
+        }
+        let (key, key_hash) = auth::generate();
+        let new_key = NewApiKey {
+            name: body.name,
+            prefix: auth::display_prefix(&key),
+            key_hash,
+            scopes: body.scopes,
+        };
+        let api_key = repository::api_keys::insert(&pool, &new_key).await?;
+        keys.reload(&pool).await?;
+        Ok((StatusCode::CREATED, Json(CreatedApiKey { key, api_key })))
+    }
+
+    #[utoipa::path(
+        get,
+        path = "/v1/keys/{id}",
+        operation_id = "get_api_key",
+        tag = "keys",
+        params(("id" = Uuid, Path, description = "Id of the api key")),
+        responses(
+            (status = 200, body = ApiKey),
+            (status = 404, body = ErrorResponse),
+        ),
+    )]
+    pub(crate) async fn by_id(
+        Path(id): Path<Uuid>,
+        State(pool): State<DBPool>,
+    ) -> Result<Json<ApiKey>, Error> {
+        let key = repository::api_keys::by_id(&pool, id)
+            .await?
+            .ok_or(Error::NotFound("api key"))?;
+        Ok(Json(key))
+    }
+
+    #[utoipa::path(
+        delete,
+        path = "/v1/keys/{id}",
+        operation_id = "delete_api_key",
+        tag = "keys",
+        params(("id" = Uuid, Path, description = "Id of the api key")),
+        responses(
+            (status = 204, description = "The key was revoked"),
+            (status = 404, body = ErrorResponse),
+        ),
+    )]
+    pub(crate) async fn delete(
+        Path(id): Path<Uuid>,
+        State(pool): State<DBPool>,
+        State(keys): State<ApiKeys>,
+    ) -> Result<NoContent, Error> {
+        if !repository::api_keys::delete(&pool, id).await? {
+            return Err(Error::NotFound("api key"));
+        }
+        keys.reload(&pool).await?;
+        Ok(NoContent)
     }
 }
@@ -0,0 +1,478 @@
+//! A Torznab indexer api, so media managers like Sonarr and Prowlarr can search the downloads.
+//!
+//! See <https://torznab.github.io/spec-1.3-draft/torznab/Specification-v1.3.html>.
+use std::collections::BTreeMap;
+use std::io;
+
+use axum::extract::{OriginalUri, Query, State};
+use axum::http::HeaderMap;
+use axum::http::header::CONTENT_TYPE;
+use axum::response::{IntoResponse, Response};
+use quick_xml::Writer;
+use quick_xml::events::{BytesDecl, Event};
+use rss::extension::Extension;
+use serde::Deserialize;
+use tracing::error;
+
+use crate::auth::{ApiKeys, Denied};
+use crate::controllers::feed;
+use crate::controllers::feed::{EnclosureLink, FeedInfo};
+use crate::datasource::repository;
+use crate::datasource::repository::api_keys::ApiKeyScope;
+use crate::datasource::repository::downloads::{QueryOptions, Variant};
+use crate::models::{Download, DownloadGroup, DownloadVariant};
+use crate::state::DBPool;
+
+const XML_TYPE: &str = "application/xml; charset=utf-8";
+const TORZNAB_NAMESPACE: &str = "http://torznab.com/schemas/2015/feed";
+const DEFAULT_LIMIT: u32 = 50;
+const MAX_LIMIT: u32 = 100;
+
+const MOVIES: u32 = 2000;
+const TV: u32 = 5000;
+const TV_ANIME: u32 = 5070;
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct TorznabQuery {
+    /// The function to call.
+    t: String,
+    q: Option<String>,
+    /// Comma separated category ids.
+    cat: Option<String>,
+    season: Option<String>,
+    ep: Option<String>,
+    limit: Option<u32>,
+    offset: Option<u32>,
+    /// Api key with the read scope, required for searches.
+    apikey: Option<String>,
+}
+
+/// Errors reported in the Torznab error format.
+#[derive(Debug)]
+enum TorznabError {
+    IncorrectCredentials,
+    InsufficientPrivileges,
+    IncorrectParameter(&'static str),
+    NoSuchFunction,
+    Internal,
+}
+
+impl IntoResponse for TorznabError {
+    fn into_response(self) -> Response {
+        let (code, description) = match self {
+            Self::IncorrectCredentials => (100, "Incorrect user credentials".to_string()),
+            Self::InsufficientPrivileges => (102, "Insufficient privileges".to_string()),
+            Self::IncorrectParameter(name) => (201, format!("Incorrect parameter: {name}")),
+            Self::NoSuchFunction => (202, "No such function".to_string()),
+            Self::Internal => (900, "Internal error".to_string()),
+        };
+        let body = write_xml(|writer| {
+            writer
+                .create_element("error")
+                .with_attribute(("code", code.to_string().as_str()))
+                .with_attribute(("description", description.as_str()))
+                .write_empty()?;
+            Ok(())
+        });
+        // indexer clients expect errors in the body of a successful response
+        xml_response(body)
+    }
+}
+
+pub(crate) async fn api(
+    Query(query): Query<TorznabQuery>,
+    State(pool): State<DBPool>,
+    State(keys): State<ApiKeys>,
+    OriginalUri(uri): OriginalUri,
+    headers: HeaderMap,
+) -> Response {
+    let tv = match query.t.as_str() {
+        "caps" => return xml_response(caps()),
+        "search" => false,
+        "tvsearch" => true,
+        _ => return TorznabError::NoSuchFunction.into_response(),
+    };
+    if let Err(denied) = keys.require(query.apikey.as_deref(), ApiKeyScope::Read) {
+        return match denied {
+            Denied::Unauthenticated => TorznabError::IncorrectCredentials,
+            Denied::Forbidden(_) | Denied::NotStored => TorznabError::InsufficientPrivileges,
+        }
+        .into_response();
+    }
+    match search(pool, &query, tv).await {
+        Ok(groups) => {
+            let info = FeedInfo::new(
+                "anime-service".to_string(),
+                &headers,
+                &uri,
+                EnclosureLink::Torrent,
+            );
+            xml_response(results(&info, &groups).to_string())
+        }
+        Err(e) => e.into_response(),
+    }
+}
+
+fn xml_response(body: String) -> Response {
+    ([(CONTENT_TYPE, XML_TYPE)], body).into_response()
+}
+
+fn write_xml<F>(content: F) -> String
+where
+    F: FnOnce(&mut Writer<Vec<u8>>) -> io::Result<()>,
+{
+    let mut writer = Writer::new(Vec::new());
+    writer
+        .write_event(Event::Decl(BytesDecl::new("1.0", Some("UTF-8"), None)))
+        .and_then(|()| content(&mut writer))
+        .expect("writing to a vec does not fail");
+    String::from_utf8(writer.into_inner()).expect("the written xml is valid utf-8")
+}
+
+fn caps() -> String {
+    write_xml(|writer| {
+        writer
+            .create_element("caps")
+            .write_inner_content(|writer| {
+                writer
+                    .create_element("server")
+                    .with_attribute(("title", "anime-service"))
+                    .write_empty()?;
+                writer
+                    .create_element("limits")
+                    .with_attribute(("max", MAX_LIMIT.to_string().as_str()))
+                    .with_attribute(("default", DEFAULT_LIMIT.to_string().as_str()))
+                    .write_empty()?;
+                writer
+                    .create_element("searching")
+                    .write_inner_content(|writer| {
+                        for (function, available, params) in [
+                            ("search", "yes", "q"),
+                            ("tv-search", "yes", "q,season,ep"),
+                            ("movie-search", "no", "q"),
+                        ] {
+                            writer
+                                .create_element(function)
+                                .with_attribute(("available", available))
+                                .with_attribute(("supportedParams", params))
+                                .write_empty()?;
+                        }
+                        Ok(())
+                    })?;
+                writer
+                    .create_element("categories")
+                    .write_inner_content(|writer| {
+                        writer
+                            .create_element("category")
+                            .with_attribute(("id", MOVIES.to_string().as_str()))
+                            .with_attribute(("name", "Movies"))
+                            .write_empty()?;
+                        writer
+                            .create_element("category")
+                            .with_attribute(("id", TV.to_string().as_str()))
+                            .with_attribute(("name", "TV"))
+                            .write_inner_content(|writer| {
+                                writer
+                                    .create_element("subcat")
+                                    .with_attribute(("id", TV_ANIME.to_string().as_str()))
+                                    .with_attribute(("name", "Anime"))
+                                    .write_empty()?;
+                                Ok(())
+                            })?;
+                        Ok(())
+                    })?;
+                Ok(())
+            })?;
+        Ok(())
+    })
+}
+
+/// Parses an optional numeric parameter, where clients send empty values for absent ones.
+fn number(value: Option<&str>, name: &'static str) -> Result<Option<u32>, TorznabError> {
+    value
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|_| TorznabError::IncorrectParameter(name))
+        })
+        .transpose()
+}
+
+/// The variants of downloads listed in the requested categories, all of them when none are given.
+fn variants(cat: Option<&str>) -> Result<Vec<Variant>, TorznabError> {
+    let categories = cat
+        .into_iter()
+        .flat_map(|cat| cat.split(','))
+        .map(|id| number(Some(id), "cat"))
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+    if categories.is_empty() {
+        return Ok(vec![Variant::Batch, Variant::Episode, Variant::Movie]);
+    }
+    let mut variants = Vec::new();
+    if categories.iter().any(|&c| c == TV || c == TV_ANIME) {
+        variants.extend([Variant::Batch, Variant::Episode]);
+    }
+    if categories.contains(&MOVIES) {
+        variants.push(Variant::Movie);
+    }
+    Ok(variants)
+}
+
+async fn search(
+    pool: DBPool,
+    query: &TorznabQuery,
+    tv: bool,
+) -> Result<Vec<DownloadGroup>, TorznabError> {
+    let mut variants = variants(query.cat.as_deref())?;
+    let mut title = query
+        .q
+        .as_deref()
+        .map(str::trim)
+        .filter(|q| !q.is_empty())
+        .map(|q| format!("%{q}"));
+    let mut episode = None;
+    if tv {
+        variants.retain(|&variant| variant != Variant::Movie);
+        episode = number(query.ep.as_deref(), "ep")?;
+        // later seasons are released with their number appended to the title, like `Title S2`
+        if let Some(season) = number(query.season.as_deref(), "season")?
+            && season > 1
+        {
+            title = Some(format!("{} S{season}", title.as_deref().unwrap_or("%")));
+        }
+    }
+    if variants.is_empty() {
+        return Ok(Vec::new());
+    }
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let offset = query.offset.unwrap_or_default();
+    // every group has at least one download, so these groups hold all items up to the page
+    let options = QueryOptions {
+        title,
+        variants,
+        episode,
+        limit: Some(offset.saturating_add(limit)),
+        ..QueryOptions::default()
+    };
+    let groups = repository::downloads::get_with_downloads(pool, None, Some(options))
+        .await
+        .map_err(|e| {
+            error!(error = ?e, "failed to query downloads");
+            TorznabError::Internal
+        })?;
+    Ok(page(groups, offset as usize, limit as usize))
+}
+
+/// Keeps the downloads at `offset..offset + limit`, counting every resolution as an item like
+/// indexer clients do, and drops the groups left without any.
+fn page(groups: Vec<DownloadGroup>, offset: usize, limit: usize) -> Vec<DownloadGroup> {
+    let mut index = 0;
+    groups
+        .into_iter()
+        .filter_map(|mut group| {
+            group.downloads.retain(|_| {
+                index += 1;
+                (offset..offset + limit).contains(&(index - 1))
+            });
+            (!group.downloads.is_empty()).then_some(group)
+        })
+        .collect()
+}
+
+fn categories(variant: &DownloadVariant) -> &'static [u32] {
+    match variant {
+        DownloadVariant::Batch(_) | DownloadVariant::Episode(_) => &[TV, TV_ANIME],
+        DownloadVariant::Movie => &[MOVIES],
+    }
+}
+
+fn attr(name: &str, value: String) -> Extension {
+    Extension {
+        name: "torznab:attr".to_string(),
+        attrs: BTreeMap::from([
+            ("name".to_string(), name.to_string()),
+            ("value".to_string(), value),
+        ]),
+        ..Extension::default()
+    }
+}
+
+fn info_hash(magnet: &str) -> Option<&str> {
+    let hash = magnet.strip_prefix("magnet:?xt=urn:btih:")?;
+    Some(hash.split_once('&').map_or(hash, |(hash, _)| hash))
+}
+
+fn item(group: &DownloadGroup, download: &Download) -> rss::Item {
+    let categories = categories(&group.variant);
+    let mut attrs: Vec<_> = categories
+        .iter()
+        .map(|c| attr("category", c.to_string()))
+        .collect();
+    attrs.extend(download.size.map(|size| attr("size", size.to_string())));
+    attrs.extend(
+        download
+            .seeders
+            .map(|seeders| attr("seeders", seeders.to_string())),
+    );
+    if let Some(magnet) = &download.magnet {
+        attrs.push(attr("magneturl", magnet.clone()));
+        attrs.extend(info_hash(magnet).map(|hash| attr("infohash", hash.to_string())));
+    }
+    if let DownloadVariant::Episode(episode) = &group.variant {
+        attrs.push(attr("episode", episode.episode.to_string()));
+    }
+    rss::Item {
+        title: Some(download.file_name.clone()),
+        link: Some(download.torrent.clone()),
+        comments: Some(download.comments.clone()),
+        guid: Some(rss::Guid {
+            value: download.comments.clone(),
+            permalink: true,
+        }),
+        pub_date: Some(download.published_date.to_rfc2822()),
+        categories: categories
+            .iter()
+            .map(|c| rss::Category {
+                name: c.to_string(),
+                domain: None,
+            })
+            .collect(),
+        enclosure: Some(rss::Enclosure {
+            url: download.torrent.clone(),
+            length: download.size.unwrap_or_default().to_string(),
+            mime_type: feed::BITTORRENT_TYPE.to_string(),
+        }),
+        extensions: BTreeMap::from([(
+            "torznab".to_string(),
+            BTreeMap::from([("attr".to_string(), attrs)]),
+        )]),
+        ..rss::Item::default()
+    }
+}
+
+fn results(info: &FeedInfo, groups: &[DownloadGroup]) -> rss::Channel {
+    rss::Channel {
+        title: info.title.clone(),
+        link: info.url.clone(),
+        description: info.title.clone(),
+        namespaces: BTreeMap::from([("torznab".to_string(), TORZNAB_NAMESPACE.to_string())]),
+        items: feed::entries(groups)
+            .into_iter()
+            .map(|(group, download)| item(group, download))
+            .collect(),
+        ..rss::Channel::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Episode;
+
+    #[test]
+    fn test_variants() {
+        let all = vec![Variant::Batch, Variant::Episode, Variant::Movie];
+        assert_eq!(variants(None).unwrap(), all);
+        assert_eq!(variants(Some("")).unwrap(), all);
+        assert_eq!(
+            variants(Some("5070")).unwrap(),
+            [Variant::Batch, Variant::Episode]
+        );
+        assert_eq!(variants(Some("2000,5000")).unwrap(), all);
+        assert!(variants(Some("8000")).unwrap().is_empty());
+        assert!(variants(Some("anime")).is_err());
+    }
+
+    #[test]
+    fn test_info_hash() {
+        assert_eq!(info_hash("magnet:?xt=urn:btih:abc&dn=file"), Some("abc"));
+        assert_eq!(info_hash("magnet:?xt=urn:btih:abc"), Some("abc"));
+        assert_eq!(info_hash("https://nyaa.si/download/1.torrent"), None);
+    }
+
+    #[test]
+    fn test_caps() {
+        let caps = caps();
+        assert!(caps.contains(r#"<tv-search available="yes" supportedParams="q,season,ep"/>"#));
+        assert!(caps.contains(r#"<subcat id="5070" name="Anime"/>"#));
+    }
+
+    fn group(title: &str, resolutions: &[u16]) -> DownloadGroup {
+        DownloadGroup::fixture(title).with_resolutions(resolutions)
+    }
+
+    #[test]
+    fn test_page() {
+        let groups = || {
+            vec![
+                group("Frieren", &[1080, 720, 480]),
+                group("Dandadan", &[1080]),
+                group("Oshi no Ko", &[1080, 720]),
+            ]
+        };
+        let file_names = |groups: Vec<DownloadGroup>| -> Vec<String> {
+            groups
+                .into_iter()
+                .flat_map(|group| group.downloads)
+                .map(|download| download.file_name)
+                .collect()
+        };
+        assert_eq!(
+            file_names(page(groups(), 0, 2)),
+            ["Frieren (1080p)", "Frieren (720p)"]
+        );
+        assert_eq!(
+            file_names(page(groups(), 2, 2)),
+            ["Frieren (480p)", "Dandadan (1080p)"]
+        );
+        assert_eq!(file_names(page(groups(), 5, 2)), ["Oshi no Ko (720p)"]);
+        assert!(page(groups(), 6, 2).is_empty());
+    }
+
+    #[test]
+    fn test_results() {
+        let group = DownloadGroup::fixture("Frieren")
+            .with_variant(DownloadVariant::Episode(Episode::fixture(7)))
+            .with_download(
+                Download::fixture(1080)
+                    .with_magnet("magnet:?xt=urn:btih:abc&dn=file")
+                    .with_size(1024)
+                    .with_seeders(12),
+            );
+        let info = FeedInfo::new(
+            "anime-service".to_string(),
+            &HeaderMap::new(),
+            &"/torznab/api?t=search".parse().unwrap(),
+            EnclosureLink::Torrent,
+        );
+        let xml = results(&info, &[group]).to_string();
+        let channel = rss::Channel::read_from(xml.as_bytes()).unwrap();
+        assert_eq!(channel.namespaces()["torznab"], TORZNAB_NAMESPACE);
+        let attrs: Vec<_> = channel.items()[0].extensions()["torznab"]["attr"]
+            .iter()
+            .map(|attr| {
+                (
+                    attr.attrs()["name"].as_str(),
+                    attr.attrs()["value"].as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            attrs,
+            [
+                ("category", "5000"),
+                ("category", "5070"),
+                ("size", "1024"),
+                ("seeders", "12"),
+                ("magneturl", "magnet:?xt=urn:btih:abc&dn=file"),
+                ("infohash", "abc"),
+                ("episode", "7"),
+            ]
+        );
+    }
+}
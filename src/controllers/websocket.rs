@@ -0,0 +1,290 @@
+use std::collections::HashMap;
+use std::future::pending;
+use std::time::Duration;
+
+use ahash::RandomState;
+use axum::Extension;
+use axum::body::Bytes;
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Query, State};
+use axum::response::Response;
+use futures::stream::SplitSink;
+use futures::{SinkExt, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::time::{Instant, interval_at};
+use tracing::{debug, error};
+
+use crate::datasource::repository::downloads::Variant;
+use crate::datasource::repository::events::EventKind;
+use crate::errors::Error;
+use crate::models::{DownloadEvent, DownloadGroup};
+use crate::rate_limit::{ClientId, SubscriptionPermit};
+use crate::state::{AppState, ReqwestClient};
+use crate::subscription;
+use crate::subscription::filter::{self, DownloadFilter, TitlePattern};
+
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(30);
+const MAX_SUBSCRIPTIONS: usize = 16;
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct WebSocketQuery {
+    since: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientMessage {
+    Subscribe {
+        id: String,
+        #[serde(default)]
+        filter: FilterParams,
+    },
+    Unsubscribe {
+        id: String,
+    },
+    Ping,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct FilterParams {
+    titles: Vec<String>,
+    show_ids: Vec<u32>,
+    variants: Vec<Variant>,
+    min_resolution: Option<u16>,
+    resolution: Option<u16>,
+    providers: Vec<String>,
+}
+
+impl FilterParams {
+    async fn into_filter(self, client: &ReqwestClient) -> kitsu::Result<DownloadFilter> {
+        Ok(DownloadFilter {
+            titles: self.titles.iter().map(|t| TitlePattern::new(t)).collect(),
+            show_titles: filter::show_titles(client, &self.show_ids).await?,
+            variants: self.variants,
+            min_resolution: self.min_resolution,
+            resolution: self.resolution,
+            providers: self.providers,
+            ..DownloadFilter::default()
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerMessage<'a> {
+    Subscribed {
+        id: &'a str,
+    },
+    Unsubscribed {
+        id: &'a str,
+    },
+    Download {
+        subscription: &'a str,
+        sequence: u64,
+        data: &'a DownloadGroup,
+    },
+    /// A new version of an episode that was downloaded before.
+    Revised {
+        subscription: &'a str,
+        sequence: u64,
+        data: &'a DownloadGroup,
+    },
+    Pong,
+    Error {
+        message: String,
+    },
+}
+
+type Sink = SplitSink<WebSocket, Message>;
+
+pub(crate) async fn downloads(
+    ws: WebSocketUpgrade,
+    State(state): State<AppState>,
+    Extension(client): Extension<ClientId>,
+    Query(params): Query<WebSocketQuery>,
+) -> Result<Response, Error> {
+    // a socket counts as a single subscription, however many filters it subscribes to
+    let permit = state
+        .rate_limits
+        .subscribe(client)
+        .ok_or(Error::TooManySubscriptions)?;
+    Ok(ws.on_upgrade(move |socket| handle_socket(socket, state, params.since, permit)))
+}
+
+async fn handle_socket(
+    socket: WebSocket,
+    state: AppState,
+    since: Option<u64>,
+    _permit: SubscriptionPermit,
+) {
+    let (mut sink, mut incoming) = socket.split();
+    // events are only streamed once the first subscription exists, so none are replayed unfiltered
+    let mut events = None;
+    let mut subscriptions = HashMap::<String, DownloadFilter, RandomState>::default();
+    let mut keep_alive = interval_at(Instant::now() + KEEP_ALIVE_INTERVAL, KEEP_ALIVE_INTERVAL);
+    let mut awaiting_pong = false;
+
+    loop {
+        let result = tokio::select! {
+            message = incoming.next() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    handle_message(&mut sink, &mut subscriptions, &state.client, &text).await
+                }
+                Some(Ok(Message::Pong(_))) => {
+                    awaiting_pong = false;
+                    Ok(())
+                }
+                Some(Ok(Message::Close(_))) | None => break,
+                // pings are answered automatically and binary messages are not part of the protocol
+                Some(Ok(Message::Ping(_) | Message::Binary(_))) => Ok(()),
+                Some(Err(e)) => {
+                    debug!(error = ?e, "failed to receive websocket message");
+                    break;
+                }
+            },
+            event = next_event(&mut events) => {
+                let Some(event) = event else {
+                    debug!("download sender closed, closing websocket");
+                    break;
+                };
+                send_event(&mut sink, &subscriptions, &event).await
+            }
+            _ = keep_alive.tick() => {
+                if awaiting_pong {
+                    debug!("websocket client did not respond to ping");
+                    break;
+                }
+                awaiting_pong = true;
+                sink.send(Message::Ping(Bytes::new())).await
+            }
+        };
+        if let Err(e) = result {
+            debug!(error = ?e, "failed to send websocket message");
+            break;
+        }
+        if events.is_none() && !subscriptions.is_empty() {
+            events = Some(Box::pin(subscription::subscribe(
+                &state.downloads_channel,
+                Some(state.pool.clone()),
+                since,
+            )));
+        }
+    }
+    let _ = sink.close().await;
+}
+
+/// Waits for the next event, or forever when the event stream has not been started yet.
+async fn next_event<S: Stream + Unpin>(events: &mut Option<S>) -> Option<S::Item> {
+    match events {
+        Some(events) => events.next().await,
+        None => pending().await,
+    }
+}
+
+async fn handle_message(
+    sink: &mut Sink,
+    subscriptions: &mut HashMap<String, DownloadFilter, RandomState>,
+    client: &ReqwestClient,
+    text: &str,
+) -> Result<(), axum::Error> {
+    let message = match serde_json::from_str::<ClientMessage>(text) {
+        Ok(message) => message,
+        Err(e) => {
+            let message = format!("invalid message: {e}");
+            return send(sink, &ServerMessage::Error { message }).await;
+        }
+    };
+    match message {
+        ClientMessage::Subscribe { id, filter } => {
+            if !subscriptions.contains_key(&id) && subscriptions.len() >= MAX_SUBSCRIPTIONS {
+                let message = format!("at most {MAX_SUBSCRIPTIONS} subscriptions are allowed");
+                return send(sink, &ServerMessage::Error { message }).await;
+            }
+            match filter.into_filter(client).await {
+                Ok(filter) => {
+                    send(sink, &ServerMessage::Subscribed { id: &id }).await?;
+                    subscriptions.insert(id, filter);
+                    Ok(())
+                }
+                Err(e) => {
+                    let message = format!("failed to resolve shows: {e}");
+                    send(sink, &ServerMessage::Error { message }).await
+                }
+            }
+        }
+        ClientMessage::Unsubscribe { id } => {
+            subscriptions.remove(&id);
+            send(sink, &ServerMessage::Unsubscribed { id: &id }).await
+        }
+        ClientMessage::Ping => send(sink, &ServerMessage::Pong).await,
+    }
+}
+
+async fn send_event(
+    sink: &mut Sink,
+    subscriptions: &HashMap<String, DownloadFilter, RandomState>,
+    event: &DownloadEvent,
+) -> Result<(), axum::Error> {
+    for (id, filter) in subscriptions {
+        let Some(event) = filter.apply(event.clone()) else {
+            continue;
+        };
+        let message = match event.kind {
+            EventKind::Download => ServerMessage::Download {
+                subscription: id,
+                sequence: event.sequence,
+                data: &event.group,
+            },
+            EventKind::Revised => ServerMessage::Revised {
+                subscription: id,
+                sequence: event.sequence,
+                data: &event.group,
+            },
+        };
+        send(sink, &message).await?;
+    }
+    Ok(())
+}
+
+async fn send(sink: &mut Sink, message: &ServerMessage<'_>) -> Result<(), axum::Error> {
+    match serde_json::to_string(message) {
+        Ok(text) => sink.send(Message::text(text)).await,
+        Err(e) => {
+            error!(error = ?e, "failed to serialize");
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_subscribe_message() {
+        let text = r#"{"type":"subscribe","id":"a","filter":{"titles":["*frieren*"],"variants":["episode"],"resolution":1080}}"#;
+        let message = serde_json::from_str::<ClientMessage>(text).unwrap();
+        let ClientMessage::Subscribe { id, filter } = message else {
+            panic!("expected a subscribe message");
+        };
+        assert_eq!(id, "a");
+        assert_eq!(filter.titles, vec!["*frieren*"]);
+        assert_eq!(filter.variants, vec![Variant::Episode]);
+        assert_eq!(filter.resolution, Some(1080));
+    }
+
+    #[test]
+    fn test_serialize_download_message() {
+        let group = DownloadGroup::fixture("Frieren");
+        let message = ServerMessage::Download {
+            subscription: "a",
+            sequence: 3,
+            data: &group,
+        };
+        let json = serde_json::to_value(&message).unwrap();
+        assert_eq!(json["type"], "download");
+        assert_eq!(json["sequence"], 3);
+        assert_eq!(json["data"]["variant"], "movie");
+    }
+}
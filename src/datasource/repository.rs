@@ -1,2 +1,6 @@
+pub mod api_keys;
 mod download_resolutions;
 pub mod downloads;
+pub mod events;
+pub mod watchlist;
+pub mod webhooks;
@@ -0,0 +1,137 @@
+use std::fmt;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use sqlx::types::Uuid;
+use sqlx::{Executor, Postgres, query_file, query_file_as};
+
+use crate::models::ApiKey;
+
+/// What an api key grants access to.
+#[derive(
+    Debug,
+    Copy,
+    Clone,
+    PartialEq,
+    Eq,
+    sqlx::Type,
+    serde::Serialize,
+    serde::Deserialize,
+    utoipa::ToSchema,
+)]
+#[sqlx(type_name = "api_key_scope", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum ApiKeyScope {
+    /// Listing shows, downloads, feeds and calendars, and following shows on the watchlist of the
+    /// key.
+    Read,
+    /// Streaming new downloads over server-sent events, websockets and gRPC.
+    Subscribe,
+    /// Managing webhooks and api keys. Grants every other scope as well.
+    Admin,
+}
+
+impl fmt::Display for ApiKeyScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scope = match self {
+            Self::Read => "read",
+            Self::Subscribe => "subscribe",
+            Self::Admin => "admin",
+        };
+        f.write_str(scope)
+    }
+}
+
+struct ApiKeyEntity {
+    id: Uuid,
+    name: String,
+    prefix: String,
+    scopes: Vec<ApiKeyScope>,
+    created_at: DateTime<Utc>,
+}
+
+impl From<ApiKeyEntity> for ApiKey {
+    fn from(value: ApiKeyEntity) -> Self {
+        Self {
+            id: value.id,
+            name: value.name,
+            prefix: value.prefix,
+            scopes: value.scopes,
+            created_at: value.created_at,
+        }
+    }
+}
+
+/// The digest an api key is recognized by, along with what it grants.
+#[derive(Debug, Clone)]
+pub struct ApiKeyHash {
+    pub id: Uuid,
+    pub key_hash: Vec<u8>,
+    pub scopes: Vec<ApiKeyScope>,
+}
+
+#[derive(Debug)]
+pub struct NewApiKey {
+    pub name: String,
+    pub prefix: String,
+    pub key_hash: Vec<u8>,
+    pub scopes: Vec<ApiKeyScope>,
+}
+
+pub async fn insert<'e, E>(executor: E, key: &NewApiKey) -> Result<ApiKey>
+where
+    E: Executor<'e, Database = Postgres>,
+{
+    let record = query_file_as!(
+        ApiKeyEntity,
+        "queries/api_key/insert_api_key.sql",
+        key.name,
+        key.prefix,
+        key.key_hash,
+        &key.scopes as &[ApiKeyScope],
+    )
+    .fetch_one(executor)
+    .await?;
+    Ok(record.into())
+}
+
+pub async fn all<'e, E>(executor: E) -> Result<Vec<ApiKey>>
+where
+    E: Executor<'e, Database = Postgres>,
+{
+    let records = query_file_as!(ApiKeyEntity, "queries/api_key/query_api_keys.sql")
+        .fetch_all(executor)
+        .await?;
+    Ok(records.into_iter().map(Into::into).collect())
+}
+
+pub async fn by_id<'e, E>(executor: E, id: Uuid) -> Result<Option<ApiKey>>
+where
+    E: Executor<'e, Database = Postgres>,
+{
+    let record = query_file_as!(ApiKeyEntity, "queries/api_key/query_api_key_by_id.sql", id)
+        .fetch_optional(executor)
+        .await?;
+    Ok(record.map(Into::into))
+}
+
+/// Deletes the key, returning whether it existed.
+pub async fn delete<'e, E>(executor: E, id: Uuid) -> Result<bool>
+where
+    E: Executor<'e, Database = Postgres>,
+{
+    let result = query_file!("queries/api_key/delete_api_key.sql", id)
+        .execute(executor)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn hashes<'e, E>(executor: E) -> Result<Vec<ApiKeyHash>>
+where
+    E: Executor<'e, Database = Postgres>,
+{
+    let hashes = query_file_as!(ApiKeyHash, "queries/api_key/query_api_key_hashes.sql")
+        .fetch_all(executor)
+        .await?;
+    Ok(hashes)
+}
@@ -22,18 +22,23 @@ pub(super) mod models {
         pub torrent: String,
         pub file_name: String,
         pub comments: String,
-        #[allow(dead_code)]
         pub magnet: Option<String>,
+        pub size: Option<i64>,
+        pub seeders: Option<i32>,
         pub created_at: DateTime<Utc>,
     }
 
     impl From<DownloadEntity> for Download {
         fn from(value: DownloadEntity) -> Self {
             Self {
+                id: None,
                 comments: value.comments,
                 resolution: value.resolution.cast_unsigned(),
                 torrent: value.torrent,
+                magnet: value.magnet,
                 file_name: value.file_name,
+                size: value.size.map(i64::cast_unsigned),
+                seeders: value.seeders.map(i32::cast_unsigned),
                 published_date: value.created_at,
             }
         }
@@ -51,7 +56,9 @@ where
         download.torrent,
         &download.file_name,
         download.comments,
-        Option::<String>::None,
+        download.magnet,
+        download.size.map(u64::cast_signed),
+        download.seeders.map(u32::cast_signed),
         download.published_date,
     )
     .execute(executor)
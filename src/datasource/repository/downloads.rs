@@ -4,8 +4,9 @@ use anyhow::Context;
 use chrono::{DateTime, Utc};
 use futures::StreamExt;
 use sqlx::types::Uuid;
-use sqlx::{Connection, Executor, Pool, Postgres, query_file};
+use sqlx::{Connection, Executor, PgConnection, Pool, Postgres, query_file};
 
+use crate::completeness::Coverage;
 use crate::datasource::repository::download_resolutions;
 use crate::models::{DownloadGroup, DownloadVariant, Episode};
 
@@ -13,33 +14,64 @@ pub mod batch;
 pub mod episode;
 pub mod movie;
 
-// TODO: remove default and provide dynamically
-const PROVIDER_DEFAULT: &str = "SubsPlease";
-
-#[derive(Debug, Copy, Clone, sqlx::Type)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, sqlx::Type, serde::Deserialize)]
 #[sqlx(type_name = "download_variant", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
 pub enum Variant {
     Batch,
     Episode,
     Movie,
 }
 
+impl From<&DownloadVariant> for Variant {
+    fn from(value: &DownloadVariant) -> Self {
+        match value {
+            DownloadVariant::Batch(_) => Variant::Batch,
+            DownloadVariant::Episode(_) => Variant::Episode,
+            DownloadVariant::Movie => Variant::Movie,
+        }
+    }
+}
+
+/// Upserts every group on `conn`, leaving committing to the caller so the events of the groups
+/// can be appended in the same transaction.
 pub async fn insert_groups(
-    executor: Pool<Postgres>,
+    conn: &mut PgConnection,
     groups: &[DownloadGroup],
 ) -> anyhow::Result<Vec<Uuid>> {
-    let mut transaction = executor.begin().await?;
     let mut ids = Vec::with_capacity(groups.len());
     for group in groups {
-        ids.push(upsert_group(&mut *transaction, group).await?);
+        ids.push(upsert_group(conn, group).await?);
     }
-    transaction.commit().await?;
     Ok(ids)
 }
 
 #[derive(Debug, Default)]
 pub struct QueryOptions {
     pub title: Option<String>,
+    pub id: Option<Uuid>,
+    pub provider: Option<String>,
+    /// Lowercase titles stripped of everything but letters and digits, of which one must match.
+    pub normalized_titles: Vec<String>,
+    /// Variants of which one must match, in addition to the `variant` queried for.
+    pub variants: Vec<Variant>,
+    /// Only return this episode and the batches containing it.
+    pub episode: Option<u32>,
+    /// Only return downloads ordered after this position, see [`Cursor`].
+    pub after: Option<Cursor>,
+    /// The maximum number of downloads to return, defaults to 25.
+    pub limit: Option<u32>,
+    /// The number of downloads to skip.
+    pub offset: Option<u32>,
+    /// Leave out episodes of which a newer version was released by the same provider.
+    pub latest_versions: bool,
+}
+
+/// A position in the downloads ordered by their last update, newest first.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Cursor {
+    pub updated_at: DateTime<Utc>,
+    pub id: Uuid,
 }
 
 pub async fn get_with_downloads(
@@ -61,7 +93,10 @@ pub async fn get_with_downloads(
     let result: anyhow::Result<Vec<_>> = rows
         .into_iter()
         .map(|r| {
-            Ok(DownloadGroup {
+            let group = DownloadGroup {
+                id: None,
+                links: None,
+                provider: r.provider,
                 title: r.title,
                 variant: match r.variant {
                     Variant::Batch => {
@@ -80,13 +115,15 @@ pub async fn get_with_downloads(
                         decimal: r.decimal,
                         version: r.version,
                         extra: r.extra,
+                        superseded: r.superseded,
                     }),
                     Variant::Movie => DownloadVariant::Movie,
                 },
                 created_at: r.created_at,
                 updated_at: r.updated_at,
                 downloads: downloads.remove(&r.id).unwrap_or_default(),
-            })
+            };
+            Ok(group.with_id(r.id))
         })
         .collect();
     let mut episodes = result?;
@@ -103,6 +140,7 @@ where
         DownloadVariant::Batch(range) => {
             batch::upsert(
                 &mut *transaction,
+                &group.provider,
                 &group.title,
                 range,
                 &group.created_at,
@@ -113,6 +151,7 @@ where
         DownloadVariant::Episode(episode) => {
             episode::upsert(
                 &mut *transaction,
+                &group.provider,
                 &group.title,
                 episode,
                 &group.created_at,
@@ -123,6 +162,7 @@ where
         DownloadVariant::Movie => {
             movie::upsert(
                 &mut *transaction,
+                &group.provider,
                 &group.title,
                 &group.created_at,
                 &group.updated_at,
@@ -165,9 +205,90 @@ where
     Ok(record.updated_at)
 }
 
+/// The episodes and resolutions of every episode and batch download of the matching shows.
+///
+/// Shows match when their title starts with `title` and their normalized title is one of
+/// `normalized_titles`, either of which matches any show when absent or empty.
+pub async fn coverage<'e, E>(
+    executor: E,
+    title: Option<&str>,
+    normalized_titles: &[String],
+) -> anyhow::Result<Vec<Coverage>>
+where
+    E: Executor<'e, Database = Postgres>,
+{
+    let records = query_file!(
+        "queries/query_download_coverage.sql",
+        title,
+        normalized_titles
+    )
+    .fetch_all(executor)
+    .await?;
+    records
+        .into_iter()
+        .map(|record| {
+            let episodes = match record.variant {
+                Variant::Batch => {
+                    let start = record
+                        .start_index
+                        .context("expected a `start_index` for the batch variant")?;
+                    let end = record
+                        .end_index
+                        .context("expected a `end_index` for the batch variant")?;
+                    start.cast_unsigned()..=end.cast_unsigned()
+                }
+                Variant::Episode | Variant::Movie => {
+                    let episode = record
+                        .episode
+                        .context("Expected an episode number for the `episode` variant")?
+                        .cast_unsigned();
+                    episode..=episode
+                }
+            };
+            Ok(Coverage {
+                title: record.title,
+                episodes,
+                batch: record.variant == Variant::Batch,
+                resolutions: record
+                    .resolutions
+                    .into_iter()
+                    .map(i16::cast_unsigned)
+                    .collect(),
+            })
+        })
+        .collect()
+}
+
+/// A batch containing an episode, or an episode contained in a batch.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RelatedDownload {
+    pub download_id: Uuid,
+    pub related_id: Uuid,
+    pub variant: Variant,
+}
+
+/// Finds the batches containing the episodes in `ids` and the episodes contained in the batches
+/// in `ids`, of every provider releasing the same show. Ordered by the episodes they start at.
+pub async fn related<'e, E>(executor: E, ids: &[Uuid]) -> anyhow::Result<Vec<RelatedDownload>>
+where
+    E: Executor<'e, Database = Postgres>,
+{
+    let related = query_file!("queries/query_related_downloads.sql", ids)
+        .fetch_all(executor)
+        .await?
+        .into_iter()
+        .map(|record| RelatedDownload {
+            download_id: record.download_id,
+            related_id: record.related_id,
+            variant: record.variant,
+        })
+        .collect();
+    Ok(related)
+}
+
 struct DownloadEntity {
     id: Uuid,
-    _provider: String,
+    provider: String,
     title: String,
     episode: Option<u32>,
     decimal: Option<u32>,
@@ -176,6 +297,7 @@ struct DownloadEntity {
     end_index: Option<u32>,
     extra: Option<String>,
     variant: Variant,
+    superseded: bool,
     created_at: DateTime<Utc>,
     updated_at: DateTime<Utc>,
 }
@@ -188,10 +310,21 @@ async fn get_data_episodes<'e, E>(
 where
     E: Executor<'e, Database = Postgres>,
 {
+    let after = options.and_then(|o| o.after);
     let query = query_file!(
         "queries/query_downloads_by_title.sql",
         variant as _,
-        options.and_then(|o| o.title.as_ref())
+        options.and_then(|o| o.title.as_ref()),
+        options.and_then(|o| o.id),
+        options.and_then(|o| o.provider.as_ref()),
+        after.map(|c| c.updated_at),
+        after.map(|c| c.id),
+        options.and_then(|o| o.limit).map(i64::from),
+        options.map_or(&[][..], |o| &o.normalized_titles[..]),
+        options.map_or(&[][..], |o| &o.variants[..]) as _,
+        options.and_then(|o| o.episode).map(u32::cast_signed),
+        options.and_then(|o| o.offset).map(i64::from),
+        options.is_some_and(|o| o.latest_versions),
     );
     let mut stream = query.fetch(executor);
     let mut rows = Vec::with_capacity(25);
@@ -199,7 +332,7 @@ where
         let record = row?;
         rows.push(DownloadEntity {
             id: record.id,
-            _provider: record.provider,
+            provider: record.provider,
             title: record.title,
             episode: record.episode.map(i32::cast_unsigned),
             decimal: record.decimal.map(i32::cast_unsigned),
@@ -208,6 +341,7 @@ where
             end_index: record.end_index.map(i32::cast_unsigned),
             extra: record.extra,
             variant: record.variant,
+            superseded: record.superseded,
             created_at: record.created_at,
             updated_at: record.updated_at,
         });
@@ -4,10 +4,11 @@ use chrono::{DateTime, Utc};
 use sqlx::types::Uuid;
 use sqlx::{Connection, Executor, Postgres};
 
-use super::{PROVIDER_DEFAULT, RawSingleDownloadResult, SingleDownloadResult, update_download};
+use super::{RawSingleDownloadResult, SingleDownloadResult, update_download};
 
 pub(super) async fn upsert<C>(
     conn: &mut C,
+    provider: &str,
     title: &str,
     range: &RangeInclusive<u32>,
     created_at: &DateTime<Utc>,
@@ -17,20 +18,29 @@ where
     C: Connection<Database = Postgres>,
 {
     let mut transaction = conn.begin().await?;
-    if let Some(record) = get_by_unique_index(&mut *transaction, title, range).await? {
+    if let Some(record) = get_by_unique_index(&mut *transaction, provider, title, range).await? {
         if record.updated_at < *updated_at {
             update_download(&mut *transaction, record.id, updated_at).await?;
         }
         transaction.commit().await?;
         return Ok((record.id, record.resolutions));
     }
-    let id = insert(&mut *transaction, title, range, created_at, updated_at).await?;
+    let id = insert(
+        &mut *transaction,
+        provider,
+        title,
+        range,
+        created_at,
+        updated_at,
+    )
+    .await?;
     transaction.commit().await?;
     Ok((id, Vec::new()))
 }
 
 async fn get_by_unique_index<'e, E>(
     executor: E,
+    provider: &str,
     title: &str,
     range: &RangeInclusive<u32>,
 ) -> anyhow::Result<Option<SingleDownloadResult>>
@@ -40,7 +50,7 @@ where
     let record = sqlx::query_file_as!(
         RawSingleDownloadResult,
         "queries/batch/query_batch_download_by_unique.sql",
-        PROVIDER_DEFAULT,
+        provider,
         title,
         range.start().cast_signed(),
         range.end().cast_signed(),
@@ -53,6 +63,7 @@ where
 
 async fn insert<'e, E>(
     executor: E,
+    provider: &str,
     title: &str,
     range: &RangeInclusive<u32>,
     created_at: &DateTime<Utc>,
@@ -63,7 +74,7 @@ where
 {
     let record = sqlx::query_file!(
         "queries/batch/insert_batch_download.sql",
-        PROVIDER_DEFAULT,
+        provider,
         title,
         range.start().cast_signed(),
         range.end().cast_signed(),
@@ -3,11 +3,13 @@ use chrono::{DateTime, Utc};
 use sqlx::types::Uuid;
 use sqlx::{Connection, Executor, Postgres, Transaction};
 
-use super::{PROVIDER_DEFAULT, RawSingleDownloadResult, SingleDownloadResult, update_download};
+use super::{RawSingleDownloadResult, SingleDownloadResult, update_download};
 use crate::models::Episode;
+use crate::schedule::Release;
 
 pub(super) async fn upsert<C>(
     conn: &mut C,
+    provider: &str,
     title: &str,
     episode: &Episode,
     created_at: &DateTime<Utc>,
@@ -17,7 +19,7 @@ where
     C: Connection<Database = Postgres>,
 {
     let mut transaction = conn.begin().await?;
-    if let Some(record) = get_by_unique_index(&mut *transaction, title, episode).await? {
+    if let Some(record) = get_by_unique_index(&mut *transaction, provider, title, episode).await? {
         if record.updated_at < *updated_at {
             update_download(&mut *transaction, record.id, updated_at).await?;
         }
@@ -25,13 +27,22 @@ where
         return Ok((record.id, record.resolutions));
     }
 
-    let id = insert_episode(&mut transaction, title, episode, created_at, updated_at).await?;
+    let id = insert_episode(
+        &mut transaction,
+        provider,
+        title,
+        episode,
+        created_at,
+        updated_at,
+    )
+    .await?;
     transaction.commit().await?;
     Ok((id, Vec::new()))
 }
 
 async fn get_by_unique_index<'e, E>(
     executor: E,
+    provider: &str,
     title: &str,
     episode: &Episode,
 ) -> Result<Option<SingleDownloadResult>>
@@ -41,7 +52,7 @@ where
     let result = sqlx::query_file_as!(
         RawSingleDownloadResult,
         "queries/episode/query_episode_download_by_unique.sql",
-        PROVIDER_DEFAULT,
+        provider,
         title,
         episode.episode.cast_signed(),
         episode.decimal.map(u32::cast_signed),
@@ -56,6 +67,7 @@ where
 
 async fn insert_episode(
     pool: &mut Transaction<'_, Postgres>,
+    provider: &str,
     title: &str,
     episode: &Episode,
     created_at: &DateTime<Utc>,
@@ -63,7 +75,7 @@ async fn insert_episode(
 ) -> Result<Uuid> {
     let query = sqlx::query_file!(
         "queries/episode/insert_episode_download.sql",
-        PROVIDER_DEFAULT,
+        provider,
         title,
         episode.episode.cast_signed(),
         episode.decimal.map(u32::cast_signed),
@@ -74,3 +86,25 @@ async fn insert_episode(
     );
     Ok(query.fetch_one(&mut **pool).await?.id)
 }
+
+/// The first download of every episode released since `since`, ordered by title and episode.
+pub async fn releases<'e, E>(
+    executor: E,
+    title: Option<&str>,
+    since: &DateTime<Utc>,
+) -> Result<Vec<Release>>
+where
+    E: Executor<'e, Database = Postgres>,
+{
+    let releases = sqlx::query_file!("queries/episode/query_episode_releases.sql", title, since)
+        .fetch_all(executor)
+        .await?
+        .into_iter()
+        .map(|record| Release {
+            title: record.title,
+            episode: record.episode.cast_unsigned(),
+            released_at: record.released_at,
+        })
+        .collect();
+    Ok(releases)
+}
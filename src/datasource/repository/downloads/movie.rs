@@ -2,10 +2,11 @@ use chrono::{DateTime, Utc};
 use sqlx::types::Uuid;
 use sqlx::{Connection, Executor, Postgres};
 
-use super::{PROVIDER_DEFAULT, RawSingleDownloadResult, SingleDownloadResult, update_download};
+use super::{RawSingleDownloadResult, SingleDownloadResult, update_download};
 
 pub(super) async fn upsert<C>(
     conn: &mut C,
+    provider: &str,
     title: &str,
     created_at: &DateTime<Utc>,
     updated_at: &DateTime<Utc>,
@@ -14,20 +15,21 @@ where
     C: Connection<Database = Postgres>,
 {
     let mut transaction = conn.begin().await?;
-    if let Some(record) = get_by_unique_index(&mut *transaction, title).await? {
+    if let Some(record) = get_by_unique_index(&mut *transaction, provider, title).await? {
         if record.updated_at < *updated_at {
             update_download(&mut *transaction, record.id, updated_at).await?;
         }
         transaction.commit().await?;
         return Ok((record.id, record.resolutions));
     }
-    let id = insert(&mut *transaction, title, created_at, updated_at).await?;
+    let id = insert(&mut *transaction, provider, title, created_at, updated_at).await?;
     transaction.commit().await?;
     Ok((id, Vec::new()))
 }
 
 async fn get_by_unique_index<'e, E>(
     executor: E,
+    provider: &str,
     title: &str,
 ) -> anyhow::Result<Option<SingleDownloadResult>>
 where
@@ -36,7 +38,7 @@ where
     let record = sqlx::query_file_as!(
         RawSingleDownloadResult,
         "queries/movie/query_movie_download_by_unique.sql",
-        PROVIDER_DEFAULT,
+        provider,
         title,
     )
     .fetch_optional(executor)
@@ -47,6 +49,7 @@ where
 
 async fn insert<'e, E>(
     executor: E,
+    provider: &str,
     title: &str,
     created_at: &DateTime<Utc>,
     updated_at: &DateTime<Utc>,
@@ -56,7 +59,7 @@ where
 {
     let record = sqlx::query_file!(
         "queries/movie/insert_movie_download.sql",
-        PROVIDER_DEFAULT,
+        provider,
         title,
         created_at,
         updated_at,
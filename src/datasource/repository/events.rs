@@ -0,0 +1,100 @@
+use anyhow::Result;
+use futures::StreamExt;
+use sqlx::types::{Json, Uuid};
+use sqlx::{Executor, PgConnection, Postgres, query_file, query_file_as};
+
+use crate::models::{DownloadEvent, DownloadGroup};
+
+/// Whether an event is the first download of its episode, or a new version of it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, sqlx::Type)]
+#[sqlx(type_name = "download_event_kind", rename_all = "lowercase")]
+pub enum EventKind {
+    Download,
+    Revised,
+}
+
+impl EventKind {
+    /// The name of the kind in SSE event names and webhook headers.
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Download => "download",
+            Self::Revised => "revised",
+        }
+    }
+}
+
+struct DownloadEventEntity {
+    sequence: i64,
+    download_id: Uuid,
+    kind: EventKind,
+    payload: Json<DownloadGroup>,
+}
+
+impl From<DownloadEventEntity> for DownloadEvent {
+    fn from(value: DownloadEventEntity) -> Self {
+        Self {
+            sequence: value.sequence.cast_unsigned(),
+            kind: value.kind,
+            // events appended before ids were part of the payload lack them
+            group: value.payload.0.with_id(value.download_id),
+        }
+    }
+}
+
+/// Appends an event for every persisted group, returning the events in the same order.
+///
+/// Episodes of which an older version was persisted before are appended as revised. Committing is
+/// left to the caller, so the events are only stored along with their downloads.
+pub async fn append<I>(conn: &mut PgConnection, groups: I) -> Result<Vec<DownloadEvent>>
+where
+    I: IntoIterator<Item = (Uuid, DownloadGroup)>,
+{
+    let groups = groups.into_iter();
+    let mut events = Vec::with_capacity(groups.size_hint().0);
+    for (id, group) in groups {
+        let group = group.with_id(id);
+        let record = query_file!(
+            "queries/event/insert_download_event.sql",
+            id,
+            Json(&group) as _,
+        )
+        .fetch_one(&mut *conn)
+        .await?;
+        events.push(DownloadEvent {
+            sequence: record.sequence.cast_unsigned(),
+            kind: record.kind,
+            group,
+        });
+    }
+    Ok(events)
+}
+
+/// Fetches at most `limit` events that were appended after `sequence`, oldest first.
+pub async fn since<'e, E>(executor: E, sequence: u64, limit: u32) -> Result<Vec<DownloadEvent>>
+where
+    E: Executor<'e, Database = Postgres>,
+{
+    let query = query_file_as!(
+        DownloadEventEntity,
+        "queries/event/query_download_events_since.sql",
+        sequence.cast_signed(),
+        i64::from(limit),
+    );
+    let mut stream = query.fetch(executor);
+    let mut events = Vec::with_capacity(limit as usize);
+    while let Some(row) = stream.next().await {
+        events.push(row?.into());
+    }
+    Ok(events)
+}
+
+pub async fn latest_sequence<'e, E>(executor: E) -> Result<Option<u64>>
+where
+    E: Executor<'e, Database = Postgres>,
+{
+    let record = query_file!("queries/event/query_latest_download_event_sequence.sql")
+        .fetch_one(executor)
+        .await?;
+    Ok(record.sequence.map(i64::cast_unsigned))
+}
@@ -0,0 +1,130 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use sqlx::types::Uuid;
+use sqlx::{Executor, Postgres, query_file, query_file_as};
+
+use crate::models::WatchlistEntry;
+
+struct WatchlistEntity {
+    id: Uuid,
+    title: Option<String>,
+    show_id: Option<i32>,
+    show_titles: Vec<String>,
+    resolution: Option<i16>,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+impl From<WatchlistEntity> for WatchlistEntry {
+    fn from(value: WatchlistEntity) -> Self {
+        Self {
+            id: value.id,
+            title: value.title,
+            show_id: value.show_id.map(i32::cast_unsigned),
+            show_titles: value.show_titles,
+            resolution: value.resolution.map(i16::cast_unsigned),
+            created_at: value.created_at,
+            updated_at: value.updated_at,
+        }
+    }
+}
+
+/// A show to follow, by either its title or its Kitsu id.
+#[derive(Debug)]
+pub struct NewWatchlistEntry {
+    pub title: Option<String>,
+    pub show_id: Option<u32>,
+    /// The known titles of the Kitsu show, so they need not be looked up again.
+    pub show_titles: Vec<String>,
+    pub resolution: Option<u16>,
+}
+
+/// Follows a show on the watchlist of the api key.
+pub async fn insert<'e, E>(
+    executor: E,
+    api_key_id: Uuid,
+    entry: &NewWatchlistEntry,
+) -> Result<WatchlistEntry>
+where
+    E: Executor<'e, Database = Postgres>,
+{
+    let record = query_file_as!(
+        WatchlistEntity,
+        "queries/watchlist/insert_watchlist_entry.sql",
+        api_key_id,
+        entry.title,
+        entry.show_id.map(u32::cast_signed),
+        &entry.show_titles,
+        entry.resolution.map(u16::cast_signed),
+    )
+    .fetch_one(executor)
+    .await?;
+    Ok(record.into())
+}
+
+/// The watchlist of the api key.
+pub async fn all<'e, E>(executor: E, api_key_id: Uuid) -> Result<Vec<WatchlistEntry>>
+where
+    E: Executor<'e, Database = Postgres>,
+{
+    let records = query_file_as!(
+        WatchlistEntity,
+        "queries/watchlist/query_watchlist.sql",
+        api_key_id
+    )
+    .fetch_all(executor)
+    .await?;
+    Ok(records.into_iter().map(Into::into).collect())
+}
+
+pub async fn by_id<'e, E>(executor: E, id: Uuid, api_key_id: Uuid) -> Result<Option<WatchlistEntry>>
+where
+    E: Executor<'e, Database = Postgres>,
+{
+    let record = query_file_as!(
+        WatchlistEntity,
+        "queries/watchlist/query_watchlist_entry_by_id.sql",
+        id,
+        api_key_id
+    )
+    .fetch_optional(executor)
+    .await?;
+    Ok(record.map(Into::into))
+}
+
+/// Replaces the preferred resolution of the entry, returning the updated entry if it exists.
+pub async fn update_resolution<'e, E>(
+    executor: E,
+    id: Uuid,
+    api_key_id: Uuid,
+    resolution: Option<u16>,
+) -> Result<Option<WatchlistEntry>>
+where
+    E: Executor<'e, Database = Postgres>,
+{
+    let record = query_file_as!(
+        WatchlistEntity,
+        "queries/watchlist/update_watchlist_entry.sql",
+        id,
+        api_key_id,
+        resolution.map(u16::cast_signed),
+    )
+    .fetch_optional(executor)
+    .await?;
+    Ok(record.map(Into::into))
+}
+
+/// Deletes the entry, returning whether it existed.
+pub async fn delete<'e, E>(executor: E, id: Uuid, api_key_id: Uuid) -> Result<bool>
+where
+    E: Executor<'e, Database = Postgres>,
+{
+    let result = query_file!(
+        "queries/watchlist/delete_watchlist_entry.sql",
+        id,
+        api_key_id
+    )
+    .execute(executor)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
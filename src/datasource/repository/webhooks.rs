@@ -0,0 +1,236 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use sqlx::types::Uuid;
+use sqlx::{Executor, Postgres, query_file, query_file_as};
+use url::Url;
+
+use crate::models::{Webhook, WebhookDelivery};
+
+/// How the payload posted to a webhook is formatted.
+#[derive(
+    Debug,
+    Copy,
+    Clone,
+    Default,
+    PartialEq,
+    Eq,
+    sqlx::Type,
+    serde::Serialize,
+    serde::Deserialize,
+    utoipa::ToSchema,
+)]
+#[sqlx(type_name = "webhook_format", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookFormat {
+    /// The JSON encoded download group, signed with the webhook secret.
+    #[default]
+    Generic,
+    /// A Discord message with an embed.
+    Discord,
+    /// A Slack Block Kit message.
+    Slack,
+}
+
+struct WebhookEntity {
+    id: Uuid,
+    url: String,
+    format: WebhookFormat,
+    title_patterns: Vec<String>,
+    signed: bool,
+    enabled: bool,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+impl TryFrom<WebhookEntity> for Webhook {
+    type Error = url::ParseError;
+
+    fn try_from(value: WebhookEntity) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: value.id,
+            url: value.url.parse()?,
+            format: value.format,
+            title_patterns: value.title_patterns,
+            signed: value.signed,
+            enabled: value.enabled,
+            created_at: value.created_at,
+            updated_at: value.updated_at,
+        })
+    }
+}
+
+struct WebhookDeliveryEntity {
+    id: i64,
+    sequence: i64,
+    attempt: i32,
+    status_code: Option<i16>,
+    error: Option<String>,
+    created_at: DateTime<Utc>,
+}
+
+impl From<WebhookDeliveryEntity> for WebhookDelivery {
+    fn from(value: WebhookDeliveryEntity) -> Self {
+        Self {
+            id: value.id.cast_unsigned(),
+            sequence: value.sequence.cast_unsigned(),
+            attempt: value.attempt.cast_unsigned(),
+            status_code: value.status_code.map(i16::cast_unsigned),
+            error: value.error,
+            created_at: value.created_at,
+        }
+    }
+}
+
+/// The details needed to deliver an event to a webhook, including its signing secret.
+#[derive(Debug, Clone)]
+pub struct WebhookTarget {
+    pub id: Uuid,
+    pub url: String,
+    pub secret: Option<String>,
+    pub format: WebhookFormat,
+    pub title_patterns: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct NewWebhook {
+    pub url: Url,
+    pub secret: Option<String>,
+    pub format: WebhookFormat,
+    pub title_patterns: Vec<String>,
+    pub enabled: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct WebhookUpdate {
+    pub url: Option<Url>,
+    pub secret: Option<String>,
+    pub format: Option<WebhookFormat>,
+    pub title_patterns: Option<Vec<String>>,
+    pub enabled: Option<bool>,
+}
+
+pub async fn insert<'e, E>(executor: E, webhook: &NewWebhook) -> Result<Webhook>
+where
+    E: Executor<'e, Database = Postgres>,
+{
+    let record = query_file_as!(
+        WebhookEntity,
+        "queries/webhook/insert_webhook.sql",
+        webhook.url.as_str(),
+        webhook.secret,
+        webhook.format as _,
+        &webhook.title_patterns,
+        webhook.enabled,
+    )
+    .fetch_one(executor)
+    .await?;
+    Ok(record.try_into()?)
+}
+
+pub async fn all<'e, E>(executor: E) -> Result<Vec<Webhook>>
+where
+    E: Executor<'e, Database = Postgres>,
+{
+    let records = query_file_as!(WebhookEntity, "queries/webhook/query_webhooks.sql")
+        .fetch_all(executor)
+        .await?;
+    let webhooks: Result<Vec<_>, _> = records.into_iter().map(TryInto::try_into).collect();
+    Ok(webhooks?)
+}
+
+pub async fn by_id<'e, E>(executor: E, id: Uuid) -> Result<Option<Webhook>>
+where
+    E: Executor<'e, Database = Postgres>,
+{
+    let record = query_file_as!(WebhookEntity, "queries/webhook/query_webhook_by_id.sql", id)
+        .fetch_optional(executor)
+        .await?;
+    Ok(record.map(TryInto::try_into).transpose()?)
+}
+
+pub async fn update<'e, E>(executor: E, id: Uuid, update: &WebhookUpdate) -> Result<Option<Webhook>>
+where
+    E: Executor<'e, Database = Postgres>,
+{
+    let record = query_file_as!(
+        WebhookEntity,
+        "queries/webhook/update_webhook.sql",
+        id,
+        update.url.as_ref().map(Url::as_str),
+        update.secret,
+        update.format as _,
+        update.title_patterns.as_deref(),
+        update.enabled,
+    )
+    .fetch_optional(executor)
+    .await?;
+    Ok(record.map(TryInto::try_into).transpose()?)
+}
+
+/// Deletes the webhook, returning whether it existed.
+pub async fn delete<'e, E>(executor: E, id: Uuid) -> Result<bool>
+where
+    E: Executor<'e, Database = Postgres>,
+{
+    let result = query_file!("queries/webhook/delete_webhook.sql", id)
+        .execute(executor)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn enabled_targets<'e, E>(executor: E) -> Result<Vec<WebhookTarget>>
+where
+    E: Executor<'e, Database = Postgres>,
+{
+    let targets = query_file_as!(
+        WebhookTarget,
+        "queries/webhook/query_enabled_webhook_targets.sql"
+    )
+    .fetch_all(executor)
+    .await?;
+    Ok(targets)
+}
+
+pub async fn insert_delivery<'e, E>(
+    executor: E,
+    webhook_id: Uuid,
+    sequence: u64,
+    attempt: u32,
+    status_code: Option<u16>,
+    error: Option<&str>,
+) -> Result<()>
+where
+    E: Executor<'e, Database = Postgres>,
+{
+    query_file!(
+        "queries/webhook/insert_webhook_delivery.sql",
+        webhook_id,
+        sequence.cast_signed(),
+        attempt.cast_signed(),
+        status_code.map(u16::cast_signed),
+        error,
+    )
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+/// Fetches the most recent delivery attempts of a webhook, newest first.
+pub async fn deliveries<'e, E>(
+    executor: E,
+    webhook_id: Uuid,
+    limit: u32,
+) -> Result<Vec<WebhookDelivery>>
+where
+    E: Executor<'e, Database = Postgres>,
+{
+    let records = query_file_as!(
+        WebhookDeliveryEntity,
+        "queries/webhook/query_webhook_deliveries.sql",
+        webhook_id,
+        i64::from(limit),
+    )
+    .fetch_all(executor)
+    .await?;
+    Ok(records.into_iter().map(Into::into).collect())
+}
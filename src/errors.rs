@@ -1,10 +1,14 @@
 use std::num::ParseIntError;
 
 use axum::Json;
-use axum::http::StatusCode;
+use axum::http::header::WWW_AUTHENTICATE;
+use axum::http::{HeaderValue, StatusCode};
 use axum::response::{IntoResponse, Response};
-use serde_json::json;
-use tracing::error;
+use serde::Serialize;
+use tracing::{debug, error};
+use utoipa::ToSchema;
+
+use crate::datasource::repository::api_keys::ApiKeyScope;
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -16,22 +20,70 @@ pub enum Error {
     Nyaa(#[from] nyaa::Error),
     #[error(transparent)]
     ParseInt(#[from] ParseIntError),
+    #[error("{0} not found")]
+    NotFound(&'static str),
+    #[error("{0}")]
+    BadRequest(String),
+    #[error("missing or invalid api key")]
+    Unauthorized,
+    #[error("the api key lacks the {0} scope")]
+    Forbidden(ApiKeyScope),
+    #[error("rate limit exceeded, retry in {0} seconds")]
+    TooManyRequests(u64),
+    #[error("too many open subscriptions")]
+    TooManySubscriptions,
     #[error(transparent)]
     Internal(#[from] InternalError),
 }
 
 impl IntoResponse for Error {
     fn into_response(self) -> Response {
-        error!("request failed with {self}");
         let status = match self {
             Self::Nyaa(nyaa::Error::Status(code)) | Self::Kitsu(kitsu::Error::Status(code)) => code,
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Self::Unauthorized => StatusCode::UNAUTHORIZED,
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
+            Self::TooManyRequests(_) | Self::TooManySubscriptions => StatusCode::TOO_MANY_REQUESTS,
             _ => StatusCode::INTERNAL_SERVER_ERROR,
         };
-        let body = Json(json!({
-            "error": status.canonical_reason().unwrap_or_default(),
-        }));
-        (status, body).into_response()
+        // client errors are expected, only failures of the service itself are errors
+        if status.is_server_error() {
+            error!("request failed with {self}");
+        } else {
+            debug!("request failed with {self}");
+        }
+        let message = match self {
+            Self::NotFound(_)
+            | Self::BadRequest(_)
+            | Self::Unauthorized
+            | Self::Forbidden(_)
+            | Self::TooManyRequests(_)
+            | Self::TooManySubscriptions => Some(self.to_string()),
+            _ => None,
+        };
+        let body = ErrorResponse {
+            error: status.canonical_reason().unwrap_or_default(),
+            message,
+        };
+        let mut response = (status, Json(body)).into_response();
+        if status == StatusCode::UNAUTHORIZED {
+            response
+                .headers_mut()
+                .insert(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+        }
+        response
     }
 }
 
+/// The body of every failed request.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ErrorResponse {
+    /// The reason phrase of the status code.
+    error: &'static str,
+    /// Details on what was wrong with the request, for client errors.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+}
+
 pub type InternalError = anyhow::Error;
@@ -1 +1,3 @@
+pub mod handlers;
 pub mod poller;
+pub mod webhooks;
@@ -0,0 +1,331 @@
+//! Combinators for assembling [`NewDownloadsHandler`] pipelines.
+//!
+//! Wrapping combinators run before the handler they wrap, so
+//! `sink.filter(predicate)` only passes the groups matching `predicate` on to `sink`. The
+//! combinators work alike for handlers of download groups and of the events persisting them
+//! produced.
+
+use std::future::Future;
+
+use anyhow::anyhow;
+use futures::future::{BoxFuture, join_all};
+use tracing::error;
+
+use crate::jobs::poller::NewDownloadsHandler;
+use crate::models::DownloadGroup;
+use crate::state::PollerConfig;
+use crate::subscription::filter::TitlePattern;
+
+pub trait NewDownloadsHandlerExt<T>: NewDownloadsHandler<T> {
+    /// Runs `next` with the same groups once this handler succeeded.
+    fn chain<Next: NewDownloadsHandler<T>>(self, next: Next) -> Chain<Self, Next> {
+        Chain { first: self, next }
+    }
+
+    /// Only passes the groups matching `predicate` to this handler, skipping it when none match.
+    fn filter<F>(self, predicate: F) -> Filter<Self, F>
+    where
+        F: Fn(&T) -> bool + Send + Sync,
+    {
+        Filter {
+            inner: self,
+            predicate,
+        }
+    }
+
+    /// Transforms every group before passing it to this handler.
+    fn map<F>(self, mapper: F) -> Map<Self, F>
+    where
+        F: Fn(T) -> T + Send + Sync,
+    {
+        Map {
+            inner: self,
+            mapper,
+        }
+    }
+
+    /// Erases the type of this handler so differently shaped pipelines can be used alike.
+    fn boxed(self) -> BoxedHandler<T>
+    where
+        Self: 'static,
+        T: 'static,
+    {
+        BoxedHandler(Box::new(self))
+    }
+}
+
+impl<T, H: NewDownloadsHandler<T>> NewDownloadsHandlerExt<T> for H {}
+
+#[derive(Debug)]
+pub struct Chain<First, Next> {
+    first: First,
+    next: Next,
+}
+
+impl<T, First, Next> NewDownloadsHandler<T> for Chain<First, Next>
+where
+    T: Clone + Send,
+    First: NewDownloadsHandler<T>,
+    Next: NewDownloadsHandler<T>,
+{
+    async fn handle_new_downloads(&self, groups: Vec<T>) -> anyhow::Result<()> {
+        self.first.handle_new_downloads(groups.clone()).await?;
+        self.next.handle_new_downloads(groups).await
+    }
+}
+
+/// Runs all handlers concurrently with the same groups, failing if any of them failed.
+#[derive(Debug)]
+pub struct Fanout<H> {
+    handlers: Vec<H>,
+}
+
+impl<H> Fanout<H> {
+    #[must_use]
+    pub fn new(handlers: Vec<H>) -> Self {
+        Self { handlers }
+    }
+}
+
+impl<T, H> NewDownloadsHandler<T> for Fanout<H>
+where
+    T: Clone + Send + Sync,
+    H: NewDownloadsHandler<T>,
+{
+    async fn handle_new_downloads(&self, groups: Vec<T>) -> anyhow::Result<()> {
+        let results = join_all(
+            self.handlers
+                .iter()
+                .map(|handler| handler.handle_new_downloads(groups.clone())),
+        )
+        .await;
+        let mut failed = 0;
+        for e in results.into_iter().filter_map(Result::err) {
+            error!(error = ?e, "downloads handler failed");
+            failed += 1;
+        }
+        if failed > 0 {
+            return Err(anyhow!(
+                "{failed} of {} downloads handlers failed",
+                self.handlers.len()
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct Filter<H, F> {
+    inner: H,
+    predicate: F,
+}
+
+impl<T, H, F> NewDownloadsHandler<T> for Filter<H, F>
+where
+    T: Send,
+    H: NewDownloadsHandler<T>,
+    F: Fn(&T) -> bool + Send + Sync,
+{
+    async fn handle_new_downloads(&self, groups: Vec<T>) -> anyhow::Result<()> {
+        let groups: Vec<_> = groups.into_iter().filter(&self.predicate).collect();
+        if groups.is_empty() {
+            return Ok(());
+        }
+        self.inner.handle_new_downloads(groups).await
+    }
+}
+
+#[derive(Debug)]
+pub struct Map<H, F> {
+    inner: H,
+    mapper: F,
+}
+
+impl<T, H, F> NewDownloadsHandler<T> for Map<H, F>
+where
+    T: Send,
+    H: NewDownloadsHandler<T>,
+    F: Fn(T) -> T + Send + Sync,
+{
+    async fn handle_new_downloads(&self, groups: Vec<T>) -> anyhow::Result<()> {
+        let groups = groups.into_iter().map(&self.mapper).collect();
+        self.inner.handle_new_downloads(groups).await
+    }
+}
+
+/// Object safe counterpart of [`NewDownloadsHandler`] backing [`BoxedHandler`].
+trait DynNewDownloadsHandler<T>: Send + Sync {
+    fn handle_new_downloads_boxed(&self, groups: Vec<T>) -> BoxFuture<'_, anyhow::Result<()>>;
+}
+
+impl<T: 'static, H: NewDownloadsHandler<T>> DynNewDownloadsHandler<T> for H {
+    fn handle_new_downloads_boxed(&self, groups: Vec<T>) -> BoxFuture<'_, anyhow::Result<()>> {
+        Box::pin(self.handle_new_downloads(groups))
+    }
+}
+
+pub struct BoxedHandler<T = DownloadGroup>(Box<dyn DynNewDownloadsHandler<T>>);
+
+impl<T> NewDownloadsHandler<T> for BoxedHandler<T> {
+    fn handle_new_downloads(
+        &self,
+        groups: Vec<T>,
+    ) -> impl Future<Output = anyhow::Result<()>> + Send {
+        self.0.handle_new_downloads_boxed(groups)
+    }
+}
+
+/// Assembles the pipeline in front of `sink` described by the poller configuration.
+pub fn pipeline<H: NewDownloadsHandler + 'static>(sink: H, config: &PollerConfig) -> BoxedHandler {
+    let mut handler = sink.boxed();
+    if let Some(min_resolution) = config.min_resolution {
+        handler = handler
+            .filter(|group| !group.downloads.is_empty())
+            .map(move |mut group| {
+                group.downloads.retain(|d| d.resolution >= min_resolution);
+                group
+            })
+            .boxed();
+    }
+    if !config.providers.is_empty() {
+        let providers = config.providers.clone();
+        handler = handler
+            .filter(move |group| {
+                providers
+                    .iter()
+                    .any(|provider| provider.eq_ignore_ascii_case(&group.provider))
+            })
+            .boxed();
+    }
+    if !config.exclude_titles.is_empty() {
+        let patterns = title_patterns(&config.exclude_titles);
+        handler = handler
+            .filter(move |group| !patterns.iter().any(|p| p.matches(&group.title)))
+            .boxed();
+    }
+    if !config.titles.is_empty() {
+        let patterns = title_patterns(&config.titles);
+        handler = handler
+            .filter(move |group| patterns.iter().any(|p| p.matches(&group.title)))
+            .boxed();
+    }
+    handler
+}
+
+fn title_patterns(patterns: &[String]) -> Vec<TitlePattern> {
+    patterns.iter().map(|p| TitlePattern::new(p)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    #[derive(Debug, Clone, Default)]
+    struct Recorder {
+        groups: Arc<Mutex<Vec<DownloadGroup>>>,
+        fail: bool,
+    }
+
+    impl Recorder {
+        fn titles(&self) -> Vec<String> {
+            let groups = self.groups.lock().unwrap();
+            groups.iter().map(|g| g.title.clone()).collect()
+        }
+    }
+
+    impl NewDownloadsHandler for Recorder {
+        async fn handle_new_downloads(&self, groups: Vec<DownloadGroup>) -> anyhow::Result<()> {
+            self.groups.lock().unwrap().extend(groups);
+            if self.fail {
+                return Err(anyhow!("recorder failed"));
+            }
+            Ok(())
+        }
+    }
+
+    fn group(title: &str, provider: &str, resolutions: &[u16]) -> DownloadGroup {
+        DownloadGroup::fixture(title)
+            .with_provider(provider)
+            .with_resolutions(resolutions)
+    }
+
+    #[tokio::test]
+    async fn test_chain_stops_after_failure() {
+        let first = Recorder {
+            fail: true,
+            ..Recorder::default()
+        };
+        let next = Recorder::default();
+        let handler = first.clone().chain(next.clone());
+        let result = handler
+            .handle_new_downloads(vec![group("Frieren", "SubsPlease", &[1080])])
+            .await;
+        assert!(result.is_err());
+        assert_eq!(first.titles(), ["Frieren"]);
+        assert!(next.titles().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_fanout_runs_all_handlers() {
+        let failing = Recorder {
+            fail: true,
+            ..Recorder::default()
+        };
+        let succeeding = Recorder::default();
+        let handler = Fanout::new(vec![failing.clone().boxed(), succeeding.clone().boxed()]);
+        let result = handler
+            .handle_new_downloads(vec![group("Frieren", "SubsPlease", &[1080])])
+            .await;
+        assert!(result.is_err());
+        assert_eq!(failing.titles(), ["Frieren"]);
+        assert_eq!(succeeding.titles(), ["Frieren"]);
+    }
+
+    #[tokio::test]
+    async fn test_filter_and_map() {
+        let sink = Recorder::default();
+        let handler = sink
+            .clone()
+            .filter(|g| g.title.starts_with('F'))
+            .map(|mut g| {
+                g.title = g.title.to_uppercase();
+                g
+            });
+        handler
+            .handle_new_downloads(vec![
+                group("Frieren", "SubsPlease", &[1080]),
+                group("fruits basket", "SubsPlease", &[1080]),
+            ])
+            .await
+            .unwrap();
+        assert_eq!(sink.titles(), ["FRIEREN", "FRUITS BASKET"]);
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_from_config() {
+        let sink = Recorder::default();
+        let config = PollerConfig {
+            titles: vec!["*".to_string()],
+            exclude_titles: vec!["Oshi no Ko*".to_string()],
+            providers: vec!["subsplease".to_string()],
+            min_resolution: Some(720),
+        };
+        let handler = pipeline(sink.clone(), &config);
+        handler
+            .handle_new_downloads(vec![
+                group("Frieren", "SubsPlease", &[480, 1080]),
+                group("Oshi no Ko", "SubsPlease", &[1080]),
+                group("Dandadan", "Erai-raws", &[1080]),
+                group("Kaiju No. 8", "SubsPlease", &[480]),
+            ])
+            .await
+            .unwrap();
+        let groups = sink.groups.lock().unwrap();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].title, "Frieren");
+        assert_eq!(groups[0].downloads.len(), 1);
+        assert_eq!(groups[0].downloads[0].resolution, 1080);
+    }
+}
@@ -1,5 +1,6 @@
 use std::default::Default;
 use std::future::Future;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
@@ -13,15 +14,22 @@ use tracing::{debug, info, instrument, trace};
 use datasource::repository;
 
 use crate::datasource;
-use crate::models::DownloadGroup;
-use crate::state::{AppState, DBPool, ReqwestClient};
+use crate::datasource::repository::events::EventKind;
+use crate::jobs::handlers;
+use crate::jobs::handlers::{BoxedHandler, Fanout, NewDownloadsHandlerExt};
+use crate::jobs::webhooks::WebhookDispatcher;
+use crate::models::{DownloadEvent, DownloadGroup};
+use crate::state::{AppState, DBPool, PollerConfig, ReqwestClient};
 
 const DEFAULT_INTERVAL: Duration = Duration::from_mins(5);
+/// Number of polls in a row that have to fail before the poller is reported as unhealthy.
+const UNHEALTHY_AFTER_FAILURES: u32 = 3;
 
-pub trait NewDownloadsHandler: Sized + Send + Sync {
+/// Handles the downloads found by a poll, as groups or as the events persisting them produced.
+pub trait NewDownloadsHandler<T = DownloadGroup>: Sized + Send + Sync {
     fn handle_new_downloads(
         &self,
-        groups: Vec<DownloadGroup>,
+        groups: Vec<T>,
     ) -> impl Future<Output = anyhow::Result<()>> + Send;
 }
 
@@ -29,21 +37,65 @@ pub struct Poller<Handler: NewDownloadsHandler> {
     client: ReqwestClient,
     downloads_handler: Handler,
     last_update: Arc<Mutex<DateTime<Utc>>>,
+    health: PollerHealth,
+}
+
+/// Shared view on whether the poller is still able to fetch and handle new downloads.
+#[derive(Debug, Clone, Default)]
+pub struct PollerHealth {
+    consecutive_failures: Arc<AtomicU32>,
+}
+
+impl PollerHealth {
+    #[must_use]
+    pub fn is_healthy(&self) -> bool {
+        self.consecutive_failures.load(Ordering::Relaxed) < UNHEALTHY_AFTER_FAILURES
+    }
+
+    fn record(&self, success: bool) {
+        if success {
+            self.consecutive_failures.store(0, Ordering::Relaxed);
+        } else {
+            self.consecutive_failures.fetch_add(1, Ordering::Relaxed);
+        }
+    }
 }
 
 impl Poller<PersistentPoller> {
     pub async fn persistent_from_state(state: &AppState) -> anyhow::Result<Self> {
-        let last_update = repository::downloads::last_updated(&state.pool)
-            .await?
-            .unwrap_or_else(Utc::now);
+        let last_update = last_persisted_update(state).await?;
         Ok(Self::new_with_last_updated_at(
             state.client.clone(),
             PersistentPoller::new(state),
             last_update,
-        ))
+        )
+        .with_health(state.poller_health.clone()))
+    }
+}
+
+impl Poller<BoxedHandler> {
+    /// Creates a persistent poller behind the pipeline described by `config`.
+    pub async fn configured_from_state(
+        state: &AppState,
+        config: &PollerConfig,
+    ) -> anyhow::Result<Self> {
+        let last_update = last_persisted_update(state).await?;
+        Ok(Self::new_with_last_updated_at(
+            state.client.clone(),
+            handlers::pipeline(PersistentPoller::new(state), config),
+            last_update,
+        )
+        .with_health(state.poller_health.clone()))
     }
 }
 
+async fn last_persisted_update(state: &AppState) -> anyhow::Result<DateTime<Utc>> {
+    let last_update = repository::downloads::last_updated(&state.pool)
+        .await?
+        .unwrap_or_else(Utc::now);
+    Ok(last_update)
+}
+
 impl<Handler: NewDownloadsHandler + 'static> Poller<Handler> {
     pub fn new(client: ReqwestClient, handler: Handler) -> Self {
         Self::new_with_last_updated_at(client, handler, DateTime::default())
@@ -58,9 +110,22 @@ impl<Handler: NewDownloadsHandler + 'static> Poller<Handler> {
             client,
             downloads_handler: handler,
             last_update: Arc::new(Mutex::new(last_updated_at)),
+            health: PollerHealth::default(),
         }
     }
 
+    /// Reports the outcome of every poll to `health` instead of a private handle.
+    #[must_use]
+    pub fn with_health(mut self, health: PollerHealth) -> Self {
+        self.health = health;
+        self
+    }
+
+    #[must_use]
+    pub fn health(&self) -> PollerHealth {
+        self.health.clone()
+    }
+
     pub fn start(self) -> anyhow::Result<JoinHandle<()>> {
         self.start_with_period(DEFAULT_INTERVAL)
     }
@@ -83,7 +148,9 @@ impl<Handler: NewDownloadsHandler + 'static> Poller<Handler> {
     #[instrument(skip(self))]
     async fn tick(&self) {
         let last_updated_at = *self.last_update.lock().unwrap();
-        if let Ok(last_updated_at) = self.poll_nyaa(last_updated_at).await {
+        let result = self.poll_nyaa(last_updated_at).await;
+        self.health.record(result.is_ok());
+        if let Ok(last_updated_at) = result {
             *self.last_update.lock().unwrap() = last_updated_at;
         }
     }
@@ -136,52 +203,100 @@ fn interval_at_next_period(period: Duration) -> anyhow::Result<Interval> {
 
 #[derive(Debug)]
 pub struct TransientPoller {
-    sender: Sender<DownloadGroup>,
+    sender: Sender<DownloadEvent>,
+    sequence: AtomicU64,
 }
 
 impl TransientPoller {
     #[must_use]
-    pub fn new(sender: Sender<DownloadGroup>) -> Self {
-        Self { sender }
+    pub fn new(sender: Sender<DownloadEvent>) -> Self {
+        Self {
+            sender,
+            sequence: AtomicU64::new(0),
+        }
     }
 }
 
 impl NewDownloadsHandler for TransientPoller {
     async fn handle_new_downloads(&self, groups: Vec<DownloadGroup>) -> anyhow::Result<()> {
         for group in groups {
-            let _ = self.sender.send(group);
+            let sequence = self.sequence.fetch_add(1, Ordering::Relaxed) + 1;
+            // without a database earlier versions are unknown, so nothing counts as revised
+            let _ = self.sender.send(DownloadEvent {
+                sequence,
+                kind: EventKind::Download,
+                group,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Sends events to the subscribers of the downloads channel.
+#[derive(Debug)]
+pub struct Broadcast {
+    sender: Sender<DownloadEvent>,
+}
+
+impl Broadcast {
+    #[must_use]
+    pub fn new(sender: Sender<DownloadEvent>) -> Self {
+        Self { sender }
+    }
+}
+
+impl NewDownloadsHandler<DownloadEvent> for Broadcast {
+    async fn handle_new_downloads(&self, events: Vec<DownloadEvent>) -> anyhow::Result<()> {
+        for event in events {
+            // no receivers is fine, subscribers catch up from the event log
+            let _ = self.sender.send(event);
         }
         Ok(())
     }
 }
 
+/// Persists new downloads along with their events and passes the events on to `next`.
 #[derive(Debug)]
-pub struct PersistentPoller {
+pub struct PersistentPoller<Next = Fanout<BoxedHandler<DownloadEvent>>> {
     database: DBPool,
-    sender: Sender<DownloadGroup>,
+    next: Next,
 }
 
 impl PersistentPoller {
+    /// Persists new downloads, then broadcasts them to subscribers and posts them to webhooks.
     #[must_use]
     pub fn new(state: &AppState) -> Self {
-        Self {
-            database: state.pool.clone(),
-            sender: state.downloads_channel.clone(),
-        }
+        let next = Fanout::new(vec![
+            Broadcast::new(state.downloads_channel.clone()).boxed(),
+            WebhookDispatcher::new(state).boxed(),
+        ]);
+        Self::with_next(state.pool.clone(), next)
     }
+}
 
-    async fn save_downloads(&self, groups: &[DownloadGroup]) -> anyhow::Result<()> {
-        repository::downloads::insert_groups(self.database.clone(), groups).await?;
-        Ok(())
+impl<Next: NewDownloadsHandler<DownloadEvent>> PersistentPoller<Next> {
+    #[must_use]
+    pub fn with_next(database: DBPool, next: Next) -> Self {
+        Self { database, next }
+    }
+
+    async fn save_downloads(
+        &self,
+        groups: Vec<DownloadGroup>,
+    ) -> anyhow::Result<Vec<DownloadEvent>> {
+        // committed at once, so downloads are never known without the events announcing them
+        let mut transaction = self.database.begin().await?;
+        let ids = repository::downloads::insert_groups(&mut transaction, &groups).await?;
+        let events =
+            repository::events::append(&mut transaction, ids.into_iter().zip(groups)).await?;
+        transaction.commit().await?;
+        Ok(events)
     }
 }
 
-impl NewDownloadsHandler for PersistentPoller {
+impl<Next: NewDownloadsHandler<DownloadEvent>> NewDownloadsHandler for PersistentPoller<Next> {
     async fn handle_new_downloads(&self, groups: Vec<DownloadGroup>) -> anyhow::Result<()> {
-        self.save_downloads(&groups).await?;
-        for group in groups {
-            let _ = self.sender.send(group);
-        }
-        Ok(())
+        let events = self.save_downloads(groups).await?;
+        self.next.handle_new_downloads(events).await
     }
 }
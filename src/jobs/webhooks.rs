@@ -0,0 +1,394 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use ahash::RandomState;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use tokio::time::timeout;
+use tracing::{debug, error, instrument, warn};
+use url::Url;
+
+use crate::datasource::repository;
+use crate::datasource::repository::events::EventKind;
+use crate::datasource::repository::webhooks::{WebhookFormat, WebhookTarget};
+use crate::jobs::poller::NewDownloadsHandler;
+use crate::models::{DownloadEvent, DownloadGroup, DownloadVariant};
+use crate::state::{AppState, DBPool, ReqwestClient};
+use crate::subscription::filter::TitlePattern;
+
+mod discord;
+mod slack;
+
+const EVENT_HEADER: &str = "x-webhook-event";
+const SEQUENCE_HEADER: &str = "x-webhook-sequence";
+const SIGNATURE_HEADER: &str = "x-webhook-signature-256";
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_ATTEMPTS: u32 = 5;
+const INITIAL_BACKOFF: Duration = Duration::from_secs(30);
+const POSTER_TIMEOUT: Duration = Duration::from_secs(5);
+const POSTER_CACHE_SIZE: usize = 512;
+
+/// Posts the events of new downloads to the registered webhooks, as the last stage of the poller.
+///
+/// Generic webhooks receive the JSON encoded `DownloadGroup` as body, signed with the webhook
+/// secret using HMAC-SHA256, while Discord and Slack webhooks receive a rendered chat message.
+/// Failed deliveries are retried with exponential backoff and every attempt is recorded in the
+/// delivery log.
+#[derive(Debug, Clone)]
+pub struct WebhookDispatcher {
+    client: ReqwestClient,
+    pool: DBPool,
+    posters: Arc<Mutex<HashMap<String, Option<Url>, RandomState>>>,
+}
+
+/// A single request to a webhook, retried until it succeeds or runs out of attempts.
+#[derive(Debug, Clone)]
+struct Delivery {
+    url: String,
+    sequence: u64,
+    kind: EventKind,
+    body: Vec<u8>,
+    signature: Option<String>,
+}
+
+impl WebhookDispatcher {
+    #[must_use]
+    pub fn new(state: &AppState) -> Self {
+        Self {
+            client: state.client.clone(),
+            pool: state.pool.clone(),
+            posters: Arc::default(),
+        }
+    }
+
+    /// Starts delivering `event` to those of the enabled `targets` whose title patterns match.
+    #[instrument(skip_all, fields(sequence = event.sequence))]
+    async fn dispatch(&self, targets: &[WebhookTarget], event: DownloadEvent) {
+        let targets: Vec<_> = targets
+            .iter()
+            .filter(|target| matches_title(target, &event.group.title))
+            .collect();
+        if targets.is_empty() {
+            return;
+        }
+        let poster = if targets.iter().any(|t| t.format != WebhookFormat::Generic) {
+            self.poster(&event.group.title).await
+        } else {
+            None
+        };
+        for target in targets {
+            let body = match render(target.format, &event.group, poster.as_ref()) {
+                Ok(body) => body,
+                Err(e) => {
+                    error!(error = ?e, webhook = %target.id, "failed to render webhook payload");
+                    continue;
+                }
+            };
+            let delivery = Delivery {
+                signature: target.secret.as_deref().map(|secret| sign(secret, &body)),
+                url: target.url.clone(),
+                sequence: event.sequence,
+                kind: event.kind,
+                body,
+            };
+            let dispatcher = self.clone();
+            let webhook_id = target.id;
+            tokio::task::spawn(async move {
+                dispatcher.deliver(webhook_id, delivery).await;
+            });
+        }
+    }
+
+    #[instrument(skip(self, delivery))]
+    async fn deliver(&self, webhook_id: uuid::Uuid, delivery: Delivery) {
+        let mut backoff = INITIAL_BACKOFF;
+        for attempt in 1..=MAX_ATTEMPTS {
+            let (status_code, error) = send(&self.client, &delivery).await;
+            if let Err(e) = repository::webhooks::insert_delivery(
+                &self.pool,
+                webhook_id,
+                delivery.sequence,
+                attempt,
+                status_code,
+                error.as_deref(),
+            )
+            .await
+            {
+                error!(error = ?e, "failed to record webhook delivery");
+            }
+            let Some(error) = error else {
+                debug!(attempt, "delivered webhook");
+                return;
+            };
+            warn!(attempt, error, "webhook delivery failed");
+            if attempt < MAX_ATTEMPTS {
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+            }
+        }
+        error!("giving up webhook delivery after {MAX_ATTEMPTS} attempts");
+    }
+
+    /// Looks up the Kitsu poster of a show, remembering the result per title.
+    async fn poster(&self, title: &str) -> Option<Url> {
+        if let Some(poster) = self.posters.lock().unwrap().get(title) {
+            return poster.clone();
+        }
+        let search = kitsu::anime::search(&self.client, title, 1);
+        let poster = match timeout(POSTER_TIMEOUT, search).await {
+            Ok(Ok(collection)) => collection.data.into_iter().next().map(|anime| {
+                let images = anime.attributes.poster_image;
+                images.small.unwrap_or(images.original)
+            }),
+            Ok(Err(e)) => {
+                warn!(error = ?e, title, "failed to look up poster");
+                return None;
+            }
+            Err(_) => {
+                warn!(title, "timed out looking up poster");
+                return None;
+            }
+        };
+        let mut posters = self.posters.lock().unwrap();
+        if posters.len() >= POSTER_CACHE_SIZE {
+            posters.clear();
+        }
+        posters.insert(title.to_string(), poster.clone());
+        poster
+    }
+}
+
+impl NewDownloadsHandler<DownloadEvent> for WebhookDispatcher {
+    /// Dispatches every event, logging instead of failing so the poll is not retried for
+    /// downloads that were already persisted.
+    async fn handle_new_downloads(&self, events: Vec<DownloadEvent>) -> anyhow::Result<()> {
+        // loaded once for all events of the poll instead of once per event
+        let targets = match repository::webhooks::enabled_targets(&self.pool).await {
+            Ok(targets) => targets,
+            Err(e) => {
+                error!(error = ?e, "failed to load webhooks");
+                return Ok(());
+            }
+        };
+        if targets.is_empty() {
+            return Ok(());
+        }
+        for event in events {
+            self.dispatch(&targets, event).await;
+        }
+        Ok(())
+    }
+}
+
+/// Sends a single delivery attempt, returning the response status code and an error message
+/// when the attempt failed.
+async fn send(client: &ReqwestClient, delivery: &Delivery) -> (Option<u16>, Option<String>) {
+    let mut request = client
+        .post(&delivery.url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header(EVENT_HEADER, delivery.kind.name())
+        .header(SEQUENCE_HEADER, delivery.sequence)
+        .timeout(DELIVERY_TIMEOUT)
+        .body(delivery.body.clone());
+    if let Some(signature) = &delivery.signature {
+        request = request.header(SIGNATURE_HEADER, signature);
+    }
+    match request.send().await {
+        Ok(response) if response.status().is_success() => (Some(response.status().as_u16()), None),
+        Ok(response) => (
+            Some(response.status().as_u16()),
+            Some(format!("unexpected status code {}", response.status())),
+        ),
+        Err(e) => (None, Some(e.to_string())),
+    }
+}
+
+fn matches_title(target: &WebhookTarget, title: &str) -> bool {
+    target.title_patterns.is_empty()
+        || target
+            .title_patterns
+            .iter()
+            .any(|pattern| TitlePattern::new(pattern).matches(title))
+}
+
+fn render(
+    format: WebhookFormat,
+    group: &DownloadGroup,
+    poster: Option<&Url>,
+) -> serde_json::Result<Vec<u8>> {
+    match format {
+        WebhookFormat::Generic => serde_json::to_vec(group),
+        WebhookFormat::Discord => serde_json::to_vec(&discord::message(group, poster)),
+        WebhookFormat::Slack => serde_json::to_vec(&slack::message(group, poster)),
+    }
+}
+
+/// Computes the value of the signature header, `sha256=` followed by the hex encoded
+/// HMAC-SHA256 of the body.
+fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("hmac accepts keys of any size");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+fn headline(group: &DownloadGroup) -> String {
+    match &group.variant {
+        DownloadVariant::Movie => group.title.clone(),
+        variant => format!("{} - {}", group.title, variant_label(variant)),
+    }
+}
+
+fn variant_label(variant: &DownloadVariant) -> String {
+    match variant {
+        DownloadVariant::Batch(range) => format!("Episodes {}-{}", range.start(), range.end()),
+        DownloadVariant::Episode(episode) => format!(
+            "Episode {}{}{}{}",
+            episode.episode,
+            episode.decimal.map(|d| format!(".{d}")).unwrap_or_default(),
+            episode.version.map(|v| format!("v{v}")).unwrap_or_default(),
+            episode
+                .extra
+                .as_ref()
+                .map(|e| format!(" {e}"))
+                .unwrap_or_default(),
+        ),
+        DownloadVariant::Movie => "Movie".to_string(),
+    }
+}
+
+fn resolutions(group: &DownloadGroup) -> String {
+    let mut resolutions: Vec<_> = group.downloads.iter().map(|d| d.resolution).collect();
+    resolutions.sort_unstable_by(|a, b| b.cmp(a));
+    resolutions.dedup();
+    resolutions
+        .iter()
+        .map(|r| format!("{r}p"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::Router;
+    use axum::body::Bytes;
+    use axum::http::HeaderMap;
+    use axum::routing::post;
+    use serde_json::Value;
+    use tokio::sync::mpsc;
+
+    use crate::models::{Download, Episode};
+
+    use super::*;
+
+    fn group() -> DownloadGroup {
+        let download = |resolution: u16| {
+            Download::fixture(resolution)
+                .with_file_name(&format!("[SubsPlease] Frieren - 07v2 ({resolution}p).mkv"))
+        };
+        DownloadGroup::fixture("Frieren")
+            .with_variant(DownloadVariant::Episode(Episode {
+                version: Some(2),
+                ..Episode::fixture(7)
+            }))
+            .with_download(download(720))
+            .with_download(download(1080))
+    }
+
+    fn target(format: WebhookFormat, title_patterns: &[&str]) -> WebhookTarget {
+        WebhookTarget {
+            id: uuid::Uuid::nil(),
+            url: String::new(),
+            secret: None,
+            format,
+            title_patterns: title_patterns.iter().map(ToString::to_string).collect(),
+        }
+    }
+
+    #[test]
+    fn test_sign() {
+        let signature = sign("key", b"The quick brown fox jumps over the lazy dog");
+        assert_eq!(
+            signature,
+            "sha256=f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+    }
+
+    #[test]
+    fn test_matches_title() {
+        assert!(matches_title(&target(WebhookFormat::Slack, &[]), "Frieren"));
+        assert!(matches_title(
+            &target(WebhookFormat::Slack, &["oshi*", "frieren"]),
+            "Frieren"
+        ));
+        assert!(!matches_title(
+            &target(WebhookFormat::Slack, &["oshi*"]),
+            "Frieren"
+        ));
+    }
+
+    #[test]
+    fn test_render_discord() {
+        let poster: Url = "https://media.kitsu.app/anime/1/poster.jpg"
+            .parse()
+            .unwrap();
+        let body = render(WebhookFormat::Discord, &group(), Some(&poster)).unwrap();
+        let json: Value = serde_json::from_slice(&body).unwrap();
+        let embed = &json["embeds"][0];
+        assert_eq!(embed["title"], "Frieren - Episode 7v2");
+        assert_eq!(embed["thumbnail"]["url"], poster.as_str());
+        assert_eq!(embed["fields"][1]["value"], "1080p, 720p");
+        assert_eq!(embed["footer"]["text"], "SubsPlease");
+    }
+
+    #[test]
+    fn test_render_slack() {
+        let body = render(WebhookFormat::Slack, &group(), None).unwrap();
+        let json: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["text"], "New release: Frieren - Episode 7v2");
+        assert_eq!(json["blocks"][0]["type"], "header");
+        assert_eq!(json["blocks"][0]["text"]["text"], "Frieren - Episode 7v2");
+        assert_eq!(
+            json["blocks"][1]["fields"][1]["text"],
+            "*Resolutions*\n1080p, 720p"
+        );
+        assert!(json["blocks"][1].get("accessory").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_send_to_local_server() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let app = Router::new().route(
+            "/hook",
+            post(async move |headers: HeaderMap, body: Bytes| {
+                tx.send((headers, body)).unwrap();
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let body = render(WebhookFormat::Generic, &group(), None).unwrap();
+        let delivery = Delivery {
+            url: format!("http://{address}/hook"),
+            sequence: 42,
+            kind: EventKind::Revised,
+            signature: Some(sign("0123456789abcdef", &body)),
+            body,
+        };
+        let (status_code, error) = send(&ReqwestClient::new(), &delivery).await;
+        assert_eq!(status_code, Some(200));
+        assert_eq!(error, None);
+
+        let (headers, body) = rx.recv().await.unwrap();
+        assert_eq!(headers[EVENT_HEADER], "revised");
+        assert_eq!(headers[SEQUENCE_HEADER], "42");
+        assert_eq!(
+            headers[SIGNATURE_HEADER].to_str().unwrap(),
+            sign("0123456789abcdef", &body)
+        );
+        let group: DownloadGroup = serde_json::from_slice(&body).unwrap();
+        assert_eq!(group.title, "Frieren");
+    }
+}
@@ -0,0 +1,89 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use url::Url;
+
+use crate::models::DownloadGroup;
+
+use super::{headline, resolutions, variant_label};
+
+/// Accent colour of the embed, the Nyaa blue.
+const EMBED_COLOR: u32 = 0x0031_7EB0;
+
+#[derive(Debug, Serialize)]
+pub(super) struct Message {
+    embeds: Vec<Embed>,
+}
+
+#[derive(Debug, Serialize)]
+struct Embed {
+    title: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option<String>,
+    color: u32,
+    timestamp: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thumbnail: Option<Image>,
+    fields: Vec<Field>,
+    footer: Footer,
+}
+
+#[derive(Debug, Serialize)]
+struct Image {
+    url: Url,
+}
+
+#[derive(Debug, Serialize)]
+struct Field {
+    name: &'static str,
+    value: String,
+    inline: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct Footer {
+    text: String,
+}
+
+pub(super) fn message(group: &DownloadGroup, poster: Option<&Url>) -> Message {
+    let links = group
+        .downloads
+        .iter()
+        .map(|d| {
+            format!(
+                "**{}p** [torrent]({}) · [comments]({})",
+                d.resolution, d.torrent, d.comments
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    let embed = Embed {
+        title: headline(group),
+        url: group.downloads.first().map(|d| d.comments.clone()),
+        color: EMBED_COLOR,
+        timestamp: group.updated_at,
+        thumbnail: poster.map(|url| Image { url: url.clone() }),
+        fields: vec![
+            Field {
+                name: "Release",
+                value: variant_label(&group.variant),
+                inline: true,
+            },
+            Field {
+                name: "Resolutions",
+                value: resolutions(group),
+                inline: true,
+            },
+            Field {
+                name: "Links",
+                value: links,
+                inline: false,
+            },
+        ],
+        footer: Footer {
+            text: group.provider.clone(),
+        },
+    };
+    Message {
+        embeds: vec![embed],
+    }
+}
@@ -0,0 +1,101 @@
+use serde::Serialize;
+use url::Url;
+
+use crate::models::DownloadGroup;
+
+use super::{headline, resolutions, variant_label};
+
+#[derive(Debug, Serialize)]
+pub(super) struct Message {
+    /// Fallback shown in notifications and clients that cannot render blocks.
+    text: String,
+    blocks: Vec<Block>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Block {
+    Header {
+        text: Text,
+    },
+    Section {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        text: Option<Text>,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        fields: Vec<Text>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        accessory: Option<Accessory>,
+    },
+    Context {
+        elements: Vec<Text>,
+    },
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Text {
+    PlainText { text: String },
+    Mrkdwn { text: String },
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Accessory {
+    Image { image_url: Url, alt_text: String },
+}
+
+pub(super) fn message(group: &DownloadGroup, poster: Option<&Url>) -> Message {
+    let title = headline(group);
+    let links = group
+        .downloads
+        .iter()
+        .map(|d| {
+            format!(
+                "*{}p* <{}|torrent> · <{}|comments>",
+                d.resolution, d.torrent, d.comments
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    let blocks = vec![
+        Block::Header {
+            text: Text::PlainText {
+                text: title.clone(),
+            },
+        },
+        Block::Section {
+            text: None,
+            fields: vec![
+                Text::Mrkdwn {
+                    text: format!("*Release*\n{}", variant_label(&group.variant)),
+                },
+                Text::Mrkdwn {
+                    text: format!("*Resolutions*\n{}", resolutions(group)),
+                },
+            ],
+            accessory: poster.map(|url| Accessory::Image {
+                image_url: url.clone(),
+                alt_text: group.title.clone(),
+            }),
+        },
+        Block::Section {
+            text: Some(Text::Mrkdwn { text: links }),
+            fields: Vec::new(),
+            accessory: None,
+        },
+        Block::Context {
+            elements: vec![Text::Mrkdwn {
+                text: format!(
+                    "{} · <!date^{}^{{date_short_pretty}} {{time}}|{}>",
+                    group.provider,
+                    group.updated_at.timestamp(),
+                    group.updated_at.to_rfc2822(),
+                ),
+            }],
+        },
+    ];
+    Message {
+        text: format!("New release: {title}"),
+        blocks,
+    }
+}
@@ -1,34 +1,52 @@
+use std::convert::Infallible;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::Result;
 use axum::body::Body;
-use axum::http::{HeaderValue, Request};
+use axum::extract::ConnectInfo;
+use axum::http::{HeaderName, HeaderValue, Method, Request};
+use axum::middleware::from_fn_with_state;
 use axum::response::NoContent;
-use axum::{Router as AxumRouter, Router, routing::get};
-use reqwest::header::CONTENT_TYPE;
+use axum::routing::get;
+use axum::serve::IncomingStream;
+use axum::{Extension, Router as AxumRouter, Router};
+use reqwest::header::{ACCESS_CONTROL_REQUEST_HEADERS, CONTENT_TYPE};
 use tokio::net::TcpListener;
 use tokio::sync::broadcast::Sender;
-use tower::ServiceBuilder;
-use tower::make::Shared;
+use tonic_web::GrpcWebLayer;
 use tower::steer::Steer;
+use tower::{Layer, ServiceBuilder};
 use tower_http::compression::predicate::NotForContentType;
 use tower_http::compression::{DefaultPredicate, Predicate};
+use tower_http::cors::{AllowOrigin, CorsLayer};
 use tower_http::{
     compression::CompressionLayer, decompression::DecompressionLayer, trace::TraceLayer,
 };
 use tracing::info;
+use utoipa::OpenApi;
+use utoipa_swagger_ui::SwaggerUi;
 
-use state::AppState;
+use auth::ApiKeys;
+use jobs::poller::PollerHealth;
+use rate_limit::{RateLimitLayer, RateLimits};
+use state::{AppState, CorsConfig, DBPool, ReqwestClient, SubscriptionConfig};
 
+use crate::controllers::openapi::ApiDoc;
 use crate::controllers::rest::anime;
 
+pub mod auth;
+mod completeness;
 mod controllers;
 mod datasource;
 pub mod errors;
 pub mod jobs;
 pub mod models;
+pub mod rate_limit;
+mod schedule;
 pub mod state;
+mod subscription;
 
 static SOCKET: &SocketAddr = &SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 8000);
 
@@ -40,104 +58,455 @@ fn setup_rustls() {
 
 pub async fn serve_axum(app_state: AppState) -> Result<()> {
     setup_rustls();
+    start_maintenance(
+        &app_state.api_keys,
+        Some(app_state.pool.clone()),
+        &app_state.rate_limits,
+    );
     let router = create_axum_router(app_state);
     let listener = TcpListener::bind(SOCKET).await?;
     tracing::debug!("listening on {SOCKET}");
-    axum::serve(listener, router.into_make_service()).await?;
+    axum::serve(
+        listener,
+        router.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
     Ok(())
 }
 
-pub async fn serve_tonic(sender: Sender<models::DownloadGroup>) -> Result<()> {
+/// Serves the gRPC services without download history.
+///
+/// The api keys stored in the database are only known when `api_key_pool` is given, otherwise
+/// just the admin key is.
+pub async fn serve_tonic(
+    client: ReqwestClient,
+    sender: Sender<models::DownloadEvent>,
+    subscriptions: SubscriptionConfig,
+    poller_health: PollerHealth,
+    api_keys: ApiKeys,
+    api_key_pool: Option<DBPool>,
+    rate_limits: RateLimits,
+) -> Result<()> {
     setup_rustls();
-    let router = create_tonic_router(sender);
+    start_maintenance(&api_keys, api_key_pool, &rate_limits);
+    let router = create_tonic_router(
+        client,
+        sender,
+        None,
+        subscriptions,
+        poller_health,
+        api_keys,
+        rate_limits,
+    )
+    .layer(grpc_web_layer(&CorsConfig::from_env()?)?);
     let listener = TcpListener::bind(SOCKET).await?;
     info!("Listening on {SOCKET}");
-    axum::serve(listener, router).await?;
+    axum::serve(
+        listener,
+        router.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
     Ok(())
 }
 
 pub async fn serve_combined(app_state: AppState) -> Result<()> {
     setup_rustls();
-    let tonic_router = create_tonic_router(app_state.downloads_channel.clone());
+    start_maintenance(
+        &app_state.api_keys,
+        Some(app_state.pool.clone()),
+        &app_state.rate_limits,
+    );
+    let tonic_router = create_tonic_router(
+        app_state.client.clone(),
+        app_state.downloads_channel.clone(),
+        Some(app_state.pool.clone()),
+        app_state.subscriptions,
+        app_state.poller_health.clone(),
+        app_state.api_keys.clone(),
+        app_state.rate_limits.clone(),
+    )
+    .layer(grpc_web_layer(&CorsConfig::from_env()?)?);
     let axum_router = create_axum_router(app_state);
 
     let http_grpc = Steer::new(
         vec![axum_router, tonic_router],
         |req: &Request<Body>, _services: &[_]| {
+            // matches gRPC as well as gRPC-Web, `application/grpc-web` and `application/grpc-web-text`
             let is_grpc = req
                 .headers()
                 .get(CONTENT_TYPE)
                 .map(HeaderValue::as_bytes)
                 .is_some_and(|content_type| content_type.starts_with(b"application/grpc"));
             // 0 -> http, 1 -> grpc
-            usize::from(is_grpc)
+            usize::from(is_grpc || is_grpc_web_preflight(req))
         },
     );
 
+    // attaches the address of the client like `into_make_service_with_connect_info` does, so
+    // both services can rate limit by it
+    let make_service = tower::service_fn(move |stream: IncomingStream<'_, TcpListener>| {
+        let connect_info = Extension(ConnectInfo(*stream.remote_addr()));
+        std::future::ready(Ok::<_, Infallible>(connect_info.layer(http_grpc.clone())))
+    });
     let listener = TcpListener::bind(SOCKET).await?;
     info!("Listening on {SOCKET}");
-    axum::serve(listener, Shared::new(http_grpc)).await?;
+    axum::serve(listener, make_service).await?;
     Ok(())
 }
 
+/// Starts reloading the api keys from `pool` and forgetting the rate limits of idle clients.
+fn start_maintenance(api_keys: &ApiKeys, pool: Option<DBPool>, rate_limits: &RateLimits) {
+    if let Some(pool) = pool {
+        tokio::spawn(api_keys.clone().refresh(pool));
+    }
+    tokio::spawn(rate_limits.clone().forget_idle());
+}
+
+/// Whether the request is a CORS preflight of a browser about to make a gRPC-Web call.
+fn is_grpc_web_preflight(req: &Request<Body>) -> bool {
+    req.method() == Method::OPTIONS
+        && req
+            .headers()
+            .get_all(ACCESS_CONTROL_REQUEST_HEADERS)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|header| header.trim().eq_ignore_ascii_case("x-grpc-web"))
+}
+
+/// Translates gRPC-Web requests for the tonic services and answers their CORS preflights.
+fn grpc_web_layer(config: &CorsConfig) -> Result<(CorsLayer, GrpcWebLayer)> {
+    let allow_origin = if config.allowed_origins.is_empty() {
+        AllowOrigin::any()
+    } else {
+        let origins: Result<Vec<HeaderValue>, _> =
+            config.allowed_origins.iter().map(|o| o.parse()).collect();
+        AllowOrigin::list(origins?)
+    };
+    let cors = CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_methods([Method::POST])
+        .allow_headers([
+            CONTENT_TYPE,
+            HeaderName::from_static("x-grpc-web"),
+            HeaderName::from_static("x-user-agent"),
+            HeaderName::from_static("grpc-timeout"),
+        ])
+        .expose_headers([
+            HeaderName::from_static("grpc-status"),
+            HeaderName::from_static("grpc-message"),
+            HeaderName::from_static("grpc-status-details-bin"),
+        ])
+        .max_age(Duration::from_hours(24));
+    Ok((cors, GrpcWebLayer::new()))
+}
+
 pub fn create_axum_router(app_state: AppState) -> AxumRouter {
     let compression_predicate =
         DefaultPredicate::new().and(NotForContentType::const_new("text/event-stream"));
+    let rate_limit = RateLimitLayer::new(app_state.rate_limits.clone(), app_state.api_keys.clone());
 
     AxumRouter::new()
-        .nest("/v1", v1_routes())
+        .nest("/v1", v1_routes(&app_state.api_keys))
+        .route("/torznab/api", get(controllers::torznab::api))
+        // also serves the document itself at `/v1/openapi.json`
+        .merge(SwaggerUi::new("/v1/docs").url("/v1/openapi.json", ApiDoc::openapi()))
         .with_state(app_state)
         .layer(
             ServiceBuilder::new()
                 .layer(TraceLayer::new_for_http())
+                .layer(rate_limit)
                 .layer(CompressionLayer::new().compress_when(compression_predicate))
                 .layer(DecompressionLayer::new()),
         )
 }
 
-pub fn v1_routes() -> Router<AppState> {
-    use controllers::rest::{batch, downloads, episode, movie};
+pub fn v1_routes(keys: &ApiKeys) -> Router<AppState> {
+    use controllers::rest::{api_keys, calendar, downloads, schedules, watchlist, webhooks};
+
+    let read = || from_fn_with_state(keys.clone(), auth::require_read);
+    let subscribe = || from_fn_with_state(keys.clone(), auth::require_subscribe);
+    let admin = || from_fn_with_state(keys.clone(), auth::require_admin);
 
     AxumRouter::new()
         .route("/health", get(async || NoContent))
+        .route("/calendar.ics", get(calendar::calendar).route_layer(read()))
         .nest(
             "/shows",
             AxumRouter::new()
                 .route("/", get(anime::find))
-                .route("/{id}", get(anime::by_id)),
+                .route("/schedule", get(schedules::this_week))
+                .route("/{id}", get(anime::by_id))
+                .route("/{title}/schedule", get(schedules::by_title))
+                .route("/{title}/downloads", get(downloads::by_show))
+                .route_layer(read()),
         )
+        .nest("/downloads", download_routes(keys))
         .nest(
-            "/downloads",
+            "/webhooks",
             AxumRouter::new()
-                .route("/", get(downloads::find_downloads))
-                .route("/updates", get(downloads::get_downloads_events))
-                .nest(
-                    "/batches",
-                    AxumRouter::new()
-                        .route("/", get(batch::find_downloads))
-                        .route("/updates", get(batch::get_downloads_events)),
+                .route("/", get(webhooks::find).post(webhooks::create))
+                .route(
+                    "/{id}",
+                    get(webhooks::by_id)
+                        .patch(webhooks::update)
+                        .delete(webhooks::delete),
                 )
-                .nest(
-                    "/episodes",
-                    AxumRouter::new()
-                        .route("/", get(episode::find_downloads))
-                        .route("/updates", get(episode::get_downloads_events)),
+                .route("/{id}/deliveries", get(webhooks::deliveries))
+                .route_layer(admin()),
+        )
+        .nest(
+            "/watchlist",
+            AxumRouter::new()
+                .route("/", get(watchlist::find).post(watchlist::create))
+                .route(
+                    "/{id}",
+                    get(watchlist::by_id)
+                        .patch(watchlist::update)
+                        .delete(watchlist::delete),
                 )
-                .nest(
-                    "/movies",
-                    AxumRouter::new()
-                        .route("/", get(movie::find_downloads))
-                        .route("/updates", get(movie::get_downloads_events)),
+                .route_layer(read()),
+        )
+        .nest(
+            "/me",
+            AxumRouter::new()
+                .route("/downloads", get(watchlist::downloads).route_layer(read()))
+                .route(
+                    "/downloads/updates",
+                    get(watchlist::get_downloads_events).route_layer(subscribe()),
+                ),
+        )
+        .nest(
+            "/keys",
+            AxumRouter::new()
+                .route("/", get(api_keys::find).post(api_keys::create))
+                .route("/{id}", get(api_keys::by_id).delete(api_keys::delete))
+                .route_layer(admin()),
+        )
+}
+
+/// The download routes, which need the read scope except for the update streams.
+fn download_routes(keys: &ApiKeys) -> Router<AppState> {
+    use controllers::rest::{batch, downloads, episode, movie};
+    use controllers::websocket;
+
+    let read = || from_fn_with_state(keys.clone(), auth::require_read);
+    let subscribe = || from_fn_with_state(keys.clone(), auth::require_subscribe);
+
+    AxumRouter::new()
+        .route("/", get(downloads::find_downloads))
+        .route("/feed.rss", get(downloads::rss_feed))
+        .route("/feed.atom", get(downloads::atom_feed))
+        .route("/gaps", get(downloads::gaps))
+        .route("/{id}", get(downloads::by_id))
+        .route_layer(read())
+        .route(
+            "/updates",
+            get(downloads::get_downloads_events).route_layer(subscribe()),
+        )
+        .route("/ws", get(websocket::downloads).route_layer(subscribe()))
+        .nest(
+            "/batches",
+            AxumRouter::new()
+                .route("/", get(batch::find_downloads))
+                .route("/feed.rss", get(batch::rss_feed))
+                .route("/feed.atom", get(batch::atom_feed))
+                .route_layer(read())
+                .route(
+                    "/updates",
+                    get(batch::get_downloads_events).route_layer(subscribe()),
+                ),
+        )
+        .nest(
+            "/episodes",
+            AxumRouter::new()
+                .route("/", get(episode::find_downloads))
+                .route("/feed.rss", get(episode::rss_feed))
+                .route("/feed.atom", get(episode::atom_feed))
+                .route_layer(read())
+                .route(
+                    "/updates",
+                    get(episode::get_downloads_events).route_layer(subscribe()),
+                ),
+        )
+        .nest(
+            "/movies",
+            AxumRouter::new()
+                .route("/", get(movie::find_downloads))
+                .route("/feed.rss", get(movie::rss_feed))
+                .route("/feed.atom", get(movie::atom_feed))
+                .route_layer(read())
+                .route(
+                    "/updates",
+                    get(movie::get_downloads_events).route_layer(subscribe()),
                 ),
         )
 }
 
-pub fn create_tonic_router(sender: Sender<models::DownloadGroup>) -> Router {
-    use controllers::grpc::DownloadService;
+pub fn create_tonic_router(
+    client: ReqwestClient,
+    sender: Sender<models::DownloadEvent>,
+    pool: Option<state::DBPool>,
+    subscriptions: SubscriptionConfig,
+    poller_health: PollerHealth,
+    api_keys: ApiKeys,
+    rate_limits: RateLimits,
+) -> Router {
+    use auth::ApiKeyInterceptor;
+    use controllers::grpc::{DownloadService, ShowService, health};
     use proto::api::v2::downloads_server::DownloadsServer as V2DownloadsServer;
+    use proto::api::v2::shows_server::ShowsServer as V2ShowsServer;
+    use proto::api::v3::downloads_server::DownloadsServer as V3DownloadsServer;
+    use tonic::server::NamedService;
+    use tonic::service::interceptor::InterceptedService;
 
-    let service = Arc::new(DownloadService { sender });
+    let (health_reporter, health_service) = tonic_health::server::health_reporter();
+    tokio::spawn(health::monitor(
+        health_reporter,
+        vec![
+            <V2DownloadsServer<DownloadService> as NamedService>::NAME,
+            <V2ShowsServer<ShowService> as NamedService>::NAME,
+            <V3DownloadsServer<DownloadService> as NamedService>::NAME,
+        ],
+        pool.clone(),
+        poller_health,
+    ));
+    let reflection = || {
+        tonic_reflection::server::Builder::configure()
+            .register_encoded_file_descriptor_set(proto::FILE_DESCRIPTOR_SET)
+            .register_encoded_file_descriptor_set(tonic_health::pb::FILE_DESCRIPTOR_SET)
+    };
+
+    let shows = Arc::new(ShowService {
+        pool: pool.clone(),
+        client: client.clone(),
+    });
+    let rate_limit = RateLimitLayer::new(rate_limits.clone(), api_keys.clone());
+    let service = Arc::new(DownloadService {
+        sender,
+        pool,
+        client,
+        client_buffer: subscriptions.client_buffer.get(),
+        rate_limits,
+    });
+    // health and reflection stay anonymous, so probes and tooling work without a key
+    let interceptor = ApiKeyInterceptor::new(api_keys);
     let mut builder = tonic::service::Routes::builder();
-    builder.add_service(V2DownloadsServer::from_arc(service));
-    builder.routes().into_axum_router()
+    builder.add_service(InterceptedService::new(
+        V2DownloadsServer::from_arc(service.clone()),
+        interceptor.clone(),
+    ));
+    builder.add_service(InterceptedService::new(
+        V3DownloadsServer::from_arc(service),
+        interceptor.clone(),
+    ));
+    builder.add_service(InterceptedService::new(
+        V2ShowsServer::from_arc(shows),
+        interceptor,
+    ));
+    builder.add_service(health_service);
+    builder.add_service(
+        reflection()
+            .build_v1()
+            .expect("descriptor sets should be valid"),
+    );
+    builder.add_service(
+        reflection()
+            .build_v1alpha()
+            .expect("descriptor sets should be valid"),
+    );
+    builder.routes().into_axum_router().layer(rate_limit)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::{NonZeroU32, NonZeroUsize};
+
+    use axum::http::StatusCode;
+    use sqlx::postgres::PgPoolOptions;
+    use tokio::sync::broadcast;
+    use tower::ServiceExt;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::datasource::repository::api_keys::ApiKeyScope;
+    use crate::state::{AuthConfig, RateLimitConfig};
+
+    /// The v1 routes with an admin key, a stored key per scope and read access for anonymous
+    /// clients, backed by a database that cannot be reached.
+    fn routes() -> Router {
+        let api_keys = ApiKeys::new(&AuthConfig {
+            admin_key: Some("admin".to_string()),
+            anonymous_scopes: vec![ApiKeyScope::Read],
+        });
+        api_keys.insert_stored("reader", Uuid::from_u128(1), vec![ApiKeyScope::Read]);
+        api_keys.insert_stored(
+            "subscriber",
+            Uuid::from_u128(2),
+            vec![ApiKeyScope::Subscribe],
+        );
+        let pool = PgPoolOptions::new()
+            .acquire_timeout(Duration::from_millis(100))
+            .connect_lazy("postgres://127.0.0.1:1/anime")
+            .unwrap();
+        let state = AppState {
+            client: ReqwestClient::new(),
+            pool,
+            downloads_channel: broadcast::channel(1).0,
+            subscriptions: SubscriptionConfig {
+                channel_capacity: NonZeroUsize::MIN,
+                client_buffer: NonZeroUsize::MIN,
+            },
+            poller_health: PollerHealth::default(),
+            api_keys: api_keys.clone(),
+            rate_limits: RateLimits::new(&RateLimitConfig {
+                requests_per_minute: NonZeroU32::MAX,
+                burst: NonZeroU32::MAX,
+                max_subscriptions: NonZeroUsize::MIN,
+                trust_forwarded_headers: false,
+            }),
+        };
+        v1_routes(&api_keys).with_state(state)
+    }
+
+    async fn status(path: &str, key: Option<&str>) -> StatusCode {
+        let mut request = Request::get(path);
+        if let Some(key) = key {
+            request = request.header("x-api-key", key);
+        }
+        let request = request.body(Body::empty()).unwrap();
+        routes().oneshot(request).await.unwrap().status()
+    }
+
+    /// Whether a request got past the api key checks, to fail reaching the database instead.
+    fn allowed(status: StatusCode) -> bool {
+        status != StatusCode::UNAUTHORIZED && status != StatusCode::FORBIDDEN
+    }
+
+    #[tokio::test]
+    async fn test_webhooks_require_admin() {
+        assert_eq!(status("/webhooks", None).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            status("/webhooks", Some("reader")).await,
+            StatusCode::FORBIDDEN
+        );
+        assert!(allowed(status("/webhooks", Some("admin")).await));
+    }
+
+    #[tokio::test]
+    async fn test_watchlist_requires_stored_key() {
+        for path in ["/watchlist", "/me/downloads"] {
+            assert_eq!(status(path, None).await, StatusCode::UNAUTHORIZED);
+            assert_eq!(
+                status(path, Some("unknown")).await,
+                StatusCode::UNAUTHORIZED
+            );
+            assert_eq!(
+                status(path, Some("subscriber")).await,
+                StatusCode::FORBIDDEN
+            );
+            assert_eq!(status(path, Some("admin")).await, StatusCode::BAD_REQUEST);
+            assert!(allowed(status(path, Some("reader")).await));
+        }
+    }
 }
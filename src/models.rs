@@ -1,13 +1,19 @@
 use std::num::ParseIntError;
 use std::ops::RangeInclusive;
 
-use chrono::{DateTime, Utc};
-use serde::Serialize;
+use chrono::{DateTime, NaiveTime, Utc, Weekday};
+use serde::{Deserialize, Serialize};
 use url::Url;
+use utoipa::ToSchema;
+use uuid::Uuid;
 
 use kitsu::models as kitsu;
 
-#[derive(Serialize, Copy, Clone, Debug)]
+use crate::datasource::repository::api_keys::ApiKeyScope;
+use crate::datasource::repository::events::EventKind;
+use crate::datasource::repository::webhooks::WebhookFormat;
+
+#[derive(Serialize, Copy, Clone, Debug, ToSchema)]
 pub struct ImageDimension {
     pub width: u32,
     pub height: u32,
@@ -22,13 +28,31 @@ impl From<kitsu::ImageDimension> for ImageDimension {
     }
 }
 
-#[derive(Serialize, Clone, Debug)]
+impl From<ImageDimension> for proto::api::v2::ImageDimension {
+    fn from(value: ImageDimension) -> Self {
+        Self {
+            width: value.width,
+            height: value.height,
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug, ToSchema)]
 pub struct ImageDefinition {
     pub url: Url,
     pub dimensions: ImageDimension,
 }
 
-#[derive(Serialize, Clone, Debug)]
+impl From<ImageDefinition> for proto::api::v2::ImageDefinition {
+    fn from(value: ImageDefinition) -> Self {
+        Self {
+            url: value.url.into(),
+            dimensions: Some(value.dimensions.into()),
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug, ToSchema)]
 pub struct Images {
     pub original: Url,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -77,7 +101,19 @@ impl From<kitsu::Images> for Images {
     }
 }
 
-#[derive(Serialize, Clone, Debug)]
+impl From<Images> for proto::api::v2::Images {
+    fn from(value: Images) -> Self {
+        Self {
+            original: value.original.into(),
+            large: value.large.map(Into::into),
+            medium: value.medium.map(Into::into),
+            small: value.small.map(Into::into),
+            tiny: value.tiny.map(Into::into),
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug, ToSchema)]
 pub struct Titles {
     pub en: Option<String>,
     pub en_jp: String,
@@ -94,7 +130,17 @@ impl From<kitsu::Titles> for Titles {
     }
 }
 
-#[derive(Serialize, Clone, Debug)]
+impl From<Titles> for proto::api::v2::Titles {
+    fn from(value: Titles) -> Self {
+        Self {
+            en: value.en,
+            en_jp: value.en_jp,
+            ja_jp: value.ja_jp,
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug, ToSchema)]
 pub struct Show {
     pub id: u32,
     pub created_at: DateTime<Utc>,
@@ -103,6 +149,7 @@ pub struct Show {
     pub synopsis: String,
     pub description: String,
     pub canonical_title: String,
+    pub titles: Titles,
     pub start_date: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub end_date: Option<String>,
@@ -131,6 +178,7 @@ impl TryFrom<kitsu::Anime> for Show {
             synopsis: value.attributes.synopsis,
             description: value.attributes.description,
             canonical_title: value.attributes.canonical_title,
+            titles: value.attributes.titles.into(),
             start_date: value.attributes.start_date,
             end_date: value.attributes.end_date,
             poster_image: value.attributes.poster_image.into(),
@@ -144,8 +192,71 @@ impl TryFrom<kitsu::Anime> for Show {
     }
 }
 
-#[derive(Debug, Clone, Serialize)]
+impl From<Show> for proto::api::v2::Show {
+    fn from(value: Show) -> Self {
+        Self {
+            id: value.id,
+            created_at: Some(prost_timestamp(value.created_at)),
+            updated_at: Some(prost_timestamp(value.updated_at)),
+            slug: value.slug,
+            synopsis: value.synopsis,
+            description: value.description,
+            canonical_title: value.canonical_title,
+            titles: Some(value.titles.into()),
+            start_date: value.start_date,
+            end_date: value.end_date,
+            poster_image: Some(value.poster_image.into()),
+            cover_image: value.cover_image.map(Into::into),
+            episode_count: value.episode_count,
+            episode_length: value.episode_length,
+            total_length: value.total_length,
+            youtube_video_id: value.youtube_video_id,
+            nsfw: value.nsfw,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DownloadEvent {
+    pub sequence: u64,
+    pub kind: EventKind,
+    pub group: DownloadGroup,
+}
+
+impl From<DownloadEvent> for proto::api::v2::DownloadCollection {
+    fn from(value: DownloadEvent) -> Self {
+        proto::api::v2::DownloadCollection {
+            sequence: value.sequence,
+            ..value.group.into()
+        }
+    }
+}
+
+impl From<DownloadEvent> for proto::api::v3::DownloadCollection {
+    fn from(value: DownloadEvent) -> Self {
+        let kind = match value.kind {
+            EventKind::Download => proto::api::v3::EventKind::Download,
+            EventKind::Revised => proto::api::v3::EventKind::Revised,
+        };
+        proto::api::v3::DownloadCollection {
+            sequence: Some(value.sequence),
+            kind: Some(kind.into()),
+            ..value.group.into()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct DownloadGroup {
+    /// The id of the persisted download, absent until it has been saved.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<Uuid>,
+    /// Links to the download and the downloads related to it, only set on REST responses.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub links: Option<DownloadLinks>,
+    /// Empty in the events stored before the provider was recorded.
+    #[serde(default)]
+    pub provider: String,
     pub title: String,
     #[serde(flatten)]
     pub variant: DownloadVariant,
@@ -154,6 +265,36 @@ pub struct DownloadGroup {
     pub downloads: Vec<Download>,
 }
 
+/// Links to a download and the batches or episodes of the same show that overlap with it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct DownloadLinks {
+    #[serde(rename = "self")]
+    pub this: String,
+    /// The batches containing this episode.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub batches: Vec<String>,
+    /// The episodes contained in this batch.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub episodes: Vec<String>,
+}
+
+impl DownloadLinks {
+    #[must_use]
+    pub fn new(id: Uuid) -> Self {
+        Self {
+            this: download_link(id),
+            batches: Vec::new(),
+            episodes: Vec::new(),
+        }
+    }
+}
+
+/// The REST path of the download with `id`.
+#[must_use]
+pub fn download_link(id: Uuid) -> String {
+    format!("/v1/downloads/{id}")
+}
+
 impl From<nyaa::AnimeDownloads> for DownloadGroup {
     fn from(value: nyaa::AnimeDownloads) -> Self {
         let created_at = value
@@ -169,6 +310,9 @@ impl From<nyaa::AnimeDownloads> for DownloadGroup {
             .max()
             .unwrap_or_default();
         Self {
+            id: None,
+            links: None,
+            provider: value.provider,
             title: value.title,
             variant: value.variant.into(),
             created_at,
@@ -178,6 +322,18 @@ impl From<nyaa::AnimeDownloads> for DownloadGroup {
     }
 }
 
+impl DownloadGroup {
+    /// Sets the id of the persisted group and derives the ids of its downloads from it.
+    #[must_use]
+    pub fn with_id(mut self, id: Uuid) -> Self {
+        for download in &mut self.downloads {
+            download.id = Some(format!("{id}:{}", download.resolution));
+        }
+        self.id = Some(id);
+        self
+    }
+}
+
 impl From<DownloadGroup> for proto::api::v2::DownloadCollection {
     fn from(value: DownloadGroup) -> Self {
         proto::api::v2::DownloadCollection {
@@ -186,18 +342,45 @@ impl From<DownloadGroup> for proto::api::v2::DownloadCollection {
             title: value.title,
             variant: Some(value.variant.into()),
             downloads: value.downloads.into_iter().map(Into::into).collect(),
+            sequence: 0,
+            id: value.id.map(|id| id.to_string()).unwrap_or_default(),
+        }
+    }
+}
+
+impl From<DownloadGroup> for proto::api::v3::DownloadCollection {
+    fn from(value: DownloadGroup) -> Self {
+        proto::api::v3::DownloadCollection {
+            id: value.id.map(|id| id.to_string()),
+            provider: value.provider,
+            title: value.title,
+            show_id: None,
+            variant: Some(value.variant.into()),
+            downloads: value.downloads.into_iter().map(Into::into).collect(),
+            created_at: Some(prost_timestamp(value.created_at)),
+            updated_at: Some(prost_timestamp(value.updated_at)),
+            sequence: None,
+            kind: None,
         }
     }
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 #[serde(tag = "variant", rename_all = "snake_case")]
 pub enum DownloadVariant {
+    #[schema(value_type = BatchRange)]
     Batch(RangeInclusive<u32>),
     Episode(Episode),
     Movie,
 }
 
+/// The serialized form of the episode range of a [`DownloadVariant::Batch`].
+#[derive(ToSchema)]
+pub struct BatchRange {
+    pub start: u32,
+    pub end: u32,
+}
+
 impl From<nyaa::DownloadVariant> for DownloadVariant {
     fn from(value: nyaa::DownloadVariant) -> Self {
         match value {
@@ -227,7 +410,26 @@ impl From<DownloadVariant> for proto::api::v2::download_collection::Variant {
     }
 }
 
-#[derive(Debug, Clone, Serialize)]
+impl From<DownloadVariant> for proto::api::v3::download_collection::Variant {
+    fn from(value: DownloadVariant) -> Self {
+        match value {
+            DownloadVariant::Batch(range) => {
+                proto::api::v3::download_collection::Variant::Batch(proto::api::v3::Batch {
+                    start: *range.start(),
+                    end: *range.end(),
+                })
+            }
+            DownloadVariant::Episode(ep) => {
+                proto::api::v3::download_collection::Variant::Episode(ep.into())
+            }
+            DownloadVariant::Movie => {
+                proto::api::v3::download_collection::Variant::Movie(proto::api::v3::Movie {})
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct Episode {
     pub episode: u32,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -236,6 +438,9 @@ pub struct Episode {
     pub version: Option<u32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub extra: Option<String>,
+    /// Whether a newer version of the episode was released by the same provider.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub superseded: bool,
 }
 
 impl From<nyaa::Episode> for Episode {
@@ -245,6 +450,7 @@ impl From<nyaa::Episode> for Episode {
             decimal: value.decimal,
             version: value.version,
             extra: value.extra,
+            superseded: false,
         }
     }
 }
@@ -260,22 +466,49 @@ impl From<Episode> for proto::api::v2::Episode {
     }
 }
 
-#[derive(Debug, Clone, Serialize)]
+impl From<Episode> for proto::api::v3::Episode {
+    fn from(value: Episode) -> Self {
+        Self {
+            number: value.episode,
+            decimal: value.decimal,
+            version: value.version,
+            extra: value.extra,
+            superseded: value.superseded,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct Download {
+    /// The id of the persisted resolution, `<download id>:<resolution>`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
     pub comments: String,
     pub resolution: u16,
     pub torrent: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub magnet: Option<String>,
     pub file_name: String,
+    /// Size of the download in bytes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// Seeders reported when the download was last polled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seeders: Option<u32>,
     pub published_date: DateTime<Utc>,
 }
 
 impl From<nyaa::Download> for Download {
     fn from(value: nyaa::Download) -> Self {
         Self {
+            id: None,
             comments: value.comments,
             resolution: value.resolution,
             torrent: value.torrent,
+            magnet: value.magnet,
             file_name: value.file_name,
+            size: value.size,
+            seeders: value.seeders,
             published_date: value.pub_date,
         }
     }
@@ -284,6 +517,7 @@ impl From<nyaa::Download> for Download {
 impl From<Download> for proto::api::v2::Download {
     fn from(value: Download) -> Self {
         Self {
+            id: value.id.unwrap_or_default(),
             published_date: Some(prost_timestamp(value.published_date)),
             resolution: u32::from(value.resolution),
             comments: value.comments,
@@ -293,9 +527,383 @@ impl From<Download> for proto::api::v2::Download {
     }
 }
 
+impl From<Download> for proto::api::v3::Download {
+    fn from(value: Download) -> Self {
+        Self {
+            id: value.id,
+            resolution: u32::from(value.resolution),
+            file_name: value.file_name,
+            comments: value.comments,
+            torrent: value.torrent,
+            magnet: value.magnet,
+            size: value.size,
+            seeders: value.seeders,
+            published_date: Some(prost_timestamp(value.published_date)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Webhook {
+    pub id: Uuid,
+    pub url: Url,
+    pub format: WebhookFormat,
+    pub title_patterns: Vec<String>,
+    /// Whether deliveries are signed with a secret.
+    pub signed: bool,
+    pub enabled: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct WebhookDelivery {
+    pub id: u64,
+    pub sequence: u64,
+    pub attempt: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status_code: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ApiKey {
+    pub id: Uuid,
+    pub name: String,
+    /// The first characters of the key, to recognize it by.
+    pub prefix: String,
+    pub scopes: Vec<ApiKeyScope>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A newly created api key, the only time the key itself is returned.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct CreatedApiKey {
+    /// Sent as a bearer token, in the `x-api-key` header or the `apikey` query parameter.
+    pub key: String,
+    #[serde(flatten)]
+    pub api_key: ApiKey,
+}
+
+/// A followed show, identified by either its title or its Kitsu id.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct WatchlistEntry {
+    pub id: Uuid,
+    /// Matched against download titles ignoring case and punctuation.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// Kitsu id of the show, matched against download titles by its known titles.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub show_id: Option<u32>,
+    /// The known titles of the Kitsu show, looked up when it was followed.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub show_titles: Vec<String>,
+    /// Only this resolution is included of downloads released in it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resolution: Option<u16>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// How a show keeps up with its release schedule.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ScheduleStatus {
+    /// Releases weekly and the next episode is not overdue.
+    Airing,
+    /// Releases weekly, but the next episode is overdue.
+    Late,
+    /// Released weekly, but has not released an episode for a few weeks.
+    Hiatus,
+    /// Does not release episodes on a weekly schedule.
+    Irregular,
+}
+
+/// When a show releases its episodes, inferred from when they were first downloaded.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct ShowSchedule {
+    pub title: String,
+    pub status: ScheduleStatus,
+    /// The day of the week episodes are released on in UTC, for weekly shows.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>, example = "Sat")]
+    pub weekday: Option<Weekday>,
+    /// The time of day episodes are released at in UTC, for weekly shows.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time: Option<NaiveTime>,
+    pub last_episode: u32,
+    pub last_released_at: DateTime<Utc>,
+    pub next_episode: u32,
+    /// When the next episode is expected, unless the show is irregular or on hiatus.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_expected_at: Option<DateTime<Utc>>,
+}
+
+/// Which episodes of a show were downloaded, and what is missing from them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct Completeness {
+    pub title: String,
+    /// Whether every episode from the first to the latest one was downloaded in every resolution.
+    pub complete: bool,
+    pub first_episode: u32,
+    pub latest_episode: u32,
+    /// Episodes between the first and latest one that were not downloaded at all.
+    pub missing_episodes: Vec<u32>,
+    /// Episodes that were only downloaded as part of a batch.
+    pub batch_only_episodes: Vec<u32>,
+    /// Episodes not downloaded in every resolution other episodes of the show were.
+    pub missing_resolutions: Vec<MissingResolutions>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct MissingResolutions {
+    pub episode: u32,
+    pub resolutions: Vec<u16>,
+}
+
+/// The downloads of a single show.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ShowDownloads {
+    /// Missing for shows of which only movies were downloaded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub completeness: Option<Completeness>,
+    pub downloads: Vec<DownloadGroup>,
+}
+
 fn prost_timestamp(date_time: DateTime<Utc>) -> prost_types::Timestamp {
     prost_types::Timestamp {
         seconds: date_time.timestamp(),
         nanos: date_time.timestamp_subsec_nanos().cast_signed(),
     }
 }
+
+/// Builders of test data, so tests only spell out what they depend on.
+#[cfg(test)]
+impl DownloadGroup {
+    /// A movie released by `SubsPlease`, without downloads.
+    pub(crate) fn fixture(title: &str) -> Self {
+        Self {
+            id: None,
+            links: None,
+            provider: "SubsPlease".to_string(),
+            title: title.to_string(),
+            variant: DownloadVariant::Movie,
+            created_at: DateTime::default(),
+            updated_at: DateTime::default(),
+            downloads: Vec::new(),
+        }
+    }
+
+    #[must_use]
+    pub(crate) fn with_provider(self, provider: &str) -> Self {
+        Self {
+            provider: provider.to_string(),
+            ..self
+        }
+    }
+
+    #[must_use]
+    pub(crate) fn with_variant(self, variant: DownloadVariant) -> Self {
+        Self { variant, ..self }
+    }
+
+    #[must_use]
+    pub(crate) fn with_download(mut self, download: Download) -> Self {
+        self.downloads.push(download);
+        self
+    }
+
+    /// Adds a download in each resolution, named `<title> (<resolution>p)`.
+    #[must_use]
+    pub(crate) fn with_resolutions(self, resolutions: &[u16]) -> Self {
+        resolutions.iter().fold(self, |group, &resolution| {
+            let file_name = format!("{} ({resolution}p)", group.title);
+            group.with_download(Download::fixture(resolution).with_file_name(&file_name))
+        })
+    }
+}
+
+#[cfg(test)]
+impl Download {
+    /// A download in `resolution` linking to its nyaa page and torrent.
+    pub(crate) fn fixture(resolution: u16) -> Self {
+        Self {
+            id: None,
+            comments: format!("https://nyaa.si/view/{resolution}"),
+            resolution,
+            torrent: format!("https://nyaa.si/download/{resolution}.torrent"),
+            magnet: None,
+            file_name: format!("({resolution}p).mkv"),
+            size: None,
+            seeders: None,
+            published_date: DateTime::default(),
+        }
+    }
+
+    #[must_use]
+    pub(crate) fn with_file_name(self, file_name: &str) -> Self {
+        Self {
+            file_name: file_name.to_string(),
+            ..self
+        }
+    }
+
+    #[must_use]
+    pub(crate) fn with_magnet(self, magnet: &str) -> Self {
+        Self {
+            magnet: Some(magnet.to_string()),
+            ..self
+        }
+    }
+
+    #[must_use]
+    pub(crate) fn with_size(self, size: u64) -> Self {
+        Self {
+            size: Some(size),
+            ..self
+        }
+    }
+
+    #[must_use]
+    pub(crate) fn with_seeders(self, seeders: u32) -> Self {
+        Self {
+            seeders: Some(seeders),
+            ..self
+        }
+    }
+
+    #[must_use]
+    pub(crate) fn with_published_date(self, published_date: DateTime<Utc>) -> Self {
+        Self {
+            published_date,
+            ..self
+        }
+    }
+}
+
+#[cfg(test)]
+impl Episode {
+    /// The first version of `episode`, without decimal or extra.
+    pub(crate) fn fixture(episode: u32) -> Self {
+        Self {
+            episode,
+            decimal: None,
+            version: None,
+            extra: None,
+            superseded: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn group(variant: DownloadVariant) -> DownloadGroup {
+        DownloadGroup::fixture("Frieren")
+            .with_variant(variant)
+            .with_resolutions(&[1080])
+    }
+
+    #[test]
+    fn test_download_group_json_round_trip_batch() {
+        let json = serde_json::to_value(group(DownloadVariant::Batch(1..=12))).unwrap();
+        let result: DownloadGroup = serde_json::from_value(json).unwrap();
+        assert!(matches!(result.variant, DownloadVariant::Batch(range) if range == (1..=12)));
+        assert_eq!(result.downloads.len(), 1);
+    }
+
+    #[test]
+    fn test_download_group_json_without_provider() {
+        // the payload of events stored before the provider was recorded
+        let json = serde_json::json!({
+            "title": "Frieren",
+            "variant": "episode",
+            "episode": 1,
+            "created_at": "2024-01-01T00:00:00Z",
+            "updated_at": "2024-01-01T00:00:00Z",
+            "downloads": [{
+                "comments": "https://nyaa.si/view/1",
+                "resolution": 1080,
+                "torrent": "https://nyaa.si/download/1.torrent",
+                "file_name": "[SubsPlease] Frieren - 01 (1080p).mkv",
+                "published_date": "2024-01-01T00:00:00Z",
+            }],
+        });
+        let result: DownloadGroup = serde_json::from_value(json).unwrap();
+        assert_eq!(result.provider, "");
+        assert!(matches!(result.variant, DownloadVariant::Episode(ref e) if e.episode == 1));
+    }
+
+    #[test]
+    fn test_download_group_with_id() {
+        let id = Uuid::from_u128(1);
+        let group = group(DownloadVariant::Movie).with_id(id);
+        let json = serde_json::to_value(&group).unwrap();
+        assert_eq!(json["id"], "00000000-0000-0000-0000-000000000001");
+        assert_eq!(
+            json["downloads"][0]["id"],
+            "00000000-0000-0000-0000-000000000001:1080"
+        );
+    }
+
+    #[test]
+    fn test_download_links_json() {
+        let mut group = group(DownloadVariant::Episode(Episode::fixture(7)));
+        assert!(serde_json::to_value(&group).unwrap().get("links").is_none());
+        let mut links = DownloadLinks::new(Uuid::from_u128(1));
+        links.batches.push(download_link(Uuid::from_u128(2)));
+        group.links = Some(links);
+        let json = serde_json::to_value(&group).unwrap();
+        assert_eq!(
+            json["links"],
+            serde_json::json!({
+                "self": "/v1/downloads/00000000-0000-0000-0000-000000000001",
+                "batches": ["/v1/downloads/00000000-0000-0000-0000-000000000002"],
+            })
+        );
+    }
+
+    #[test]
+    fn test_download_event_into_v3() {
+        let group = group(DownloadVariant::Movie).with_id(Uuid::from_u128(1));
+        let collection: proto::api::v3::DownloadCollection = DownloadEvent {
+            sequence: 3,
+            kind: EventKind::Revised,
+            group,
+        }
+        .into();
+        assert_eq!(
+            collection.id.as_deref(),
+            Some("00000000-0000-0000-0000-000000000001")
+        );
+        assert_eq!(collection.provider, "SubsPlease");
+        assert_eq!(collection.sequence, Some(3));
+        assert_eq!(collection.kind(), proto::api::v3::EventKind::Revised);
+        assert_eq!(collection.show_id, None);
+        let download = &collection.downloads[0];
+        assert_eq!(
+            download.id.as_deref(),
+            Some("00000000-0000-0000-0000-000000000001:1080")
+        );
+        assert_eq!(download.magnet, None);
+    }
+
+    #[test]
+    fn test_download_group_json_round_trip_episode() {
+        let episode = Episode {
+            version: Some(2),
+            superseded: true,
+            ..Episode::fixture(7)
+        };
+        let json = serde_json::to_value(group(DownloadVariant::Episode(episode))).unwrap();
+        let result: DownloadGroup = serde_json::from_value(json).unwrap();
+        let DownloadVariant::Episode(episode) = result.variant else {
+            panic!("expected an episode variant");
+        };
+        assert_eq!(episode.episode, 7);
+        assert_eq!(episode.version, Some(2));
+    }
+}
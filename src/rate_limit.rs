@@ -0,0 +1,347 @@
+//! Per-client request quotas and limits on concurrent subscriptions.
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use ahash::RandomState;
+use axum::extract::{ConnectInfo, Request};
+use axum::http::HeaderValue;
+use axum::http::header::{CONTENT_TYPE, RETRY_AFTER};
+use axum::response::{IntoResponse, Response};
+use futures::future::{BoxFuture, FutureExt, TryFutureExt};
+use governor::clock::{Clock, DefaultClock};
+use governor::{DefaultKeyedRateLimiter, Quota};
+use tonic::Status;
+use tower::{Layer, Service};
+
+use crate::auth;
+use crate::auth::{ApiKeys, KeyHash};
+use crate::errors::Error;
+use crate::state::RateLimitConfig;
+
+const FORGET_INTERVAL: Duration = Duration::from_mins(5);
+const FORWARDED_FOR_HEADER: &str = "x-forwarded-for";
+
+/// Who a request is counted against: its api key when it has a known one, otherwise its address.
+///
+/// The address is the forwarded one when forwarded headers are trusted, otherwise the one
+/// connecting, which every server attaches as [`ConnectInfo`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum ClientId {
+    Key(KeyHash),
+    Ip(IpAddr),
+    /// The address is not known, when the service is used without a server attaching it.
+    Unknown,
+}
+
+/// The request quotas and open subscriptions of every client.
+#[derive(Debug, Clone)]
+pub struct RateLimits {
+    requests: Arc<DefaultKeyedRateLimiter<ClientId>>,
+    subscriptions: Arc<Mutex<HashMap<ClientId, usize, RandomState>>>,
+    max_subscriptions: usize,
+    trust_forwarded_headers: bool,
+}
+
+impl RateLimits {
+    #[must_use]
+    pub fn new(config: &RateLimitConfig) -> Self {
+        let quota = Quota::per_minute(config.requests_per_minute).allow_burst(config.burst);
+        Self {
+            requests: Arc::new(DefaultKeyedRateLimiter::keyed(quota)),
+            subscriptions: Arc::default(),
+            max_subscriptions: config.max_subscriptions.get(),
+            trust_forwarded_headers: config.trust_forwarded_headers,
+        }
+    }
+
+    /// Counts a request of `client`, returning how long to wait if it is over its quota.
+    fn check(&self, client: &ClientId) -> Result<(), Duration> {
+        self.requests
+            .check_key(client)
+            .map_err(|not_until| not_until.wait_time_from(DefaultClock::default().now()))
+    }
+
+    /// Opens a subscription of `client`, which stays open until the permit is dropped.
+    pub(crate) fn subscribe(&self, client: ClientId) -> Option<SubscriptionPermit> {
+        let mut subscriptions = self
+            .subscriptions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let open = subscriptions.entry(client).or_default();
+        if *open >= self.max_subscriptions {
+            return None;
+        }
+        *open += 1;
+        Some(SubscriptionPermit {
+            client,
+            subscriptions: self.subscriptions.clone(),
+        })
+    }
+
+    /// Drops the quotas of clients that have been idle long enough to be back at a full burst.
+    pub async fn forget_idle(self) {
+        let mut interval = tokio::time::interval(FORGET_INTERVAL);
+        loop {
+            interval.tick().await;
+            self.requests.retain_recent();
+            self.requests.shrink_to_fit();
+        }
+    }
+}
+
+/// An open subscription, counted against the limit of its client until dropped.
+#[derive(Debug)]
+pub(crate) struct SubscriptionPermit {
+    client: ClientId,
+    subscriptions: Arc<Mutex<HashMap<ClientId, usize, RandomState>>>,
+}
+
+impl Drop for SubscriptionPermit {
+    fn drop(&mut self) {
+        let mut subscriptions = self
+            .subscriptions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if let Some(open) = subscriptions.get_mut(&self.client) {
+            *open = open.saturating_sub(1);
+            if *open == 0 {
+                subscriptions.remove(&self.client);
+            }
+        }
+    }
+}
+
+/// Rejects requests of clients over their quota and attaches the [`ClientId`] of the others.
+///
+/// Rejected gRPC calls fail with `RESOURCE_EXHAUSTED`, other requests with `429 Too Many
+/// Requests`. Both carry a `Retry-After` header.
+#[derive(Debug, Clone)]
+pub struct RateLimitLayer {
+    limits: RateLimits,
+    api_keys: ApiKeys,
+}
+
+impl RateLimitLayer {
+    #[must_use]
+    pub fn new(limits: RateLimits, api_keys: ApiKeys) -> Self {
+        Self { limits, api_keys }
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimit<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimit {
+            inner,
+            limits: self.limits.clone(),
+            api_keys: self.api_keys.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RateLimit<S> {
+    inner: S,
+    limits: RateLimits,
+    api_keys: ApiKeys,
+}
+
+impl<S> RateLimit<S> {
+    fn client_id(&self, request: &Request) -> ClientId {
+        if let Some(hash) = auth::request_key(request)
+            .as_deref()
+            .and_then(|key| self.api_keys.known_hash(key))
+        {
+            return ClientId::Key(hash);
+        }
+        if self.limits.trust_forwarded_headers
+            && let Some(ip) = forwarded_ip(request)
+        {
+            return ClientId::Ip(ip);
+        }
+        request
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map_or(ClientId::Unknown, |ConnectInfo(addr)| {
+                ClientId::Ip(addr.ip())
+            })
+    }
+}
+
+impl<S> Service<Request> for RateLimit<S>
+where
+    S: Service<Request> + Send + 'static,
+    S::Response: IntoResponse,
+    S::Error: Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Response, S::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request) -> Self::Future {
+        let client = self.client_id(&request);
+        if let Err(wait) = self.limits.check(&client) {
+            let response = too_many_requests(&request, wait);
+            return futures::future::ready(Ok(response)).boxed();
+        }
+        request.extensions_mut().insert(client);
+        self.inner
+            .call(request)
+            .map_ok(IntoResponse::into_response)
+            .boxed()
+    }
+}
+
+/// The address the closest proxy saw the request coming from, which it appended last to
+/// `X-Forwarded-For`. The earlier ones are ignored, as the client could have sent them.
+fn forwarded_ip(request: &Request) -> Option<IpAddr> {
+    request
+        .headers()
+        .get_all(FORWARDED_FOR_HEADER)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .next_back()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+fn too_many_requests(request: &Request, wait: Duration) -> Response {
+    // round up, so clients retrying right after the header says so are not rejected again
+    let seconds = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+    let is_grpc = request
+        .headers()
+        .get(CONTENT_TYPE)
+        .is_some_and(|content_type| content_type.as_bytes().starts_with(b"application/grpc"));
+    let mut response = if is_grpc {
+        Status::resource_exhausted("rate limit exceeded").into_http()
+    } else {
+        Error::TooManyRequests(seconds).into_response()
+    };
+    response
+        .headers_mut()
+        .insert(RETRY_AFTER, HeaderValue::from(seconds));
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use std::num::{NonZeroU32, NonZeroUsize};
+
+    use axum::body::Body;
+    use axum::http::StatusCode;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::state::AuthConfig;
+
+    fn config() -> RateLimitConfig {
+        RateLimitConfig {
+            requests_per_minute: NonZeroU32::new(1).unwrap(),
+            burst: NonZeroU32::new(2).unwrap(),
+            max_subscriptions: NonZeroUsize::new(1).unwrap(),
+            trust_forwarded_headers: false,
+        }
+    }
+
+    fn limits() -> RateLimits {
+        RateLimits::new(&config())
+    }
+
+    /// Sends a request from `addr` through a rate limited service, returning the status.
+    async fn status(limits: &RateLimits, addr: [u8; 4], forwarded_for: Option<&str>) -> StatusCode {
+        let service = RateLimitLayer::new(limits.clone(), ApiKeys::new(&AuthConfig::default()))
+            .layer(tower::service_fn(|_: Request| async {
+                Ok::<_, Infallible>(StatusCode::OK)
+            }));
+        let mut request = Request::new(Body::empty());
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from((addr, 50000))));
+        if let Some(forwarded_for) = forwarded_for {
+            request
+                .headers_mut()
+                .insert(FORWARDED_FOR_HEADER, forwarded_for.parse().unwrap());
+        }
+        service.oneshot(request).await.unwrap().status()
+    }
+
+    #[test]
+    fn test_check() {
+        let limits = limits();
+        let client = ClientId::Ip(IpAddr::from([127, 0, 0, 1]));
+        assert!(limits.check(&client).is_ok());
+        assert!(limits.check(&client).is_ok());
+        let wait = limits.check(&client).unwrap_err();
+        assert!(wait > Duration::ZERO && wait <= Duration::from_mins(1));
+        assert!(limits.check(&ClientId::Unknown).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_clients_are_limited_separately() {
+        let limits = limits();
+        assert_eq!(status(&limits, [10, 0, 0, 1], None).await, StatusCode::OK);
+        assert_eq!(status(&limits, [10, 0, 0, 1], None).await, StatusCode::OK);
+        assert_eq!(
+            status(&limits, [10, 0, 0, 1], None).await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        assert_eq!(status(&limits, [10, 0, 0, 2], None).await, StatusCode::OK);
+        // forwarded headers are ignored unless trusted
+        assert_eq!(
+            status(&limits, [10, 0, 0, 1], Some("192.0.2.1")).await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+    }
+
+    #[tokio::test]
+    async fn test_trusted_forwarded_headers() {
+        let limits = RateLimits::new(&RateLimitConfig {
+            trust_forwarded_headers: true,
+            ..config()
+        });
+        let proxy = [10, 0, 0, 1];
+        for _ in 0..2 {
+            assert_eq!(
+                status(&limits, proxy, Some("192.0.2.1")).await,
+                StatusCode::OK
+            );
+        }
+        assert_eq!(
+            status(&limits, proxy, Some("192.0.2.1")).await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        // only the address appended by the proxy counts, not one the client sent along
+        assert_eq!(
+            status(&limits, proxy, Some("192.0.2.1, 192.0.2.2")).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(&limits, proxy, Some("192.0.2.2, 192.0.2.1")).await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        // falls back to the connecting address without the header
+        assert_eq!(status(&limits, proxy, None).await, StatusCode::OK);
+    }
+
+    #[test]
+    fn test_subscribe() {
+        let limits = limits();
+        let permit = limits.subscribe(ClientId::Unknown);
+        assert!(permit.is_some());
+        assert!(limits.subscribe(ClientId::Unknown).is_none());
+        drop(permit);
+        assert!(limits.subscribe(ClientId::Unknown).is_some());
+    }
+}
@@ -0,0 +1,263 @@
+//! Infers when shows release their episodes from when their downloads were first seen.
+use chrono::{DateTime, Datelike, NaiveTime, TimeDelta, Timelike, Utc};
+
+use crate::models::{ScheduleStatus, ShowSchedule};
+
+pub(crate) const WEEK: TimeDelta = TimeDelta::weeks(1);
+/// How far back releases are considered.
+pub(crate) const HISTORY: TimeDelta = TimeDelta::weeks(26);
+/// How far the time between episodes may drift from a week for a show to still release weekly,
+/// and how long after its expected time an episode is late.
+const TOLERANCE: TimeDelta = TimeDelta::days(1);
+/// The number of most recent releases the cadence of a show is inferred from.
+const RECENT_RELEASES: usize = 6;
+/// Weeks an episode may be overdue before the show is considered on hiatus.
+const MAX_MISSED_WEEKS: i32 = 2;
+
+/// The first time an episode of a show was downloaded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Release {
+    pub(crate) title: String,
+    pub(crate) episode: u32,
+    pub(crate) released_at: DateTime<Utc>,
+}
+
+/// Splits releases ordered by title into the releases of each show.
+pub(crate) fn by_show(releases: &[Release]) -> impl Iterator<Item = &[Release]> {
+    releases.chunk_by(|a, b| a.title == b.title)
+}
+
+fn recent(releases: &[Release]) -> &[Release] {
+    &releases[releases.len().saturating_sub(RECENT_RELEASES)..]
+}
+
+/// The median time between the recent episodes of a show, from its releases ordered by episode.
+///
+/// Needs at least three releases, so a single late episode does not decide the cadence.
+pub(crate) fn cadence(releases: &[Release]) -> Option<TimeDelta> {
+    let recent = recent(releases);
+    let mut intervals: Vec<_> = recent
+        .windows(2)
+        .filter_map(|pair| {
+            let episodes = i32::try_from(pair[1].episode.checked_sub(pair[0].episode)?).ok()?;
+            (episodes > 0).then(|| (pair[1].released_at - pair[0].released_at) / episodes)
+        })
+        .collect();
+    if intervals.len() < 2 {
+        return None;
+    }
+    intervals.sort();
+    Some(intervals[intervals.len() / 2])
+}
+
+/// Whether the releases of a show, ordered by episode, follow a weekly schedule.
+pub(crate) fn is_weekly(releases: &[Release]) -> bool {
+    cadence(releases).is_some_and(|cadence| (cadence - WEEK).abs() <= TOLERANCE)
+}
+
+/// The moment in the week a show typically releases, as the one closest to its last release.
+///
+/// This is the median of when the recent episodes were released within their week, so a single
+/// early or late episode does not shift the schedule.
+fn slot(releases: &[Release]) -> Option<DateTime<Utc>> {
+    let last = releases.last()?.released_at;
+    let recent = recent(releases);
+    let week = WEEK.num_seconds();
+    let mut offsets: Vec<_> = recent
+        .iter()
+        .map(|release| {
+            let offset = (release.released_at - last).num_seconds().rem_euclid(week);
+            if offset > week / 2 {
+                offset - week
+            } else {
+                offset
+            }
+        })
+        .collect();
+    offsets.sort_unstable();
+    Some(last + TimeDelta::seconds(offsets[offsets.len() / 2]))
+}
+
+/// Infers the schedule of a show from its releases ordered by episode.
+pub(crate) fn schedule(releases: &[Release], now: DateTime<Utc>) -> Option<ShowSchedule> {
+    let last = releases.last()?;
+    let slot = slot(releases).filter(|_| is_weekly(releases));
+    let expected_at = slot.map(|slot| slot + WEEK);
+    let status = match expected_at {
+        None => ScheduleStatus::Irregular,
+        Some(at) if now <= at + TOLERANCE => ScheduleStatus::Airing,
+        Some(at) if now <= at + WEEK * MAX_MISSED_WEEKS => ScheduleStatus::Late,
+        Some(_) => ScheduleStatus::Hiatus,
+    };
+    Some(ShowSchedule {
+        title: last.title.clone(),
+        status,
+        weekday: slot.map(|slot| slot.weekday()),
+        time: slot.and_then(|slot| NaiveTime::from_hms_opt(slot.hour(), slot.minute(), 0)),
+        last_episode: last.episode,
+        last_released_at: last.released_at,
+        next_episode: last.episode + 1,
+        next_expected_at: expected_at.filter(|_| status != ScheduleStatus::Hiatus),
+    })
+}
+
+/// Predicts the next `count` episodes of a weekly show from its releases ordered by episode.
+///
+/// Nothing is predicted for shows without a weekly schedule, or that are on hiatus, as they have
+/// most likely finished airing.
+pub(crate) fn predict(releases: &[Release], now: DateTime<Utc>, count: u32) -> Vec<Release> {
+    let Some(schedule) = schedule(releases, now) else {
+        return Vec::new();
+    };
+    let Some(expected_at) = schedule.next_expected_at else {
+        return Vec::new();
+    };
+    (0..count)
+        .map(|n| Release {
+            title: schedule.title.clone(),
+            episode: schedule.next_episode + n,
+            released_at: expected_at + WEEK * n.cast_signed(),
+        })
+        .collect()
+}
+
+/// The schedules of the shows airing this week, ordered by when in the week they release.
+pub(crate) fn this_week(releases: &[Release], now: DateTime<Utc>) -> Vec<ShowSchedule> {
+    let mut schedules: Vec<_> = by_show(releases)
+        .filter_map(|releases| schedule(releases, now))
+        .filter(|schedule| {
+            matches!(
+                schedule.status,
+                ScheduleStatus::Airing | ScheduleStatus::Late
+            )
+        })
+        .collect();
+    schedules.sort_by_key(|schedule| {
+        (
+            schedule
+                .weekday
+                .map(|weekday| weekday.num_days_from_monday()),
+            schedule.time,
+        )
+    });
+    schedules
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Weekday;
+
+    use super::*;
+
+    fn releases(title: &str, days: &[i64]) -> Vec<Release> {
+        let start = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        (1..)
+            .zip(days)
+            .map(|(episode, &day)| Release {
+                title: title.to_string(),
+                episode,
+                released_at: start + TimeDelta::days(day),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_by_show() {
+        let mut all = releases("Frieren", &[0, 7]);
+        all.extend(releases("Oshi no Ko", &[0]));
+        let shows: Vec<_> = by_show(&all).map(<[Release]>::len).collect();
+        assert_eq!(shows, [2, 1]);
+    }
+
+    #[test]
+    fn test_cadence() {
+        assert_eq!(cadence(&releases("Frieren", &[0, 7])), None);
+        // a single late episode does not change the median
+        assert_eq!(
+            cadence(&releases("Frieren", &[0, 7, 14, 24, 28])),
+            Some(WEEK)
+        );
+        assert!(is_weekly(&releases("Frieren", &[0, 7, 15, 21])));
+        assert!(!is_weekly(&releases("Frieren", &[0, 0, 0, 0])));
+        assert!(!is_weekly(&releases("Frieren", &[0, 14, 28])));
+    }
+
+    #[test]
+    fn test_predict() {
+        let releases = releases("Frieren", &[0, 7, 14]);
+        let last = releases[2].released_at;
+        let predicted = predict(&releases, last + TimeDelta::days(1), 2);
+        assert_eq!(
+            predicted,
+            [
+                Release {
+                    title: "Frieren".to_string(),
+                    episode: 4,
+                    released_at: last + WEEK,
+                },
+                Release {
+                    title: "Frieren".to_string(),
+                    episode: 5,
+                    released_at: last + WEEK * 2,
+                },
+            ]
+        );
+        assert!(predict(&releases, last + WEEK * 4, 2).is_empty());
+    }
+
+    #[test]
+    fn test_slot() {
+        // released 2023-11-14 22:13:20, then an hour early and two hours late
+        let releases = releases("Frieren", &[0, 7, 14]);
+        let shifted: Vec<_> = releases
+            .iter()
+            .zip([0, -1, 2])
+            .map(|(release, hours)| Release {
+                released_at: release.released_at + TimeDelta::hours(hours),
+                ..release.clone()
+            })
+            .collect();
+        assert_eq!(slot(&shifted), Some(releases[2].released_at));
+    }
+
+    #[test]
+    fn test_schedule() {
+        let releases = releases("Frieren", &[0, 7, 14]);
+        let expected_at = releases[2].released_at + WEEK;
+        let schedule_at = |now| schedule(&releases, now).unwrap();
+
+        let airing = schedule_at(expected_at - TimeDelta::days(2));
+        assert_eq!(airing.status, ScheduleStatus::Airing);
+        assert_eq!(airing.weekday, Some(Weekday::Tue));
+        assert_eq!(airing.time, NaiveTime::from_hms_opt(22, 13, 0));
+        assert_eq!(airing.last_episode, 3);
+        assert_eq!(airing.next_episode, 4);
+        assert_eq!(airing.next_expected_at, Some(expected_at));
+
+        let late = schedule_at(expected_at + TimeDelta::days(3));
+        assert_eq!(late.status, ScheduleStatus::Late);
+        assert_eq!(late.next_expected_at, Some(expected_at));
+
+        let hiatus = schedule_at(expected_at + WEEK * 3);
+        assert_eq!(hiatus.status, ScheduleStatus::Hiatus);
+        assert_eq!(hiatus.next_expected_at, None);
+
+        let irregular = schedule(&releases[..2], expected_at).unwrap();
+        assert_eq!(irregular.status, ScheduleStatus::Irregular);
+        assert_eq!(irregular.weekday, None);
+    }
+
+    #[test]
+    fn test_this_week() {
+        let mut all = releases("Frieren", &[0, 7, 14]);
+        all.extend(releases("Oshi no Ko", &[-2, 5, 12]));
+        all.extend(releases("Spy x Family", &[0, 1, 2]));
+        let now = all[2].released_at;
+        let titles: Vec<_> = this_week(&all, now)
+            .into_iter()
+            .map(|schedule| schedule.title)
+            .collect();
+        // released on tuesday and sunday respectively
+        assert_eq!(titles, ["Frieren", "Oshi no Ko"]);
+    }
+}
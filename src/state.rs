@@ -1,3 +1,5 @@
+use std::num::{NonZeroU32, NonZeroUsize};
+
 use anyhow::Result;
 use axum::extract::FromRef;
 use serde::Deserialize;
@@ -6,26 +8,156 @@ use sqlx::{Pool, Postgres};
 use tokio::sync::broadcast;
 use url::Url;
 
-use crate::models::DownloadGroup;
+use crate::auth::ApiKeys;
+use crate::datasource::repository::api_keys::ApiKeyScope;
+use crate::jobs::poller::PollerHealth;
+use crate::models::DownloadEvent;
+use crate::rate_limit::RateLimits;
 
 #[derive(Debug, Clone)]
 pub struct AppState {
     pub client: ReqwestClient,
     pub pool: DBPool,
-    pub downloads_channel: broadcast::Sender<DownloadGroup>,
+    pub downloads_channel: broadcast::Sender<DownloadEvent>,
+    pub subscriptions: SubscriptionConfig,
+    pub poller_health: PollerHealth,
+    pub api_keys: ApiKeys,
+    pub rate_limits: RateLimits,
 }
 
 impl AppState {
     pub fn new() -> Result<Self> {
-        let (tx, _) = broadcast::channel(32);
+        let subscriptions = SubscriptionConfig::from_env()?;
+        let (tx, _) = broadcast::channel(subscriptions.channel_capacity.get());
         Ok(Self {
             client: reqwest::Client::new(),
             pool: create_db_pool()?,
             downloads_channel: tx,
+            subscriptions,
+            poller_health: PollerHealth::default(),
+            api_keys: ApiKeys::new(&AuthConfig::from_env()?),
+            rate_limits: RateLimits::new(&RateLimitConfig::from_env()?),
         })
     }
 }
 
+/// Buffer sizes used when delivering download events to subscribers, read from the
+/// `SUBSCRIPTION_` prefixed environment variables.
+#[derive(Debug, Copy, Clone, Deserialize)]
+pub struct SubscriptionConfig {
+    /// Number of events the broadcast channel retains before a slow subscriber lags behind.
+    #[serde(default = "SubscriptionConfig::default_channel_capacity")]
+    pub channel_capacity: NonZeroUsize,
+    /// Number of messages buffered for each individual gRPC subscriber.
+    #[serde(default = "SubscriptionConfig::default_client_buffer")]
+    pub client_buffer: NonZeroUsize,
+}
+
+impl SubscriptionConfig {
+    pub fn from_env() -> Result<Self> {
+        Ok(envy::prefixed("SUBSCRIPTION_").from_env()?)
+    }
+
+    fn default_channel_capacity() -> NonZeroUsize {
+        NonZeroUsize::new(32).expect("32 is not zero")
+    }
+
+    fn default_client_buffer() -> NonZeroUsize {
+        NonZeroUsize::new(3).expect("3 is not zero")
+    }
+}
+
+/// Which polled downloads are handed on for persisting and broadcasting, read from the
+/// `POLLER_` prefixed environment variables. List values are comma separated.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct PollerConfig {
+    /// Title patterns, where `*` matches anything, of which one must match. Empty accepts all.
+    pub titles: Vec<String>,
+    /// Title patterns of downloads to skip.
+    pub exclude_titles: Vec<String>,
+    /// Providers to accept, case-insensitively. Empty accepts all.
+    pub providers: Vec<String>,
+    /// Downloads below this resolution are dropped.
+    pub min_resolution: Option<u16>,
+}
+
+impl PollerConfig {
+    pub fn from_env() -> Result<Self> {
+        Ok(envy::prefixed("POLLER_").from_env()?)
+    }
+}
+
+/// Browser origins allowed to call the gRPC-Web endpoints, read from the `CORS_` prefixed
+/// environment variables. List values are comma separated, any origin is allowed when empty.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct CorsConfig {
+    pub allowed_origins: Vec<String>,
+}
+
+impl CorsConfig {
+    pub fn from_env() -> Result<Self> {
+        Ok(envy::prefixed("CORS_").from_env()?)
+    }
+}
+
+/// Who may call the api, read from the `AUTH_` prefixed environment variables. List values are
+/// comma separated.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct AuthConfig {
+    /// A key granted every scope without being stored, to create the first api keys with.
+    pub admin_key: Option<String>,
+    /// Scopes granted to requests without an api key, such as `read,subscribe` for a public
+    /// instance. Empty requires a key for everything but the health check and documentation.
+    pub anonymous_scopes: Vec<ApiKeyScope>,
+}
+
+impl AuthConfig {
+    pub fn from_env() -> Result<Self> {
+        Ok(envy::prefixed("AUTH_").from_env()?)
+    }
+}
+
+/// Quotas of every client, identified by its api key or otherwise its address, read from the
+/// `RATE_LIMIT_` prefixed environment variables.
+#[derive(Debug, Copy, Clone, Deserialize)]
+pub struct RateLimitConfig {
+    /// Requests a client may make per minute once its burst is used up.
+    #[serde(default = "RateLimitConfig::default_requests_per_minute")]
+    pub requests_per_minute: NonZeroU32,
+    /// Requests a client may make in quick succession.
+    #[serde(default = "RateLimitConfig::default_burst")]
+    pub burst: NonZeroU32,
+    /// Server-sent event, websocket and gRPC subscriptions a client may have open at once.
+    #[serde(default = "RateLimitConfig::default_max_subscriptions")]
+    pub max_subscriptions: NonZeroUsize,
+    /// Identifies clients by the address a reverse proxy put in the `X-Forwarded-For` header
+    /// instead of the connecting one. Only enable it when every request passes such a proxy, as
+    /// clients could pick their own address otherwise.
+    #[serde(default)]
+    pub trust_forwarded_headers: bool,
+}
+
+impl RateLimitConfig {
+    pub fn from_env() -> Result<Self> {
+        Ok(envy::prefixed("RATE_LIMIT_").from_env()?)
+    }
+
+    fn default_requests_per_minute() -> NonZeroU32 {
+        NonZeroU32::new(300).expect("300 is not zero")
+    }
+
+    fn default_burst() -> NonZeroU32 {
+        NonZeroU32::new(60).expect("60 is not zero")
+    }
+
+    fn default_max_subscriptions() -> NonZeroUsize {
+        NonZeroUsize::new(4).expect("4 is not zero")
+    }
+}
+
 pub type ReqwestClient = reqwest::Client;
 
 impl FromRef<AppState> for ReqwestClient {
@@ -34,6 +166,12 @@ impl FromRef<AppState> for ReqwestClient {
     }
 }
 
+impl FromRef<AppState> for ApiKeys {
+    fn from_ref(input: &AppState) -> Self {
+        input.api_keys.clone()
+    }
+}
+
 pub type DBPool = Pool<Postgres>;
 
 impl FromRef<AppState> for DBPool {
@@ -42,7 +180,7 @@ impl FromRef<AppState> for DBPool {
     }
 }
 
-impl FromRef<AppState> for broadcast::Sender<DownloadGroup> {
+impl FromRef<AppState> for broadcast::Sender<DownloadEvent> {
     fn from_ref(input: &AppState) -> Self {
         input.downloads_channel.clone()
     }
@@ -0,0 +1,173 @@
+use std::pin::pin;
+
+use async_stream::stream;
+use futures::{Stream, StreamExt};
+use tokio::sync::broadcast::Sender;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{debug, error, warn};
+
+use crate::datasource::repository;
+use crate::models::DownloadEvent;
+use crate::state::DBPool;
+
+pub(crate) mod filter;
+
+const REPLAY_PAGE_SIZE: u32 = 100;
+
+/// Subscribes to download events.
+///
+/// When a database is available, the events persisted after `since` are replayed before switching
+/// to live events, and a subscriber that lags behind the broadcast channel is caught up from the
+/// database instead of silently missing events. The stream ends once the sender is closed.
+pub(crate) fn subscribe(
+    sender: &Sender<DownloadEvent>,
+    pool: Option<DBPool>,
+    since: Option<u64>,
+) -> impl Stream<Item = DownloadEvent> + use<> {
+    // subscribe before replaying, so events sent in the meantime are buffered instead of lost
+    let mut receiver = sender.subscribe();
+    stream! {
+        let mut cursor = Cursor::since(since);
+        if let Some(pool) = &pool {
+            match since {
+                Some(sequence) => {
+                    let mut replayed = pin!(replay(pool.clone(), sequence));
+                    while let Some(event) = replayed.next().await {
+                        cursor.deliver(event.sequence);
+                        yield event;
+                    }
+                }
+                None => match repository::events::latest_sequence(pool).await {
+                    Ok(sequence) => cursor = Cursor::after_latest(sequence),
+                    Err(e) => error!(error = ?e, "failed to fetch the latest download event"),
+                },
+            }
+        }
+        loop {
+            match receiver.recv().await {
+                Ok(event) => {
+                    if cursor.deliver(event.sequence) {
+                        yield event;
+                    }
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!(skipped, "subscriber lagged behind");
+                    if let (Some(pool), Some(sequence)) = (&pool, cursor.catch_up_from()) {
+                        let mut replayed = pin!(replay(pool.clone(), sequence));
+                        while let Some(event) = replayed.next().await {
+                            if cursor.deliver(event.sequence) {
+                                yield event;
+                            }
+                        }
+                    }
+                }
+                Err(RecvError::Closed) => {
+                    debug!("download sender closed");
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Tracks the events a subscriber has, to skip duplicates and to know where to catch up from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Cursor {
+    /// The last event the subscriber has.
+    delivered: Option<u64>,
+    /// Where catching up starts while no event was delivered yet.
+    baseline: Option<u64>,
+}
+
+impl Cursor {
+    /// A subscriber that has every event up to `since`.
+    fn since(since: Option<u64>) -> Self {
+        Self {
+            delivered: since,
+            baseline: None,
+        }
+    }
+
+    /// A new subscriber, for which `latest` was the latest persisted event when it subscribed.
+    ///
+    /// Live events are not compared to `latest`, as those persisted while it was fetched are
+    /// already buffered and would be skipped. Without any events yet, catching up starts from
+    /// the very first one.
+    fn after_latest(latest: Option<u64>) -> Self {
+        Self {
+            delivered: None,
+            baseline: Some(latest.unwrap_or(0)),
+        }
+    }
+
+    /// Records delivering the event with `sequence`, returning false if it was delivered before.
+    fn deliver(&mut self, sequence: u64) -> bool {
+        if self
+            .delivered
+            .is_some_and(|delivered| sequence <= delivered)
+        {
+            return false;
+        }
+        self.delivered = Some(sequence);
+        true
+    }
+
+    /// The sequence after which the persisted events are replayed once the subscriber lagged.
+    fn catch_up_from(&self) -> Option<u64> {
+        self.delivered.or(self.baseline)
+    }
+}
+
+/// Streams all persisted events after `since`, fetching them from the database page by page.
+fn replay(pool: DBPool, since: u64) -> impl Stream<Item = DownloadEvent> {
+    stream! {
+        let mut sequence = since;
+        loop {
+            match repository::events::since(&pool, sequence, REPLAY_PAGE_SIZE).await {
+                Ok(events) => {
+                    let exhausted = events.len() < REPLAY_PAGE_SIZE as usize;
+                    for event in events {
+                        sequence = event.sequence;
+                        yield event;
+                    }
+                    if exhausted {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    error!(error = ?e, "failed to replay download events");
+                    break;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cursor_delivers_events_persisted_while_subscribing() {
+        let mut cursor = Cursor::after_latest(Some(5));
+        assert!(cursor.deliver(5));
+        assert!(!cursor.deliver(5));
+        assert!(cursor.deliver(6));
+        assert_eq!(cursor.catch_up_from(), Some(6));
+    }
+
+    #[test]
+    fn test_cursor_catches_up_without_events() {
+        let cursor = Cursor::after_latest(None);
+        assert_eq!(cursor.catch_up_from(), Some(0));
+        assert_eq!(Cursor::since(None).catch_up_from(), None);
+    }
+
+    #[test]
+    fn test_cursor_since() {
+        let mut cursor = Cursor::since(Some(5));
+        assert!(!cursor.deliver(5));
+        assert!(cursor.deliver(6));
+        assert_eq!(cursor.catch_up_from(), Some(6));
+    }
+}
@@ -0,0 +1,544 @@
+use std::time::Duration;
+
+use tokio::time::Instant;
+use tracing::error;
+use uuid::Uuid;
+
+use crate::datasource::repository;
+use crate::datasource::repository::downloads::Variant;
+use crate::models::{DownloadEvent, DownloadGroup, WatchlistEntry};
+use crate::state::{DBPool, ReqwestClient};
+
+/// How long a subscription following the watchlist uses it before reading it again.
+const WATCHLIST_REFRESH_INTERVAL: Duration = Duration::from_mins(1);
+
+/// Criteria a subscriber uses to only receive the downloads it is interested in.
+///
+/// A group matches when its title matches any of the title patterns or show titles (or neither
+/// is set and `require_title` is not), its variant is one of `variants` and its provider one of
+/// `providers`. Empty lists match everything. Downloads below `min_resolution` or other than
+/// `resolution` are dropped, as are groups left without any. Of groups matching a show title with
+/// a preferred resolution, only the downloads in that resolution are kept if there are any.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct DownloadFilter {
+    pub(crate) titles: Vec<TitlePattern>,
+    pub(crate) show_titles: Vec<ShowTitle>,
+    /// Matches nothing when no title patterns or show titles are set, such as for an empty
+    /// watchlist.
+    pub(crate) require_title: bool,
+    pub(crate) variants: Vec<Variant>,
+    pub(crate) min_resolution: Option<u16>,
+    pub(crate) resolution: Option<u16>,
+    pub(crate) providers: Vec<String>,
+}
+
+impl DownloadFilter {
+    pub(crate) fn apply(&self, event: DownloadEvent) -> Option<DownloadEvent> {
+        let group = self.apply_group(event.group)?;
+        Some(DownloadEvent { group, ..event })
+    }
+
+    /// Filters a group the same way as the events containing it.
+    pub(crate) fn apply_group(&self, mut group: DownloadGroup) -> Option<DownloadGroup> {
+        if !self.matches(&group) {
+            return None;
+        }
+        if self.min_resolution.is_some() || self.resolution.is_some() {
+            group.downloads.retain(|download| {
+                self.min_resolution
+                    .is_none_or(|min_resolution| download.resolution >= min_resolution)
+                    && self
+                        .resolution
+                        .is_none_or(|resolution| download.resolution == resolution)
+            });
+            if group.downloads.is_empty() {
+                return None;
+            }
+        }
+        let preferred = self
+            .show_titles
+            .iter()
+            .filter(|show| show.matches(&group.title))
+            .find_map(ShowTitle::resolution);
+        if let Some(preferred) = preferred
+            && group
+                .downloads
+                .iter()
+                .any(|download| download.resolution == preferred)
+        {
+            group
+                .downloads
+                .retain(|download| download.resolution == preferred);
+        }
+        Some(group)
+    }
+
+    fn matches(&self, group: &DownloadGroup) -> bool {
+        self.matches_title(&group.title)
+            && (self.variants.is_empty() || self.variants.contains(&(&group.variant).into()))
+            && (self.providers.is_empty()
+                || self
+                    .providers
+                    .iter()
+                    .any(|provider| provider.eq_ignore_ascii_case(&group.provider)))
+    }
+
+    fn matches_title(&self, title: &str) -> bool {
+        if self.titles.is_empty() && self.show_titles.is_empty() {
+            return !self.require_title;
+        }
+        self.titles.iter().any(|pattern| pattern.matches(title))
+            || self.show_titles.iter().any(|show| show.matches(title))
+    }
+
+    /// The Kitsu id of the first requested show whose titles match `title`.
+    pub(crate) fn show_id(&self, title: &str) -> Option<u32> {
+        self.show_titles
+            .iter()
+            .find(|show| show.matches(title))
+            .and_then(ShowTitle::show_id)
+    }
+
+    /// Restricts this filter to the followed shows with the given titles.
+    #[must_use]
+    pub(crate) fn with_watchlist(mut self, titles: Vec<ShowTitle>) -> Self {
+        self.show_titles.extend(titles);
+        self.require_title = true;
+        self
+    }
+}
+
+/// The filter of a subscription, which may follow the watchlist as it changes.
+///
+/// A subscription following the watchlist reads it again for the first event arriving more than
+/// [`WATCHLIST_REFRESH_INTERVAL`] after it was last read, so shows followed or unfollowed while
+/// the subscription is open are picked up. If reading it fails, the previous watchlist is kept.
+#[derive(Debug)]
+pub(crate) struct SubscriptionFilter {
+    filter: DownloadFilter,
+    watchlist: Option<Watchlist>,
+}
+
+#[derive(Debug)]
+struct Watchlist {
+    base: DownloadFilter,
+    api_key_id: Uuid,
+    pool: DBPool,
+    client: ReqwestClient,
+    read_at: Instant,
+}
+
+impl SubscriptionFilter {
+    /// Restricts `base` to the shows followed by the api key, starting with the titles just read
+    /// from its watchlist.
+    pub(crate) fn watchlist(
+        base: DownloadFilter,
+        titles: Vec<ShowTitle>,
+        api_key_id: Uuid,
+        pool: DBPool,
+        client: ReqwestClient,
+    ) -> Self {
+        Self {
+            filter: base.clone().with_watchlist(titles),
+            watchlist: Some(Watchlist {
+                base,
+                api_key_id,
+                pool,
+                client,
+                read_at: Instant::now(),
+            }),
+        }
+    }
+
+    /// The filter to apply to the next event, reading the watchlist again if it is outdated.
+    pub(crate) async fn current(&mut self) -> &DownloadFilter {
+        if let Some(watchlist) = &mut self.watchlist
+            && watchlist.read_at.elapsed() >= WATCHLIST_REFRESH_INTERVAL
+        {
+            watchlist.read_at = Instant::now();
+            if let Some(titles) = watchlist.titles().await {
+                self.filter = watchlist.base.clone().with_watchlist(titles);
+            }
+        }
+        &self.filter
+    }
+}
+
+impl From<DownloadFilter> for SubscriptionFilter {
+    fn from(filter: DownloadFilter) -> Self {
+        Self {
+            filter,
+            watchlist: None,
+        }
+    }
+}
+
+impl Watchlist {
+    async fn titles(&self) -> Option<Vec<ShowTitle>> {
+        let entries = match repository::watchlist::all(&self.pool, self.api_key_id).await {
+            Ok(entries) => entries,
+            Err(e) => {
+                error!(error = ?e, "failed to read the watchlist of a subscription");
+                return None;
+            }
+        };
+        match watchlist_titles(&self.client, &entries).await {
+            Ok(titles) => Some(titles),
+            Err(e) => {
+                error!(error = ?e, "failed to look up the titles of the watchlist");
+                None
+            }
+        }
+    }
+}
+
+/// A case-insensitive title pattern where `*` matches any sequence of characters.
+#[derive(Debug, Clone)]
+pub(crate) struct TitlePattern {
+    segments: Vec<String>,
+}
+
+impl TitlePattern {
+    pub(crate) fn new(pattern: &str) -> Self {
+        Self {
+            segments: pattern.to_lowercase().split('*').map(Into::into).collect(),
+        }
+    }
+
+    /// Creates a pattern matching every title that starts with `prefix`.
+    pub(crate) fn prefix(prefix: &str) -> Self {
+        Self {
+            segments: vec![prefix.to_lowercase(), String::new()],
+        }
+    }
+
+    pub(crate) fn matches(&self, title: &str) -> bool {
+        let title = title.to_lowercase();
+        let Some((first, rest)) = self.segments.split_first() else {
+            return title.is_empty();
+        };
+        let Some(mut remaining) = title.strip_prefix(first.as_str()) else {
+            return false;
+        };
+        let Some((last, middle)) = rest.split_last() else {
+            return remaining.is_empty();
+        };
+        for segment in middle {
+            match remaining.find(segment.as_str()) {
+                Some(index) => remaining = &remaining[index + segment.len()..],
+                None => return false,
+            }
+        }
+        remaining.ends_with(last.as_str())
+    }
+}
+
+/// A show title compared to download titles ignoring case, whitespace and punctuation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ShowTitle {
+    normalized: String,
+    show_id: Option<u32>,
+    resolution: Option<u16>,
+}
+
+impl ShowTitle {
+    pub(crate) fn new(title: &str) -> Self {
+        Self {
+            normalized: normalize(title),
+            show_id: None,
+            resolution: None,
+        }
+    }
+
+    /// Creates a title known to belong to the Kitsu show `show_id`.
+    pub(crate) fn of_show(show_id: u32, title: &str) -> Self {
+        Self {
+            show_id: Some(show_id),
+            ..Self::new(title)
+        }
+    }
+
+    pub(crate) fn matches(&self, title: &str) -> bool {
+        self.normalized == normalize(title)
+    }
+
+    /// The lowercase title with everything but letters and digits removed.
+    pub(crate) fn normalized(&self) -> &str {
+        &self.normalized
+    }
+
+    pub(crate) fn show_id(&self) -> Option<u32> {
+        self.show_id
+    }
+
+    /// Prefers the downloads of the show in `resolution` over its other resolutions.
+    #[must_use]
+    pub(crate) fn with_resolution(self, resolution: Option<u16>) -> Self {
+        Self { resolution, ..self }
+    }
+
+    pub(crate) fn resolution(&self) -> Option<u16> {
+        self.resolution
+    }
+}
+
+/// Looks up the known titles of the given Kitsu shows, so they can be matched against downloads.
+pub(crate) async fn show_titles(
+    client: &ReqwestClient,
+    show_ids: &[u32],
+) -> kitsu::Result<Vec<ShowTitle>> {
+    let mut titles = Vec::new();
+    for &id in show_ids {
+        titles.extend(
+            kitsu_titles(client, id)
+                .await?
+                .iter()
+                .map(|title| ShowTitle::of_show(id, title)),
+        );
+    }
+    Ok(titles)
+}
+
+/// Looks up the canonical, romanized, English and abbreviated titles of a Kitsu show.
+pub(crate) async fn kitsu_titles(
+    client: &ReqwestClient,
+    show_id: u32,
+) -> kitsu::Result<Vec<String>> {
+    let attributes = kitsu::anime::single(client, show_id).await?.data.attributes;
+    let mut titles = vec![attributes.canonical_title, attributes.titles.en_jp];
+    titles.extend(attributes.titles.en);
+    titles.extend(attributes.abbreviated_titles);
+    Ok(titles)
+}
+
+/// The titles of the followed shows, each preferring the resolution of its watchlist entry.
+pub(crate) async fn watchlist_titles(
+    client: &ReqwestClient,
+    entries: &[WatchlistEntry],
+) -> kitsu::Result<Vec<ShowTitle>> {
+    let mut titles = Vec::new();
+    for entry in entries {
+        let show_titles = match (&entry.title, entry.show_id) {
+            (Some(title), _) => vec![ShowTitle::new(title)],
+            // entries followed before their titles were stored have to look them up
+            (None, Some(show_id)) if entry.show_titles.is_empty() => {
+                show_titles(client, &[show_id]).await?
+            }
+            (None, Some(show_id)) => entry
+                .show_titles
+                .iter()
+                .map(|title| ShowTitle::of_show(show_id, title))
+                .collect(),
+            (None, None) => Vec::new(),
+        };
+        titles.extend(
+            show_titles
+                .into_iter()
+                .map(|title| title.with_resolution(entry.resolution)),
+        );
+    }
+    Ok(titles)
+}
+
+fn normalize(title: &str) -> String {
+    title
+        .chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+
+    use super::*;
+    use crate::datasource::repository::events::EventKind;
+    use crate::models::DownloadVariant;
+
+    fn event(title: &str, variant: DownloadVariant, resolutions: &[u16]) -> DownloadEvent {
+        DownloadEvent {
+            sequence: 1,
+            kind: EventKind::Download,
+            group: DownloadGroup::fixture(title)
+                .with_variant(variant)
+                .with_resolutions(resolutions),
+        }
+    }
+
+    #[test]
+    fn test_title_pattern() {
+        assert!(TitlePattern::new("frieren").matches("Frieren"));
+        assert!(!TitlePattern::new("frieren").matches("Sousou no Frieren"));
+        assert!(TitlePattern::new("*frieren").matches("Sousou no Frieren"));
+        assert!(TitlePattern::new("sousou*frieren").matches("Sousou no Frieren"));
+        assert!(TitlePattern::new("*no*").matches("Sousou no Frieren"));
+        assert!(!TitlePattern::new("*no*x").matches("Sousou no Frieren"));
+        assert!(TitlePattern::prefix("sousou").matches("Sousou no Frieren"));
+        assert!(!TitlePattern::prefix("frieren").matches("Sousou no Frieren"));
+    }
+
+    #[test]
+    fn test_show_title() {
+        let show = ShowTitle::new("Sousou no Frieren: Part 2");
+        assert!(show.matches("Sousou no Frieren - Part 2"));
+        assert!(!show.matches("Sousou no Frieren"));
+    }
+
+    #[test]
+    fn test_filter_show_id() {
+        let filter = DownloadFilter {
+            show_titles: vec![
+                ShowTitle::of_show(46474, "Sousou no Frieren"),
+                ShowTitle::of_show(1, "Cowboy Bebop"),
+            ],
+            ..DownloadFilter::default()
+        };
+        assert_eq!(filter.show_id("Sousou no Frieren"), Some(46474));
+        assert_eq!(filter.show_id("Cowboy Bebop"), Some(1));
+        assert_eq!(filter.show_id("Frieren"), None);
+    }
+
+    #[test]
+    fn test_filter_variants_and_titles() {
+        let filter = DownloadFilter {
+            titles: vec![TitlePattern::new("*frieren*")],
+            variants: vec![Variant::Batch],
+            ..DownloadFilter::default()
+        };
+        assert!(
+            filter
+                .apply(event("Frieren", DownloadVariant::Batch(1..=4), &[1080]))
+                .is_some()
+        );
+        assert!(
+            filter
+                .apply(event("Frieren", DownloadVariant::Movie, &[1080]))
+                .is_none()
+        );
+        assert!(
+            filter
+                .apply(event("Oshi no Ko", DownloadVariant::Batch(1..=4), &[1080]))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_filter_min_resolution() {
+        let filter = DownloadFilter {
+            min_resolution: Some(720),
+            ..DownloadFilter::default()
+        };
+        let result = filter.apply(event("Frieren", DownloadVariant::Movie, &[480, 720, 1080]));
+        let resolutions: Vec<_> = result
+            .unwrap()
+            .group
+            .downloads
+            .iter()
+            .map(|d| d.resolution)
+            .collect();
+        assert_eq!(resolutions, vec![720, 1080]);
+        assert!(
+            filter
+                .apply(event("Frieren", DownloadVariant::Movie, &[480]))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_filter_exact_resolution() {
+        let filter = DownloadFilter {
+            resolution: Some(720),
+            ..DownloadFilter::default()
+        };
+        let result = filter.apply(event("Frieren", DownloadVariant::Movie, &[480, 720, 1080]));
+        assert_eq!(result.unwrap().group.downloads.len(), 1);
+    }
+
+    #[test]
+    fn test_filter_preferred_resolution() {
+        let filter = DownloadFilter {
+            show_titles: vec![
+                ShowTitle::new("Frieren").with_resolution(Some(720)),
+                ShowTitle::new("Oshi no Ko"),
+            ],
+            require_title: true,
+            ..DownloadFilter::default()
+        };
+        let resolutions = |title, resolutions| {
+            filter
+                .apply(event(title, DownloadVariant::Movie, resolutions))
+                .map(|event| {
+                    let downloads = event.group.downloads.iter();
+                    downloads.map(|d| d.resolution).collect::<Vec<_>>()
+                })
+        };
+        assert_eq!(resolutions("Frieren", &[480, 720, 1080]), Some(vec![720]));
+        assert_eq!(resolutions("Frieren", &[1080]), Some(vec![1080]));
+        assert_eq!(
+            resolutions("Oshi no Ko", &[720, 1080]),
+            Some(vec![720, 1080])
+        );
+        assert_eq!(resolutions("Cowboy Bebop", &[1080]), None);
+    }
+
+    #[test]
+    fn test_filter_require_title() {
+        let filter = DownloadFilter {
+            require_title: true,
+            ..DownloadFilter::default()
+        };
+        assert!(
+            filter
+                .apply(event("Frieren", DownloadVariant::Movie, &[1080]))
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_subscription_filter_keeps_watchlist_when_reading_fails() {
+        let pool = sqlx::postgres::PgPoolOptions::new()
+            .acquire_timeout(Duration::from_millis(100))
+            .connect_lazy("postgres://127.0.0.1:1/anime")
+            .unwrap();
+        let titles = vec![ShowTitle::new("Frieren")];
+        let mut filter = SubscriptionFilter::watchlist(
+            DownloadFilter::default(),
+            titles.clone(),
+            Uuid::from_u128(1),
+            pool,
+            ReqwestClient::new(),
+        );
+        if let Some(watchlist) = &mut filter.watchlist {
+            watchlist.read_at -= WATCHLIST_REFRESH_INTERVAL;
+        }
+        let current = filter.current().await;
+        assert_eq!(current.show_titles, titles);
+        assert!(current.require_title);
+        let watchlist = filter.watchlist.as_ref().unwrap();
+        assert!(watchlist.read_at.elapsed() < WATCHLIST_REFRESH_INTERVAL);
+    }
+
+    #[tokio::test]
+    async fn test_watchlist_titles_from_stored_titles() {
+        let entry = WatchlistEntry {
+            id: Uuid::from_u128(1),
+            title: None,
+            show_id: Some(46474),
+            show_titles: vec!["Sousou no Frieren".to_string(), "Frieren".to_string()],
+            resolution: Some(1080),
+            created_at: DateTime::default(),
+            updated_at: DateTime::default(),
+        };
+        let titles = watchlist_titles(&ReqwestClient::new(), &[entry])
+            .await
+            .unwrap();
+        assert_eq!(
+            titles,
+            [
+                ShowTitle::of_show(46474, "Sousou no Frieren").with_resolution(Some(1080)),
+                ShowTitle::of_show(46474, "Frieren").with_resolution(Some(1080)),
+            ]
+        );
+    }
+}